@@ -3,6 +3,7 @@
 use crate::span::ShortSpan;
 use crate::string_util::count_newlines;
 use derive_new::new;
+use serde::{Deserialize, Serialize};
 use std::fmt;
 
 pub trait CountNewlines {
@@ -21,19 +22,19 @@ where
     fn count_until(&self, pred: P, until: U) -> usize;
 }
 
-#[derive(new, Clone, Debug)]
+#[derive(new, Clone, Debug, Serialize, Deserialize)]
 pub struct NonAnnotatedStringData {
     pub span: ShortSpan,
     pub value: String,
 }
 
-#[derive(new, Clone, Debug)]
+#[derive(new, Clone, Debug, Serialize, Deserialize)]
 pub struct NonAnnotatedStringListData {
     pub span: ShortSpan,
     pub value: Vec<String>,
 }
 
-#[derive(new, Clone, Debug)]
+#[derive(new, Clone, Debug, Serialize, Deserialize)]
 pub struct MultilineStringData {
     pub span: ShortSpan,
     pub annotations: Vec<String>,
@@ -46,7 +47,7 @@ impl CountNewlines for MultilineStringData {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum ClobExpr {
     MultilineString(MultilineStringData),
     QuotedString(AtomicData),
@@ -74,7 +75,7 @@ impl CountItemsBeforeNewline for &[ClobExpr] {
     }
 }
 
-#[derive(new, Clone, Debug)]
+#[derive(new, Clone, Debug, Serialize, Deserialize)]
 pub struct ClobData {
     pub span: ShortSpan,
     pub annotations: Vec<String>,
@@ -94,7 +95,7 @@ impl CountNewlines for ClobData {
     }
 }
 
-#[derive(new, Clone, Debug)]
+#[derive(new, Clone, Debug, Serialize, Deserialize)]
 pub struct ListData {
     pub span: ShortSpan,
     pub annotations: Vec<String>,
@@ -110,7 +111,7 @@ impl ListData {
     }
 }
 
-#[derive(new, Clone, Copy)]
+#[derive(new, Clone, Copy, Serialize, Deserialize)]
 pub struct NewlinesData {
     pub span: ShortSpan,
     pub newline_count: u16,
@@ -125,7 +126,7 @@ impl fmt::Debug for NewlinesData {
     }
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub enum AtomicType {
     Blob,
     Boolean,
@@ -137,7 +138,7 @@ pub enum AtomicType {
     Timestamp,
 }
 
-#[derive(new, Clone, Debug)]
+#[derive(new, Clone, Debug, Serialize, Deserialize)]
 pub struct AtomicData {
     pub typ: AtomicType,
     pub span: ShortSpan,
@@ -145,7 +146,7 @@ pub struct AtomicData {
     pub value: String,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Expr {
     Atomic(AtomicData),
     Clob(ClobData),
@@ -0,0 +1,278 @@
+// Copyright Ion Fusion contributors. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+use crate::config::FusionConfig;
+use crate::diff_util::{Hunk, diff_hunks};
+use crate::error::Error;
+use crate::file::FusionFile;
+use crate::format;
+use crate::layout::{self, LayoutViolation, LineEnding};
+use std::path::{Path, PathBuf};
+
+/// The rule that a checkstyle violation breaks
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Rule {
+    Format,
+    LineEnding,
+    MixedLineEndings,
+    Bom,
+    FinalNewline,
+    /// The file couldn't be read or parsed, so its style couldn't be checked
+    Parse,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 6] = [
+        Rule::Format,
+        Rule::LineEnding,
+        Rule::MixedLineEndings,
+        Rule::Bom,
+        Rule::FinalNewline,
+        Rule::Parse,
+    ];
+
+    pub fn id(self) -> &'static str {
+        match self {
+            Rule::Format => "format",
+            Rule::LineEnding => "line-ending",
+            Rule::MixedLineEndings => "mixed-line-endings",
+            Rule::Bom => "bom",
+            Rule::FinalNewline => "final-newline",
+            Rule::Parse => "parse",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Rule::Format => "Code must match the output of the formatter",
+            Rule::LineEnding => "Files must use the configured line ending",
+            Rule::MixedLineEndings => "Files must not mix LF and CRLF line endings",
+            Rule::Bom => "Files must not start with a byte order mark unless preserve_bom is set",
+            Rule::FinalNewline => "Files must end with a single newline if (and only if) final_newline is set",
+            Rule::Parse => "Files must be readable and parse as Fusion",
+        }
+    }
+}
+
+/// A 1-based line and column. Columns count characters, not bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Position {
+    fn new(line: usize, column: usize) -> Position {
+        Position { line, column }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Violation {
+    pub file_name: PathBuf,
+    pub rule: Rule,
+    pub message: String,
+    pub start: Position,
+    /// Exclusive end of the range that the violation covers
+    pub end: Position,
+    /// Text that fixes the violation when it replaces the range from `start` to `end`
+    pub replacement: Option<String>,
+}
+
+/// Finds every way in which `file` doesn't adhere to the configured style
+pub fn check(fusion_config: &FusionConfig, file: &FusionFile) -> Vec<Violation> {
+    let formatted = format::format(fusion_config, &file.ast);
+    check_formatted(fusion_config, &file.file_name, &file.contents, &formatted)
+}
+
+/// Reports a file that couldn't be checked because it failed to load or parse, at where parsing failed, or at
+/// the start of the file if the error isn't located
+pub fn file_error(file_name: &Path, error: &Error) -> Violation {
+    let (line, column) = error.line_col().unwrap_or((1, 1));
+    Violation {
+        file_name: file_name.to_path_buf(),
+        rule: Rule::Parse,
+        message: error.to_string(),
+        start: Position::new(line, column),
+        end: Position::new(line, column),
+        replacement: None,
+    }
+}
+
+/// Same as [`check`], for when the formatted version of `contents` is already known
+pub fn check_formatted(
+    fusion_config: &FusionConfig,
+    file_name: &Path,
+    contents: &str,
+    formatted: &str,
+) -> Vec<Violation> {
+    let normalized = layout::normalize(contents);
+    let mut violations: Vec<Violation> = diff_hunks(normalized.trim_end(), formatted.trim_end())
+        .iter()
+        .map(|hunk| hunk_violation(file_name, hunk))
+        .collect();
+    for layout_violation in layout::check(fusion_config, contents) {
+        violations.push(layout_violation_location(file_name, contents, layout_violation));
+    }
+    violations
+}
+
+/// Narrows a hunk down to the characters that actually changed, so that the violation points
+/// at the exact column where the formatting is wrong
+fn hunk_violation(file_name: &Path, hunk: &Hunk<'_>) -> Violation {
+    let removed: Vec<char> = hunk
+        .removed
+        .iter()
+        .flat_map(|line| line.chars().chain(['\n']))
+        .collect();
+    let added: Vec<char> = hunk.added.iter().flat_map(|line| line.chars().chain(['\n'])).collect();
+    let prefix = removed.iter().zip(&added).take_while(|(l, r)| l == r).count();
+    let max_suffix = removed.len().min(added.len()) - prefix;
+    let suffix = removed
+        .iter()
+        .rev()
+        .zip(added.iter().rev())
+        .take(max_suffix)
+        .take_while(|(l, r)| l == r)
+        .count();
+
+    let position_at = |offset: usize| {
+        let newlines = removed[..offset].iter().filter(|chr| **chr == '\n').count();
+        let line_start = removed[..offset]
+            .iter()
+            .rposition(|chr| *chr == '\n')
+            .map_or(0, |i| i + 1);
+        Position::new(hunk.start_line + newlines, offset - line_start + 1)
+    };
+    let message = match hunk.added.is_empty() {
+        true => "Unexpected line(s) that should be removed",
+        false
+            if hunk.removed.iter().zip(&hunk.added).all(|(l, r)| l.trim() == r.trim())
+                && hunk.removed.len() == hunk.added.len() =>
+        {
+            "Incorrect indentation"
+        }
+        false => "Code doesn't adhere to correct style",
+    };
+    Violation {
+        file_name: file_name.to_path_buf(),
+        rule: Rule::Format,
+        message: message.into(),
+        start: position_at(prefix),
+        end: position_at(removed.len() - suffix),
+        replacement: Some(added[prefix..(added.len() - suffix)].iter().collect()),
+    }
+}
+
+fn layout_violation_location(file_name: &Path, contents: &str, violation: LayoutViolation) -> Violation {
+    let (rule, start, end, replacement) = match violation {
+        LayoutViolation::LineEnding { expected, .. } | LayoutViolation::MixedLineEndings { expected } => {
+            let rule = match violation {
+                LayoutViolation::LineEnding { .. } => Rule::LineEnding,
+                _ => Rule::MixedLineEndings,
+            };
+            // Point at the first line that ends the wrong way
+            let (line, text) = contents
+                .split_inclusive('\n')
+                .enumerate()
+                .find(|(_, text)| text.ends_with('\n') && text.ends_with("\r\n") != (expected == LineEnding::CrLf))
+                .unwrap_or((0, ""));
+            let text = text.trim_start_matches('\u{FEFF}').trim_end_matches(['\r', '\n']);
+            let column = text.chars().count() + 1;
+            let (end_column, replacement) = match expected {
+                LineEnding::Lf => (column + 1, ""),
+                LineEnding::CrLf => (column, "\r"),
+            };
+            (
+                rule,
+                Position::new(line + 1, column),
+                Position::new(line + 1, end_column),
+                Some(replacement.to_string()),
+            )
+        }
+        LayoutViolation::UnexpectedBom => (Rule::Bom, Position::new(1, 1), Position::new(1, 2), Some(String::new())),
+        LayoutViolation::FinalNewline { .. } => {
+            let last_line = layout::normalize(contents).trim_end().lines().count().max(1);
+            let position = Position::new(last_line, 1);
+            (Rule::FinalNewline, position, position, None)
+        }
+    };
+    let mut message = violation.to_string();
+    message[..1].make_ascii_uppercase();
+    Violation {
+        file_name: file_name.to_path_buf(),
+        rule,
+        message,
+        start,
+        end,
+        replacement,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::new_default_config;
+    use crate::file::FusionFileContent;
+    use fuusak_err_macro::err_generic;
+
+    fn check_str(contents: &str, formatted: &str) -> Vec<Violation> {
+        check_formatted(&new_default_config(), Path::new("test.fusion"), contents, formatted)
+    }
+
+    #[test]
+    fn format_violations() {
+        assert_eq!(Vec::<Violation>::new(), check_str("(foo\n  bar)\n", "(foo\n  bar)"));
+
+        let violations = check_str("(foo\nbar)\n(x)\n(baz  1)\n", "(foo\n  bar)\n(x)\n(baz 1)");
+        assert_eq!(2, violations.len());
+        assert_eq!("Incorrect indentation", violations[0].message);
+        assert_eq!(
+            (Position::new(2, 1), Position::new(2, 1)),
+            (violations[0].start, violations[0].end)
+        );
+        assert_eq!(Some("  ".to_string()), violations[0].replacement);
+        assert_eq!(
+            (Position::new(4, 6), Position::new(4, 7)),
+            (violations[1].start, violations[1].end)
+        );
+        assert_eq!(Some(String::new()), violations[1].replacement);
+
+        let violations = check_str("(foo)\n\n\n\n(bar)\n", "(foo)\n\n(bar)");
+        assert_eq!(1, violations.len());
+        assert_eq!(Rule::Format, violations[0].rule);
+        assert_eq!(
+            (Position::new(3, 1), Position::new(5, 1)),
+            (violations[0].start, violations[0].end)
+        );
+    }
+
+    #[test]
+    fn file_errors() {
+        let error = FusionFileContent::new("bad.fusion".into(), "(a)\n(b\n  c]\n".into())
+            .parse(&new_default_config())
+            .unwrap_err();
+        let violation = file_error(Path::new("bad.fusion"), &error);
+        assert_eq!(Rule::Parse, violation.rule);
+        assert_eq!(Position::new(3, 4), violation.start);
+
+        let violation = file_error(Path::new("missing.fusion"), &err_generic!("Failed to load file"));
+        assert_eq!(Position::new(1, 1), violation.start);
+    }
+
+    #[test]
+    fn layout_violations() {
+        let violations = check_str("(a)\r\n(b)\r\n(c)\n", "(a)\n(b)\n(c)");
+        assert_eq!(1, violations.len());
+        assert_eq!(Rule::MixedLineEndings, violations[0].rule);
+        assert_eq!(
+            (Position::new(3, 4), Position::new(3, 4)),
+            (violations[0].start, violations[0].end)
+        );
+        assert_eq!(Some("\r".to_string()), violations[0].replacement);
+
+        let violations = check_str("(foo)\n(bar)", "(foo)\n(bar)");
+        assert_eq!(Rule::FinalNewline, violations[0].rule);
+        assert_eq!("Doesn't end with a single newline", violations[0].message);
+        assert_eq!(Position::new(2, 1), violations[0].start);
+    }
+}
@@ -1,69 +1,307 @@
 // Copyright Ion Fusion contributors. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 use crate::error::Error;
+use crate::file::build_glob_set;
+use crate::layout::LineEnding;
 use fuusak_err_macro::err_generic;
-use serde::Deserialize;
-use std::path::PathBuf;
+use globset::GlobSet;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use toml::Spanned;
 
-const NEWLINE_MODE_NO_CHANGE: &str = "no-change";
-const NEWLINE_MODE_FIX_UP: &str = "fix-up";
+pub const CONFIG_FILE_NAME: &str = "fuusak.toml";
 
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NewlineMode {
+    /// Makes zero changes to newlines in the file
+    NoChange,
+    /// Shuffles around newlines for improved formatting
+    FixUp,
+}
+
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineEndingMode {
+    /// Keeps the line endings each file predominantly uses
+    Auto,
+    Lf,
+    Crlf,
+}
+
+#[derive(Clone, Debug, Hash, Serialize)]
 pub struct FusionConfig {
     /// Newline mode 'no-change' will make zero changes to newlines in the file.
     /// Mode 'fix-up' will shuffle around newlines for improved formatting.
-    pub newline_mode: String,
+    pub newline_mode: NewlineMode,
     /// If true, multi-line Fusion strings (''') will have their whitespace modified
     pub format_multiline_string_contents: bool,
+    /// If true, docstrings (the multi-line string after the signature of a `define`-like form) will be
+    /// reindented relative to the form, have trailing whitespace trimmed, and have blank lines collapsed.
+    pub format_docstrings: bool,
+    /// Function/macro names whose third argument is a docstring when followed by a body.
+    pub docstring_symbols: Vec<String>,
+    /// Number of columns that a fixed indent adds, which is also the display width assumed for tabs.
+    pub indent_width: usize,
+    /// If true, block indentation is written with tabs. Alignment (for example, lining up arguments
+    /// after an opening symbol) is always written with spaces.
+    pub use_tabs: bool,
     /// Function/macro names that should have a fixed indent for their body.
     /// For example, `define`, `begin`, and `let`, may want a fixed indent to avoid crazy indentation levels.
     pub fixed_indent_symbols: Vec<String>,
     /// Function/macro names that should use fixed indent if their body is long.
     /// For example, `if` could be formatted normally if it's short, but formatted like a `define` if long.
     pub smart_indent_symbols: Vec<String>,
+    /// If true, line and block comments will be rewrapped to fit within `max_width`
+    pub reflow_comments: bool,
+    /// Maximum line width that reflowed comments should fit within
+    pub max_width: usize,
+    /// Line ending mode 'auto' keeps the line endings each file predominantly uses.
+    /// Modes 'lf' and 'crlf' convert all line endings to the given style.
+    pub line_ending: LineEndingMode,
+    /// If true, a byte order mark at the start of a file is kept. Otherwise, it is removed.
+    pub preserve_bom: bool,
+    /// If true, files end with a single newline. Otherwise, files end without a newline.
+    pub final_newline: bool,
+    /// File extensions (without the leading '.') that bulk commands like `format-all` operate on.
+    /// Like `include` and `exclude`, it can only be set in the outermost config file, outside of overrides.
+    pub extensions: Vec<String>,
+    /// Glob patterns, relative to the current directory, that files must match for bulk commands to operate on them.
+    /// If empty, all files with one of the `extensions` are included.
+    pub include: Vec<String>,
+    /// Glob patterns, relative to the current directory, for files and directories that bulk commands should skip
+    pub exclude: Vec<String>,
+    /// Layout of the package, from the `[package]` section
+    #[serde(skip)]
+    pub package: PackageConfig,
+}
+
+/// Where the index finds a package's modules and tests. Paths are relative to the package directory,
+/// which is the directory of the config file that declares the `[package]` section.
+#[derive(Clone, Debug, Hash, Serialize)]
+pub struct PackageConfig {
+    /// Directories containing the package's modules, which are all indexed
+    pub module_roots: Vec<String>,
+    /// Directories containing the package's test scripts
+    pub test_roots: Vec<String>,
+    /// Modules that test scripts are evaluated in
+    pub test_top_level_modules: Vec<String>,
+    /// Additional read-only directories that required modules are looked up in, such as an unpacked
+    /// Fusion distribution or a sibling package. Only modules that are required are indexed.
+    pub repositories: Vec<String>,
+    /// Binding catalog describing the Fusion standard library. If empty, the catalog bundled with fuusak is used.
+    pub catalog: String,
+    /// Directory of the innermost config file with a `[package]` section, or `None` if there isn't one
+    #[serde(skip)]
+    pub declared_in: Option<PathBuf>,
+}
+
+impl PackageConfig {
+    fn from_default_toml(package: TomlPackageConfig) -> PackageConfig {
+        PackageConfig {
+            module_roots: package.module_roots.unwrap(),
+            test_roots: package.test_roots.unwrap(),
+            test_top_level_modules: package.test_top_level_modules.unwrap(),
+            repositories: package.repositories.unwrap(),
+            catalog: package.catalog.unwrap(),
+            declared_in: None,
+        }
+    }
+
+    fn from_toml_with_defaults(package: TomlPackageConfig, defaults: PackageConfig) -> PackageConfig {
+        PackageConfig {
+            module_roots: package.module_roots.unwrap_or(defaults.module_roots),
+            test_roots: package.test_roots.unwrap_or(defaults.test_roots),
+            test_top_level_modules: package
+                .test_top_level_modules
+                .unwrap_or(defaults.test_top_level_modules),
+            repositories: package.repositories.unwrap_or(defaults.repositories),
+            catalog: package.catalog.unwrap_or(defaults.catalog),
+            declared_in: defaults.declared_in,
+        }
+    }
+
+    /// Returns the package directory, which the package's paths are relative to. Without a config file that
+    /// declares the package, it's the current directory.
+    pub fn directory(&self) -> Result<PathBuf, Error> {
+        match &self.declared_in {
+            Some(directory) => Ok(directory.clone()),
+            None => current_directory(),
+        }
+    }
 }
 
 impl FusionConfig {
     fn from_default_toml(toml: TomlFusionFile) -> FusionConfig {
-        let fusion = toml.fusion;
+        let fusion = toml.fusion.unwrap();
         FusionConfig {
             newline_mode: fusion.newline_mode.unwrap(),
             format_multiline_string_contents: fusion.format_multiline_string_contents.unwrap(),
+            format_docstrings: fusion.format_docstrings.unwrap(),
+            docstring_symbols: fusion.docstring_symbols.unwrap(),
+            indent_width: fusion.indent_width.unwrap().get(),
+            use_tabs: fusion.use_tabs.unwrap(),
             fixed_indent_symbols: fusion.fixed_indent_symbols.unwrap(),
             smart_indent_symbols: fusion.smart_indent_symbols.unwrap(),
+            reflow_comments: fusion.reflow_comments.unwrap(),
+            max_width: fusion.max_width.unwrap(),
+            line_ending: fusion.line_ending.unwrap(),
+            preserve_bom: fusion.preserve_bom.unwrap(),
+            final_newline: fusion.final_newline.unwrap(),
+            extensions: fusion.extensions.unwrap().into_inner(),
+            include: fusion.include.unwrap().into_inner(),
+            exclude: fusion.exclude.unwrap().into_inner(),
+            package: PackageConfig::from_default_toml(toml.package.unwrap()),
         }
     }
 
-    fn from_toml_with_defaults(toml: TomlFusionFile, defaults: FusionConfig) -> FusionConfig {
-        let fusion = toml.fusion;
+    fn from_toml_with_defaults(fusion: TomlFusionConfig, defaults: FusionConfig) -> FusionConfig {
         FusionConfig {
             newline_mode: fusion.newline_mode.unwrap_or(defaults.newline_mode),
             format_multiline_string_contents: fusion
                 .format_multiline_string_contents
                 .unwrap_or(defaults.format_multiline_string_contents),
+            format_docstrings: fusion.format_docstrings.unwrap_or(defaults.format_docstrings),
+            docstring_symbols: fusion.docstring_symbols.unwrap_or(defaults.docstring_symbols),
+            indent_width: fusion.indent_width.map_or(defaults.indent_width, NonZeroUsize::get),
+            use_tabs: fusion.use_tabs.unwrap_or(defaults.use_tabs),
             fixed_indent_symbols: fusion.fixed_indent_symbols.unwrap_or(defaults.fixed_indent_symbols),
             smart_indent_symbols: fusion.smart_indent_symbols.unwrap_or(defaults.smart_indent_symbols),
+            reflow_comments: fusion.reflow_comments.unwrap_or(defaults.reflow_comments),
+            max_width: fusion.max_width.unwrap_or(defaults.max_width),
+            line_ending: fusion.line_ending.unwrap_or(defaults.line_ending),
+            preserve_bom: fusion.preserve_bom.unwrap_or(defaults.preserve_bom),
+            final_newline: fusion.final_newline.unwrap_or(defaults.final_newline),
+            extensions: fusion.extensions.map_or(defaults.extensions, Spanned::into_inner),
+            include: fusion.include.map_or(defaults.include, Spanned::into_inner),
+            exclude: fusion.exclude.map_or(defaults.exclude, Spanned::into_inner),
+            package: defaults.package,
         }
     }
 }
 
 #[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 struct TomlFusionFile {
+    /// If true, config discovery doesn't look for config files in any parent directories
+    pub root: Option<bool>,
+    pub fusion: Option<TomlFusionConfig>,
+    pub package: Option<TomlPackageConfig>,
+    pub overrides: Option<Vec<TomlOverride>>,
+}
+
+/// Settings that only apply to files matching one of the `files` glob patterns,
+/// which are relative to the directory containing the config file
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TomlOverride {
+    pub files: Spanned<Vec<String>>,
     pub fusion: TomlFusionConfig,
 }
 
 /// `TomlFusionConfig` has every member as optional so that configs can
 /// be sparse and have defaults applied if values are not specified.
-#[derive(Deserialize)]
+/// Unset members are left out when it's serialized, which tells which settings a config file specifies.
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 struct TomlFusionConfig {
-    pub newline_mode: Option<String>,
+    pub newline_mode: Option<NewlineMode>,
     pub format_multiline_string_contents: Option<bool>,
+    pub format_docstrings: Option<bool>,
+    pub docstring_symbols: Option<Vec<String>>,
+    pub indent_width: Option<NonZeroUsize>,
+    pub use_tabs: Option<bool>,
     pub fixed_indent_symbols: Option<Vec<String>>,
     pub smart_indent_symbols: Option<Vec<String>>,
+    pub reflow_comments: Option<bool>,
+    pub max_width: Option<usize>,
+    pub line_ending: Option<LineEndingMode>,
+    pub preserve_bom: Option<bool>,
+    pub final_newline: Option<bool>,
+    pub extensions: Option<Spanned<Vec<String>>>,
+    pub include: Option<Spanned<Vec<String>>>,
+    pub exclude: Option<Spanned<Vec<String>>>,
+}
+
+impl TomlFusionConfig {
+    /// Returns the name and span of the first setting that selects the files bulk commands operate on.
+    /// Bulk commands select files once, for the whole run, so these settings can't vary per file.
+    fn file_selection_setting(&self) -> Option<(&'static str, std::ops::Range<usize>)> {
+        [
+            ("extensions", &self.extensions),
+            ("include", &self.include),
+            ("exclude", &self.exclude),
+        ]
+        .into_iter()
+        .find_map(|(key, setting)| Some((key, setting.as_ref()?.span())))
+    }
+}
+
+#[derive(Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct TomlPackageConfig {
+    pub module_roots: Option<Vec<String>>,
+    pub test_roots: Option<Vec<String>>,
+    pub test_top_level_modules: Option<Vec<String>>,
+    pub repositories: Option<Vec<String>>,
+    pub catalog: Option<String>,
 }
 
 impl FusionConfig {
     pub fn newline_fix_up_mode(&self) -> bool {
-        self.newline_mode == NEWLINE_MODE_FIX_UP
+        self.newline_mode == NewlineMode::FixUp
+    }
+
+    /// Returns the line ending that all files should use, or `None` if each file's existing line ending is kept
+    pub fn configured_line_ending(&self) -> Option<LineEnding> {
+        match self.line_ending {
+            LineEndingMode::Auto => None,
+            LineEndingMode::Lf => Some(LineEnding::Lf),
+            LineEndingMode::Crlf => Some(LineEnding::CrLf),
+        }
+    }
+
+    /// Hashes the settings that affect how a file is formatted and checked, leaving out which files bulk commands
+    /// select and the package layout
+    pub fn hash_style<H: Hasher>(&self, state: &mut H) {
+        // Destructured so that new settings have to be sorted into one group or the other
+        let FusionConfig {
+            newline_mode,
+            format_multiline_string_contents,
+            format_docstrings,
+            docstring_symbols,
+            indent_width,
+            use_tabs,
+            fixed_indent_symbols,
+            smart_indent_symbols,
+            reflow_comments,
+            max_width,
+            line_ending,
+            preserve_bom,
+            final_newline,
+            extensions: _,
+            include: _,
+            exclude: _,
+            package: _,
+        } = self;
+        newline_mode.hash(state);
+        format_multiline_string_contents.hash(state);
+        format_docstrings.hash(state);
+        docstring_symbols.hash(state);
+        indent_width.hash(state);
+        use_tabs.hash(state);
+        fixed_indent_symbols.hash(state);
+        smart_indent_symbols.hash(state);
+        reflow_comments.hash(state);
+        max_width.hash(state);
+        line_ending.hash(state);
+        preserve_bom.hash(state);
+        final_newline.hash(state);
     }
 }
 
@@ -73,53 +311,521 @@ pub fn new_default_config() -> FusionConfig {
     FusionConfig::from_default_toml(toml::from_str(DEFAULT_CONFIG).expect("well-formed default config"))
 }
 
-pub fn load_config(config_file_name: Option<&str>, silent: bool) -> Result<FusionConfig, Error> {
-    let default_config = new_default_config();
-    let config_path = match config_file_name {
-        // Path given via CLI; just use it as is
-        Some(path) => {
-            let given = PathBuf::from(path);
-            if !given.exists() {
-                return Err(err_generic!("specified config file {:?} doesn't exist", given));
+/// Where the effective value of a setting came from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConfigSource {
+    /// The built-in default config
+    Default,
+    /// The `[fusion]` or `[package]` section of a config file
+    File(PathBuf),
+    /// The `[[overrides]]` entry at (1-based) `index` in a config file
+    Override {
+        path: PathBuf,
+        index: usize,
+        files: Vec<String>,
+    },
+}
+
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigSource::Default => write!(f, "default"),
+            ConfigSource::File(path) => write!(f, "{}", path.display()),
+            ConfigSource::Override { path, index, files } => {
+                write!(f, "{} [[overrides]] #{} (files = {:?})", path.display(), index, files)
             }
-            given
         }
-        // Otherwise, look in the current working directory
-        None => PathBuf::from("fuusak.toml"),
-    };
+    }
+}
+
+/// A single setting of an effective config, as shown by `show-config`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConfigSetting {
+    /// The config file section that the setting belongs in, such as `fusion`
+    pub section: &'static str,
+    pub key: String,
+    /// The value, written as TOML
+    pub value: String,
+    pub source: ConfigSource,
+}
+
+/// A config file that has been read and parsed, along with its compiled override patterns
+struct ConfigFile {
+    path: PathBuf,
+    directory: PathBuf,
+    root: bool,
+    fusion: TomlFusionConfig,
+    package: Option<TomlPackageConfig>,
+    overrides: Vec<ConfigOverride>,
+    /// Name and location of the first file selection setting in the `[fusion]` section, if any
+    file_selection: Option<(&'static str, (usize, usize))>,
+}
+
+struct ConfigOverride {
+    files: Vec<String>,
+    patterns: GlobSet,
+    fusion: TomlFusionConfig,
+}
+
+impl ConfigFile {
+    fn load(path: &Path) -> Result<ConfigFile, Error> {
+        let config_contents = std::fs::read_to_string(path)
+            .map_err(|err| err_generic!("Failed to read config file {:?}: {}", path, err))?;
+        let toml: TomlFusionFile = toml::from_str(&config_contents).map_err(|err| {
+            let location = err.span().map(|span| text_location(&config_contents, span.start));
+            invalid_config_error(path, location, err.message())
+        })?;
+        let mut overrides = Vec::new();
+        for toml_override in toml.overrides.unwrap_or_default() {
+            if let Some((key, span)) = toml_override.fusion.file_selection_setting() {
+                let location = text_location(&config_contents, span.start);
+                let message = format!("`{key}` can't be set in [[overrides]], since it applies to the whole run");
+                return Err(invalid_config_error(path, Some(location), message));
+            }
+            let location = text_location(&config_contents, toml_override.files.span().start);
+            let files = toml_override.files.into_inner();
+            let patterns = build_glob_set(&files).map_err(|err| invalid_config_error(path, Some(location), err))?;
+            overrides.push(ConfigOverride {
+                files,
+                patterns,
+                fusion: toml_override.fusion,
+            });
+        }
+        let fusion = toml.fusion.unwrap_or_default();
+        let file_selection = fusion
+            .file_selection_setting()
+            .map(|(key, span)| (key, text_location(&config_contents, span.start)));
+        let path = std::path::absolute(path).map_err(|err| err_generic!("Failed to resolve {:?}: {}", path, err))?;
+        Ok(ConfigFile {
+            directory: path.parent().map(Path::to_path_buf).unwrap_or_default(),
+            path,
+            root: toml.root.unwrap_or(false),
+            fusion,
+            package: toml.package,
+            overrides,
+            file_selection,
+        })
+    }
+
+    /// Returns this file's settings, followed by those of each override matching `file_path`, in the order
+    /// that they're applied
+    fn layers(&self, file_path: Option<&Path>) -> Vec<ConfigLayer<'_>> {
+        let mut layers = vec![ConfigLayer {
+            source: ConfigSource::File(self.path.clone()),
+            directory: &self.directory,
+            fusion: &self.fusion,
+            package: self.package.as_ref(),
+        }];
+        let relative_path = file_path.and_then(|file_path| file_path.strip_prefix(&self.directory).ok());
+        if let Some(relative_path) = relative_path {
+            for (index, config_override) in self.overrides.iter().enumerate() {
+                if config_override.patterns.is_match(relative_path) {
+                    let source = ConfigSource::Override {
+                        path: self.path.clone(),
+                        index: index + 1,
+                        files: config_override.files.clone(),
+                    };
+                    layers.push(ConfigLayer {
+                        source,
+                        directory: &self.directory,
+                        fusion: &config_override.fusion,
+                        package: None,
+                    });
+                }
+            }
+        }
+        layers
+    }
+}
+
+/// The settings from one config file, or one of its overrides
+struct ConfigLayer<'a> {
+    source: ConfigSource,
+    /// Directory of the config file
+    directory: &'a Path,
+    fusion: &'a TomlFusionConfig,
+    package: Option<&'a TomlPackageConfig>,
+}
+
+/// Converts a byte offset into `contents` to a 1-based (line, column)
+fn text_location(contents: &str, offset: usize) -> (usize, usize) {
+    let before = &contents[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+fn invalid_config_error(path: &Path, location: Option<(usize, usize)>, message: impl fmt::Display) -> Error {
+    match location {
+        Some((line, column)) => err_generic!(
+            "Invalid config file {}:{}:{}: {}",
+            path.display(),
+            line,
+            column,
+            message
+        ),
+        None => err_generic!("Invalid config file {}: {}", path.display(), message),
+    }
+}
+
+/// Determines the config for each file by merging every `fuusak.toml` found in the file's directory and
+/// its parents (stopping at one with `root = true`). Settings in nested config files override those in
+/// their parents, and `[[overrides]]` override the settings of the file they're in for matching files.
+/// If a config file was given on the command line, only that file is used.
+pub struct ConfigResolver {
+    explicit: Option<Arc<ConfigFile>>,
+    /// The config file found in each directory that has been searched, if any
+    discovered: Mutex<HashMap<PathBuf, Option<Arc<ConfigFile>>>>,
+}
+
+impl ConfigResolver {
+    pub fn new(config_file_name: Option<&str>) -> Result<ConfigResolver, Error> {
+        let explicit = match config_file_name {
+            // Path given via CLI; just use it as is
+            Some(path) => {
+                let given = PathBuf::from(path);
+                if !given.exists() {
+                    return Err(err_generic!("specified config file {:?} doesn't exist", given));
+                }
+                Some(Arc::new(ConfigFile::load(&given)?))
+            }
+            None => None,
+        };
+        Ok(ConfigResolver {
+            explicit,
+            discovered: Mutex::new(HashMap::new()),
+        })
+    }
 
-    if !config_path.exists() {
+    /// Loads the config for the current working directory, which applies to package-wide operations
+    pub fn load_current_directory(&self, silent: bool) -> Result<FusionConfig, Error> {
+        self.load_directory(&current_directory()?, silent)
+    }
+
+    fn load_directory(&self, directory: &Path, silent: bool) -> Result<FusionConfig, Error> {
+        let config_files = self.config_files(directory)?;
         if !silent {
-            println!("Using default config...");
+            if config_files.is_empty() {
+                println!("Using default config...");
+            }
+            for config_file in &config_files {
+                println!("Using config file {}...", config_file.path.display());
+            }
         }
-        return Ok(default_config);
-    } else if !silent {
-        println!("Using config file {}...", config_path.display());
+        Ok(merge(&layers(&config_files, None)))
     }
 
-    let config_contents = std::fs::read(&config_path)
-        .map_err(|err| err_generic!("Failed to read config file {:?}: {}", config_file_name, err))?;
-    let config = toml::from_slice(&config_contents)
-        .map_err(|err| err_generic!("Failed to parse config file: {:?}: {}", config_file_name, err))?;
+    /// Determines the config that applies to the file at `file_path`
+    pub fn config_for_file(&self, file_path: &Path) -> Result<FusionConfig, Error> {
+        let file_path = absolute_path(file_path)?;
+        let config_files = self.config_files(file_path.parent().unwrap_or(&file_path))?;
+        Ok(merge(&layers(&config_files, Some(&file_path))))
+    }
 
-    let config = FusionConfig::from_toml_with_defaults(config, default_config);
-    if config.newline_mode != NEWLINE_MODE_NO_CHANGE && config.newline_mode != NEWLINE_MODE_FIX_UP {
-        return Err(err_generic!(
-            "Unknown newline mode in config: {}. Should be '{}' or '{}'",
-            config.newline_mode,
-            NEWLINE_MODE_NO_CHANGE,
-            NEWLINE_MODE_FIX_UP
-        ));
+    /// Lists every setting of the config that applies to the file at `file_path` (or the current directory,
+    /// if there's no file), along with where its value came from
+    pub fn explain(&self, file_path: Option<&Path>) -> Result<Vec<ConfigSetting>, Error> {
+        let file_path = file_path.map(absolute_path).transpose()?;
+        let directory = match &file_path {
+            Some(file_path) => file_path.parent().unwrap_or(file_path).to_path_buf(),
+            None => current_directory()?,
+        };
+        let config_files = self.config_files(&directory)?;
+        let layers = layers(&config_files, file_path.as_deref());
+        let config = merge(&layers);
+        let fusion_layers: Vec<_> = layers.iter().map(|layer| (&layer.source, Some(layer.fusion))).collect();
+        let package_layers: Vec<_> = layers.iter().map(|layer| (&layer.source, layer.package)).collect();
+        let mut settings = explain_section("fusion", &config, &fusion_layers);
+        settings.extend(explain_section("package", &config.package, &package_layers));
+        Ok(settings)
     }
-    Ok(config)
+
+    /// Finds the config files that apply to `directory`, outermost first
+    fn config_files(&self, directory: &Path) -> Result<Vec<Arc<ConfigFile>>, Error> {
+        if let Some(explicit) = &self.explicit {
+            return Ok(vec![explicit.clone()]);
+        }
+        let mut config_files = Vec::new();
+        for directory in directory.ancestors() {
+            if let Some(config_file) = self.discover(directory)? {
+                config_files.push(config_file.clone());
+                if config_file.root {
+                    break;
+                }
+            }
+        }
+        config_files.reverse();
+        // Bulk commands select files with the settings of the directory they're run from, so nested config files
+        // would only take effect when run from below them
+        for config_file in config_files.iter().skip(1) {
+            if let Some((key, location)) = config_file.file_selection {
+                let message = format!("`{key}` can only be set in the outermost config file");
+                return Err(invalid_config_error(&config_file.path, Some(location), message));
+            }
+        }
+        Ok(config_files)
+    }
+
+    fn discover(&self, directory: &Path) -> Result<Option<Arc<ConfigFile>>, Error> {
+        if let Some(config_file) = self.discovered.lock().unwrap().get(directory) {
+            return Ok(config_file.clone());
+        }
+        let config_path = directory.join(CONFIG_FILE_NAME);
+        let config_file = match config_path.is_file() {
+            true => Some(Arc::new(ConfigFile::load(&config_path)?)),
+            false => None,
+        };
+        self.discovered
+            .lock()
+            .unwrap()
+            .insert(directory.to_path_buf(), config_file.clone());
+        Ok(config_file)
+    }
+}
+
+/// Lists the settings of each config file, and each of their overrides matching `file_path`, in the order
+/// that they're applied
+fn layers<'a>(config_files: &'a [Arc<ConfigFile>], file_path: Option<&Path>) -> Vec<ConfigLayer<'a>> {
+    config_files
+        .iter()
+        .flat_map(|config_file| config_file.layers(file_path))
+        .collect()
+}
+
+fn merge(layers: &[ConfigLayer<'_>]) -> FusionConfig {
+    layers.iter().fold(new_default_config(), |config, layer| {
+        let mut config = FusionConfig::from_toml_with_defaults(layer.fusion.clone(), config);
+        if let Some(package) = layer.package {
+            config.package = PackageConfig::from_toml_with_defaults(package.clone(), config.package);
+            config.package.declared_in = Some(layer.directory.to_path_buf());
+        }
+        config
+    })
+}
+
+/// Lists the settings of `effective`, each attributed to the last layer that specifies it
+fn explain_section<T: Serialize>(
+    section: &'static str,
+    effective: &impl Serialize,
+    layers: &[(&ConfigSource, Option<&T>)],
+) -> Vec<ConfigSetting> {
+    let layer_tables: Vec<(&ConfigSource, toml::Table)> = layers
+        .iter()
+        .filter_map(|(source, settings)| Some((*source, to_table(settings.as_ref()?))))
+        .collect();
+    to_table(effective)
+        .into_iter()
+        .map(|(key, value)| {
+            let source = layer_tables
+                .iter()
+                .rev()
+                .find(|(_, table)| table.contains_key(&key))
+                .map_or(ConfigSource::Default, |(source, _)| (*source).clone());
+            ConfigSetting {
+                section,
+                key,
+                value: value.to_string(),
+                source,
+            }
+        })
+        .collect()
+}
+
+fn to_table(settings: &impl Serialize) -> toml::Table {
+    toml::Table::try_from(settings).expect("configs always serialize to tables")
+}
+
+fn current_directory() -> Result<PathBuf, Error> {
+    std::env::current_dir().map_err(|err| err_generic!("failed to determine current directory: {}", err))
+}
+
+fn absolute_path(path: &Path) -> Result<PathBuf, Error> {
+    std::path::absolute(path).map_err(|err| err_generic!("Failed to resolve {:?}: {}", path, err))
 }
 
 pub fn write_default_config() -> Result<(), Error> {
     use std::fs::File;
     use std::io::Write;
 
-    let mut file = File::create("fuusak.toml").map_err(|err| err_generic!("{}", err))?;
+    let mut file = File::create(CONFIG_FILE_NAME).map_err(|err| err_generic!("{}", err))?;
     write!(file, "{DEFAULT_CONFIG}").map_err(|err| err_generic!("{}", err))?;
     println!("Wrote default config to fuusak.toml");
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn hierarchical_config() {
+        let temp_dir = TempDir::new().unwrap();
+        let directory = temp_dir.path();
+        fs::create_dir_all(directory.join("sub/generated")).unwrap();
+        fs::write(
+            directory.join(CONFIG_FILE_NAME),
+            "root = true\n\
+             [fusion]\nmax_width = 80\nindent_width = 4\n\
+             [[overrides]]\nfiles = [\"**/generated/**\"]\nfusion = { newline_mode = \"no-change\" }\n",
+        )
+        .unwrap();
+        fs::write(
+            directory.join("sub").join(CONFIG_FILE_NAME),
+            "[fusion]\nmax_width = 90\n",
+        )
+        .unwrap();
+
+        let resolver = ConfigResolver::new(None).unwrap();
+        let top = resolver.config_for_file(&directory.join("top.fusion")).unwrap();
+        assert_eq!((80, 4, false), (top.max_width, top.indent_width, top.use_tabs));
+        let nested = resolver.config_for_file(&directory.join("sub/nested.fusion")).unwrap();
+        assert_eq!((90, 4), (nested.max_width, nested.indent_width));
+        assert_eq!(NewlineMode::FixUp, nested.newline_mode);
+        let generated = resolver
+            .config_for_file(&directory.join("sub/generated/gen.fusion"))
+            .unwrap();
+        assert_eq!(NewlineMode::NoChange, generated.newline_mode);
+        assert_eq!(90, generated.max_width);
+    }
+
+    #[test]
+    fn package_directory() {
+        let temp_dir = TempDir::new().unwrap();
+        let directory = temp_dir.path();
+        fs::create_dir_all(directory.join("src/nested")).unwrap();
+        fs::create_dir_all(directory.join("vendor/lib")).unwrap();
+        fs::write(
+            directory.join(CONFIG_FILE_NAME),
+            "root = true\n[package]\nmodule_roots = [\"src\"]\n",
+        )
+        .unwrap();
+        fs::write(
+            directory.join("src").join(CONFIG_FILE_NAME),
+            "[fusion]\nmax_width = 90\n",
+        )
+        .unwrap();
+        fs::write(
+            directory.join("vendor/lib").join(CONFIG_FILE_NAME),
+            "[package]\nmodule_roots = [\"modules\"]\n",
+        )
+        .unwrap();
+
+        // Running from a subdirectory still resolves package paths against the config file declaring them
+        let resolver = ConfigResolver::new(None).unwrap();
+        let config = resolver.load_directory(&directory.join("src/nested"), true).unwrap();
+        assert_eq!(directory, config.package.directory().unwrap());
+        assert_eq!(vec!["src"], config.package.module_roots);
+        let config = resolver.load_directory(&directory.join("vendor/lib"), true).unwrap();
+        assert_eq!(directory.join("vendor/lib"), config.package.directory().unwrap());
+        assert_eq!(vec!["modules"], config.package.module_roots);
+    }
+
+    fn load_error(directory: &Path, contents: &str) -> String {
+        let path = directory.join(CONFIG_FILE_NAME);
+        fs::write(&path, contents).unwrap();
+        let error = ConfigFile::load(&path).err().unwrap().to_string();
+        error.replace(&path.display().to_string(), CONFIG_FILE_NAME)
+    }
+
+    #[test]
+    fn strict_config() {
+        let temp_dir = TempDir::new().unwrap();
+        let directory = temp_dir.path();
+
+        let error = load_error(directory, "[fusion]\nmax_width = 80\n  fixed_indent_symbol = []\n");
+        assert!(
+            error.contains("fuusak.toml:3:3: unknown field `fixed_indent_symbol`"),
+            "{error}"
+        );
+        let error = load_error(directory, "[fusion]\nnewline_mode = \"fixup\"\n");
+        assert!(error.contains("fuusak.toml:2:16: unknown variant `fixup`"), "{error}");
+        let error = load_error(directory, "[fusion]\nindent_width = 0\n");
+        assert!(error.contains("fuusak.toml:2:16:"), "{error}");
+        let error = load_error(directory, "[[overrides]]\nfiles = [\"[\"]\nfusion = {}\n");
+        assert!(error.contains("fuusak.toml:2:9: Invalid glob pattern"), "{error}");
+        let error = load_error(
+            directory,
+            "[[overrides]]\nfiles = [\"gen/**\"]\nfusion = { max_width = 90, exclude = [\"a\"] }\n",
+        );
+        assert!(
+            error.contains("fuusak.toml:3:38: `exclude` can't be set in [[overrides]]"),
+            "{error}"
+        );
+
+        // File selection settings in nested config files are rejected, rather than only applying when run from below
+        fs::write(
+            directory.join(CONFIG_FILE_NAME),
+            "root = true\n[fusion]\nextensions = [\"fusion\"]\n",
+        )
+        .unwrap();
+        fs::create_dir(directory.join("sub")).unwrap();
+        let nested_path = directory.join("sub").join(CONFIG_FILE_NAME);
+        fs::write(&nested_path, "[fusion]\nmax_width = 90\ninclude = [\"src/**\"]\n").unwrap();
+        let resolver = ConfigResolver::new(None).unwrap();
+        assert!(resolver.config_for_file(&directory.join("top.fusion")).is_ok());
+        let error = resolver
+            .config_for_file(&directory.join("sub/nested.fusion"))
+            .err()
+            .unwrap()
+            .to_string();
+        let expected = format!(
+            "{}:3:11: `include` can only be set in the outermost config file",
+            nested_path.display()
+        );
+        assert!(error.contains(&expected), "{error}");
+        fs::write(&nested_path, "root = true\n[fusion]\ninclude = [\"src/**\"]\n").unwrap();
+        let resolver = ConfigResolver::new(None).unwrap();
+        let nested = resolver.config_for_file(&directory.join("sub/nested.fusion")).unwrap();
+        assert_eq!(vec!["src/**"], nested.include);
+    }
+
+    #[test]
+    fn explain_config() {
+        let temp_dir = TempDir::new().unwrap();
+        let directory = temp_dir.path();
+        fs::write(
+            directory.join(CONFIG_FILE_NAME),
+            "root = true\n\
+             [fusion]\nmax_width = 80\n\
+             [package]\nrepositories = [\"../dist\"]\n\
+             [[overrides]]\nfiles = [\"*.gen.fusion\"]\nfusion = { max_width = 120, line_ending = \"crlf\" }\n",
+        )
+        .unwrap();
+
+        let resolver = ConfigResolver::new(None).unwrap();
+        let setting = |settings: &[ConfigSetting], key: &str| {
+            let setting = settings.iter().find(|setting| setting.key == key).unwrap();
+            (setting.value.clone(), setting.source.to_string())
+        };
+        let config_path = directory.join(CONFIG_FILE_NAME).display().to_string();
+        let settings = resolver.explain(Some(&directory.join("a.fusion"))).unwrap();
+        assert_eq!(("80".to_string(), config_path.clone()), setting(&settings, "max_width"));
+        assert_eq!(
+            ("\"fix-up\"".to_string(), "default".to_string()),
+            setting(&settings, "newline_mode")
+        );
+        assert_eq!(
+            ("[\"../dist\"]".to_string(), config_path.clone()),
+            setting(&settings, "repositories")
+        );
+        assert_eq!(
+            ("[\"ftst\"]".to_string(), "default".to_string()),
+            setting(&settings, "test_roots")
+        );
+
+        let settings = resolver.explain(Some(&directory.join("a.gen.fusion"))).unwrap();
+        let override_source = format!("{config_path} [[overrides]] #1 (files = [\"*.gen.fusion\"])");
+        assert_eq!(
+            ("120".to_string(), override_source.clone()),
+            setting(&settings, "max_width")
+        );
+        assert_eq!(
+            ("\"crlf\"".to_string(), override_source),
+            setting(&settings, "line_ending")
+        );
+    }
+}
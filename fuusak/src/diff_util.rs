@@ -16,3 +16,59 @@ pub fn human_diff_lines<L: AsRef<str>, R: AsRef<str>>(left: L, right: R) -> Stri
     }
     output
 }
+
+/// A run of consecutive changed lines, as needed to turn the left text into the right text
+#[derive(Debug, PartialEq, Eq)]
+pub struct Hunk<'a> {
+    /// 1-based line number in the left text of the first removed line, or of the line that the added lines
+    /// are inserted before if no lines were removed
+    pub start_line: usize,
+    pub removed: Vec<&'a str>,
+    pub added: Vec<&'a str>,
+}
+
+pub fn diff_hunks<'a>(left: &'a str, right: &'a str) -> Vec<Hunk<'a>> {
+    let mut hunks: Vec<Hunk<'a>> = Vec::new();
+    let mut line = 1;
+    let mut in_hunk = false;
+    for diff in diff::lines(left, right) {
+        if !in_hunk && !matches!(diff, Result::Both(..)) {
+            hunks.push(Hunk {
+                start_line: line,
+                removed: Vec::new(),
+                added: Vec::new(),
+            });
+        }
+        in_hunk = !matches!(diff, Result::Both(..));
+        match diff {
+            Result::Left(value) => {
+                hunks.last_mut().unwrap().removed.push(value);
+                line += 1;
+            }
+            Result::Right(value) => hunks.last_mut().unwrap().added.push(value),
+            Result::Both(..) => line += 1,
+        }
+    }
+    hunks
+}
+
+#[cfg(test)]
+#[test]
+fn test_diff_hunks() {
+    assert_eq!(Vec::<Hunk<'_>>::new(), diff_hunks("a\nb", "a\nb"));
+    assert_eq!(
+        vec![
+            Hunk {
+                start_line: 2,
+                removed: vec!["b"],
+                added: vec!["  b"]
+            },
+            Hunk {
+                start_line: 4,
+                removed: vec![],
+                added: vec!["e"]
+            }
+        ],
+        diff_hunks("a\nb\nc\nd", "a\n  b\nc\ne\nd")
+    );
+}
@@ -3,6 +3,7 @@
 use crate::lexer::Rule;
 use crate::span::ShortSpan;
 use fuusak_err_macro::err_generic;
+use pest::error::LineColLocation;
 use std::fmt;
 use std::fmt::Display;
 use std::path::Path;
@@ -14,6 +15,8 @@ pub enum Error {
     /// Error message with a span that needs to be converted
     /// into a generic error to be human-friendly.
     Spanned(ShortSpan, String),
+    /// Error message that already shows where in a file the error is, along with that line and column
+    Located(usize, usize, String),
 }
 
 impl Error {
@@ -22,7 +25,7 @@ impl Error {
         use pest::Span;
         use pest::error::{Error as PestError, ErrorVariant};
         match self {
-            Error::Generic(msg) => Error::Generic(msg),
+            Error::Generic(_) | Error::Located(..) => self,
             Error::Spanned(span, msg) => {
                 let pest_span = Span::new(file_contents, span.start, span.end).unwrap();
                 let pest_error = PestError::new_from_span(
@@ -34,6 +37,34 @@ impl Error {
             }
         }
     }
+
+    /// Converts a spanned error into a located one, with the line and column of the span in `file_contents`
+    pub fn locate(self, file_contents: &str) -> Error {
+        match self {
+            Error::Spanned(span, msg) => {
+                let (line, column) = span.line_col(file_contents);
+                Error::Located(line, column, msg)
+            }
+            error => error,
+        }
+    }
+
+    /// Returns the line and column that a located error is at
+    pub fn line_col(&self) -> Option<(usize, usize)> {
+        match *self {
+            Error::Located(line, column, _) => Some((line, column)),
+            _ => None,
+        }
+    }
+
+    /// Replaces the message, such as to add context to it, while keeping where the error is
+    pub fn map_message(self, f: impl FnOnce(String) -> String) -> Error {
+        match self {
+            Error::Generic(msg) => Error::Generic(f(msg)),
+            Error::Spanned(span, msg) => Error::Spanned(span, f(msg)),
+            Error::Located(line, column, msg) => Error::Located(line, column, f(msg)),
+        }
+    }
 }
 
 impl std::error::Error for Error {
@@ -41,6 +72,7 @@ impl std::error::Error for Error {
         match *self {
             Error::Generic(ref message) => message,
             Error::Spanned(_span, ref message) => message,
+            Error::Located(_line, _column, ref message) => message,
         }
     }
 }
@@ -50,12 +82,17 @@ impl Display for Error {
         match *self {
             Error::Generic(ref message) => formatter.write_str(message),
             Error::Spanned(_span, ref message) => formatter.write_str(message),
+            Error::Located(_line, _column, ref message) => formatter.write_str(message),
         }
     }
 }
 
 impl From<pest::error::Error<Rule>> for Error {
     fn from(error: pest::error::Error<Rule>) -> Self {
-        Error::Generic(error.to_string())
+        let (line, column) = match error.line_col {
+            LineColLocation::Pos(position) => position,
+            LineColLocation::Span(start, _) => start,
+        };
+        Error::Located(line, column, error.to_string())
     }
 }
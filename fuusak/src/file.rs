@@ -6,10 +6,12 @@ use crate::error::Error;
 use crate::parser;
 use derive_new::new;
 use fuusak_err_macro::err_generic;
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
 use regex::{Captures, Regex};
+use std::collections::BTreeSet;
 use std::fs::{FileType, read_to_string};
 use std::io::{self, Read};
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 
 #[derive(new, Debug)]
 pub struct FusionFile {
@@ -39,7 +41,7 @@ impl FusionFile {
         let mut fusion_files = Vec::new();
         for fusion_file_path in &fusion_file_paths {
             let contents = FusionFileContent::load(fusion_file_path).map_err(|err| err_generic!("{}", err))?;
-            let fusion_file = contents.parse(fusion_config).map_err(|err| err_generic!("{}", err))?;
+            let fusion_file = contents.parse(fusion_config)?;
             fusion_files.push(fusion_file);
         }
         Ok(fusion_files)
@@ -53,21 +55,129 @@ impl FusionFile {
 
 /// Include the "." in `desired_extension`
 pub fn find_files<P: AsRef<Path>>(path: P, desired_extension: &str) -> Result<Vec<PathBuf>, Error> {
-    let mut fusion_files: Vec<PathBuf> = Vec::new();
+    let mut fusion_files = walk_files(path)?;
+    fusion_files.retain(|path| path.as_os_str().to_string_lossy().ends_with(desired_extension));
+    Ok(fusion_files)
+}
+
+/// Decides which files bulk commands (such as `format-all`) operate on, based on the
+/// `extensions`, `include`, and `exclude` settings in the config.
+pub struct FileSelector {
+    extensions: Vec<String>,
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl FileSelector {
+    pub fn new(fusion_config: &FusionConfig) -> Result<FileSelector, Error> {
+        let include = match fusion_config.include.is_empty() {
+            true => None,
+            false => Some(build_glob_set(&fusion_config.include)?),
+        };
+        Ok(FileSelector {
+            extensions: fusion_config.extensions.clone(),
+            include,
+            exclude: build_glob_set(&fusion_config.exclude)?,
+        })
+    }
+
+    /// Returns true if `path` has one of the configured extensions, matches the include patterns (if any),
+    /// and neither it nor any of its parent directories match an exclude pattern
+    pub fn is_selected(&self, path: &Path) -> bool {
+        let path = clean_path(path);
+        let has_extension = path
+            .extension()
+            .is_some_and(|extension| self.extensions.iter().any(|desired| extension == desired.as_str()));
+        has_extension
+            && self.include.as_ref().is_none_or(|include| include.is_match(&path))
+            && !path.ancestors().any(|ancestor| self.exclude.is_match(ancestor))
+    }
+
+    /// Finds all selected files given a list of files, directories, and glob patterns.
+    /// Directories are searched recursively, and glob patterns are matched against every file under
+    /// the current directory. If `args` is empty, the current directory is searched.
+    /// The result is sorted and free of duplicates.
+    pub fn find_files(&self, args: &[String]) -> Result<Vec<PathBuf>, Error> {
+        let args = match args.is_empty() {
+            true => &["./".to_string()][..],
+            false => args,
+        };
+        let mut found = BTreeSet::new();
+        for arg in args {
+            let path = Path::new(arg);
+            if path.is_file() {
+                found.insert(clean_path(path));
+            } else if path.is_dir() {
+                found.extend(walk_files(path)?.iter().map(|path| clean_path(path)));
+            } else if arg.contains(['*', '?', '[', '{']) {
+                let pattern = build_glob_set(std::slice::from_ref(arg))?;
+                let matches = walk_files("./")?.into_iter().map(|path| clean_path(&path));
+                found.extend(matches.filter(|path| pattern.is_match(path)));
+            } else {
+                return Err(err_generic!("Path {} doesn't exist", path.display()));
+            }
+        }
+        Ok(found.into_iter().filter(|path| self.is_selected(path)).collect())
+    }
+}
+
+pub(crate) fn build_glob_set(patterns: &[String]) -> Result<GlobSet, Error> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(compile_glob(pattern)?);
+    }
+    builder
+        .build()
+        .map_err(|err| err_generic!("Invalid glob patterns {:?}: {}", patterns, err))
+}
+
+fn compile_glob(pattern: &str) -> Result<Glob, Error> {
+    let pattern = pattern.strip_prefix("./").unwrap_or(pattern);
+    GlobBuilder::new(pattern)
+        .literal_separator(true)
+        .build()
+        .map_err(|err| err_generic!("Invalid glob pattern {:?}: {}", pattern, err))
+}
+
+/// Removes `.` components so that `./foo/bar.fusion` and `foo/bar.fusion` compare (and glob match) equally
+pub fn clean_path(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| *component != Component::CurDir)
+        .collect()
+}
+
+fn walk_files<P: AsRef<Path>>(path: P) -> Result<Vec<PathBuf>, Error> {
+    let mut files = Vec::new();
     let directory_walker = ignore::WalkBuilder::new(path.as_ref())
         .follow_links(true)
         .sort_by_file_path(Ord::cmp)
         .build();
     for entry in directory_walker {
         let entry = entry.map_err(|err| err_generic!("Failed to read input file: {}", err))?;
-        let path = entry.path();
-        if !entry.file_type().as_ref().is_none_or(FileType::is_dir)
-            && path.as_os_str().to_string_lossy().ends_with(desired_extension)
-        {
-            fusion_files.push(path.into());
+        if !entry.file_type().as_ref().is_none_or(FileType::is_dir) {
+            files.push(entry.into_path());
         }
     }
-    Ok(fusion_files)
+    Ok(files)
+}
+
+#[cfg(test)]
+#[test]
+fn test_file_selector() {
+    let mut config = crate::config::new_default_config();
+    config.extensions = vec!["fusion".into(), "ion".into()];
+    config.exclude = vec!["build".into(), "**/*_generated.fusion".into()];
+    let selector = FileSelector::new(&config).unwrap();
+    assert!(selector.is_selected(Path::new("./src/foo.fusion")));
+    assert!(selector.is_selected(Path::new("data/foo.ion")));
+    assert!(!selector.is_selected(Path::new("src/foo.txt")));
+    assert!(!selector.is_selected(Path::new("./build/foo.fusion")));
+    assert!(!selector.is_selected(Path::new("src/a/foo_generated.fusion")));
+
+    config.include = vec!["src/**".into()];
+    let selector = FileSelector::new(&config).unwrap();
+    assert!(selector.is_selected(Path::new("./src/a/foo.fusion")));
+    assert!(!selector.is_selected(Path::new("test/foo.fusion")));
 }
 
 fn replace_spans(file_content: &str, debug_view: &str) -> String {
@@ -116,8 +226,9 @@ impl FusionFileContent {
     }
 
     pub fn parse(self, fusion_config: &FusionConfig) -> Result<FusionFile, Error> {
-        let ast = parser::parse(&self.file_name, &self.contents, fusion_config)
-            .map_err(|error| err_generic!("Failed to parse {:?}: {}", self.file_name, error))?;
+        let ast = parser::parse(&self.file_name, &self.contents, fusion_config).map_err(|error| {
+            error.map_message(|message| format!("Failed to parse {:?}: {}", self.file_name, message))
+        })?;
         Ok(FusionFile::new(self.file_name, self.contents, ast))
     }
 }
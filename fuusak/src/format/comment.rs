@@ -0,0 +1,203 @@
+// Copyright Ion Fusion contributors. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+use crate::ast::{Expr, NonAnnotatedStringData};
+use std::mem;
+
+/// Joins runs of consecutive full-line `//` comments into a single `CommentLine` whose value holds one
+/// comment per line so that the formatter can reflow them together as paragraphs.
+pub fn join_line_comments(ast: &[Expr]) -> Vec<Expr> {
+    join_items(ast.to_vec(), true)
+}
+
+fn join_items(items: Vec<Expr>, top_level: bool) -> Vec<Expr> {
+    let mut joined: Vec<Expr> = Vec::with_capacity(items.len());
+    for mut expr in items {
+        if let Expr::SExpr(data) | Expr::List(data) | Expr::Struct(data) = &mut expr {
+            data.items = join_items(mem::take(&mut data.items), false);
+        }
+        if let Expr::CommentLine(data) = &expr
+            && let Some(previous) = joinable_comment(&mut joined, &data.value, top_level)
+        {
+            previous.value.push('\n');
+            previous.value.push_str(&data.value);
+            previous.span.end = data.span.end;
+            continue;
+        }
+        joined.push(expr);
+    }
+    joined
+}
+
+/// If `joined` ends with a comment that starts its own line followed by a single newline, and that
+/// comment can be joined with `next`, removes the newline and returns the comment so `next` can be appended.
+fn joinable_comment<'a>(
+    joined: &'a mut Vec<Expr>,
+    next: &str,
+    top_level: bool,
+) -> Option<&'a mut NonAnnotatedStringData> {
+    let previous = match &joined[..] {
+        [.., before, Expr::CommentLine(previous), Expr::Newlines(newlines)]
+            if newlines.newline_count == 1 && before.is_newlines() =>
+        {
+            previous
+        }
+        [Expr::CommentLine(previous), Expr::Newlines(newlines)] if newlines.newline_count == 1 && top_level => previous,
+        _ => return None,
+    };
+    if is_ruler(&previous.value)
+        || is_ruler(next)
+        || split_line_comment(&previous.value).0 != split_line_comment(next).0
+    {
+        return None;
+    }
+    joined.pop();
+    match joined.last_mut() {
+        Some(Expr::CommentLine(data)) => Some(data),
+        _ => unreachable!(),
+    }
+}
+
+/// Comments made up of nothing but slashes are used as rulers and shouldn't be joined with their neighbors
+fn is_ruler(comment: &str) -> bool {
+    comment.trim().chars().all(|chr| chr == '/')
+}
+
+/// Splits a (possibly joined) `//` comment into its marker (`//`, `///`, etc.) and the text of each line.
+/// A single space after the marker is removed so that `//comment` and `// comment` are treated alike.
+pub fn split_line_comment(value: &str) -> (&str, Vec<String>) {
+    let first = value.lines().next().unwrap_or_default();
+    let marker = &first[..first.len() - first.trim_start_matches('/').len()];
+    let lines = value
+        .lines()
+        .map(|line| {
+            let text = line.trim_start().trim_start_matches('/');
+            text.strip_prefix(' ').unwrap_or(text).to_string()
+        })
+        .collect();
+    (marker, lines)
+}
+
+#[cfg(test)]
+#[test]
+fn test_split_line_comment() {
+    assert_eq!(("//", vec!["foo".to_string()]), split_line_comment("//foo"));
+    assert_eq!(("//", vec!["foo".to_string()]), split_line_comment("// foo"));
+    assert_eq!(("///", vec![" foo".to_string()]), split_line_comment("///  foo"));
+    assert_eq!(
+        ("//", vec!["foo".to_string(), "bar".to_string()]),
+        split_line_comment("// foo\n  //bar")
+    );
+    assert_eq!(("////", vec![String::new()]), split_line_comment("////"));
+}
+
+/// Rewraps comment text so that lines are no longer than `width` where possible.
+/// Blank lines separate paragraphs, list items start a new paragraph with a hanging indent
+/// (and only absorb lines indented to match), and code samples (indented lines, fenced blocks, lines starting with an opening delimiter)
+/// and rulers (lines without any alphanumeric characters) are kept verbatim.
+/// Words are never broken, so long URLs stay intact.
+pub fn reflow(lines: &[String], width: usize) -> Vec<String> {
+    let mut output = Vec::new();
+    let mut words: Vec<&str> = Vec::new();
+    let mut hanging_indent = 0;
+    let mut in_fence = false;
+    for line in lines {
+        let line = line.trim_end();
+        let is_fence = line.trim_start().starts_with("```");
+        let continues_item = hanging_indent > 0
+            && !words.is_empty()
+            && line
+                .split_at_checked(hanging_indent)
+                .is_some_and(|(indent, rest)| indent.trim().is_empty() && !rest.is_empty() && !rest.starts_with(' '));
+        if continues_item {
+            words.extend(line.split_whitespace());
+        } else if is_fence || in_fence || line.is_empty() || is_verbatim(line) {
+            wrap_words(&mut output, &mut words, hanging_indent, width);
+            in_fence ^= is_fence;
+            output.push(line.to_string());
+        } else if let Some(marker_len) = list_marker_len(line) {
+            wrap_words(&mut output, &mut words, hanging_indent, width);
+            hanging_indent = marker_len + 1;
+            words.extend(line.split_whitespace());
+        } else {
+            if hanging_indent > 0 {
+                wrap_words(&mut output, &mut words, hanging_indent, width);
+                hanging_indent = 0;
+            }
+            words.extend(line.split_whitespace());
+        }
+    }
+    wrap_words(&mut output, &mut words, hanging_indent, width);
+    output
+}
+
+fn is_verbatim(line: &str) -> bool {
+    line.starts_with([' ', '\t', '(', '[', '{']) || !line.chars().any(char::is_alphanumeric)
+}
+
+fn list_marker_len(line: &str) -> Option<usize> {
+    let marker = line.split_whitespace().next()?;
+    if !line.strip_prefix(marker).is_some_and(|rest| rest.starts_with(' ')) {
+        return None;
+    }
+    let is_bullet = marker == "-" || marker == "*" || marker == "+";
+    let is_numbered = marker.len() > 1
+        && marker.ends_with(['.', ')'])
+        && marker[..marker.len() - 1].chars().all(|chr| chr.is_ascii_digit());
+    (is_bullet || is_numbered).then_some(marker.len())
+}
+
+fn wrap_words(output: &mut Vec<String>, words: &mut Vec<&str>, hanging_indent: usize, width: usize) {
+    let mut line = String::new();
+    let mut line_len = 0;
+    for word in words.drain(..) {
+        let word_len = word.chars().count();
+        if line_len == 0 {
+            line.push_str(word);
+            line_len += word_len;
+        } else if line_len + 1 + word_len <= width {
+            line.push(' ');
+            line.push_str(word);
+            line_len += 1 + word_len;
+        } else {
+            output.push(mem::take(&mut line));
+            line.push_str(&" ".repeat(hanging_indent));
+            line.push_str(word);
+            line_len = hanging_indent + word_len;
+        }
+    }
+    if line_len > 0 {
+        output.push(line);
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_reflow() {
+    let reflow = |lines: &[&str], width| reflow(&lines.iter().map(ToString::to_string).collect::<Vec<_>>(), width);
+    assert_eq!(vec!["foo bar", "baz"], reflow(&["foo bar baz"], 7));
+    assert_eq!(vec!["foo bar baz"], reflow(&["foo", "bar", "baz"], 20));
+    assert_eq!(vec!["foo", "", "bar"], reflow(&["foo", "", "bar"], 20));
+    assert_eq!(
+        vec!["intro", "- first item", "  wraps", "- second"],
+        reflow(&["intro", "- first item wraps", "- second"], 12)
+    );
+    assert_eq!(vec!["10. item", "    wraps"], reflow(&["10. item wraps"], 10));
+    assert_eq!(
+        vec!["- item continued", "prose after"],
+        reflow(&["- item", "  continued", "prose", "after"], 20)
+    );
+    assert_eq!(
+        vec!["example:", "  (foo 1 2)", "(bar)", "done"],
+        reflow(&["example:", "  (foo 1 2)", "(bar)", "done"], 40)
+    );
+    assert_eq!(
+        vec!["```", "keep  this as is", "```", "-----"],
+        reflow(&["```", "keep  this as is", "```", "-----"], 5)
+    );
+    assert_eq!(
+        vec!["see", "https://example.com/a/very/long/url", "ok"],
+        reflow(&["see https://example.com/a/very/long/url ok"], 10)
+    );
+    // The hanging indent falls inside a multi-byte character
+    assert_eq!(vec!["- item", "aé after"], reflow(&["- item", "aé after"], 20));
+}
@@ -6,6 +6,8 @@ pub fn fixup_ast(ast: &[Expr]) -> Vec<Expr> {
     ast.iter().cloned().map(fixup_expr).collect()
 }
 
+// Newer clippy wants the `if`s here folded into match guards, which doesn't read any better
+#[allow(clippy::collapsible_match)]
 fn fixup_expr(mut expr: Expr) -> Expr {
     use Expr::{List, SExpr, Struct};
 
@@ -36,6 +38,7 @@ fn fixup_expr(mut expr: Expr) -> Expr {
     expr
 }
 
+#[allow(clippy::collapsible_match)]
 fn clear_empty(mut expr: Expr) -> Expr {
     use Expr::{List, SExpr, Struct};
 
@@ -5,11 +5,48 @@ use crate::ast::{
     MultilineStringData, NewlinesData, NonAnnotatedStringData, NonAnnotatedStringListData,
 };
 use crate::config::FusionConfig;
+use crate::format::comment::{reflow, split_line_comment};
 use crate::string_util::{
-    already_has_whitespace_before_cursor, find_cursor_pos, format_indented_multiline, last_is_one_of, repeat,
-    trim_indent,
+    already_has_whitespace_before_cursor, find_cursor_pos, format_docstring, format_indented_multiline, last_is_one_of,
+    repeat, trim_indent,
 };
+use std::borrow::Cow;
 use std::fmt::Write;
+use unicode_width::UnicodeWidthStr;
+
+/// Reflowed comments are never wrapped narrower than this, no matter how deeply they're indented
+const MIN_REFLOW_WIDTH: usize = 20;
+
+/// Indentation made up of block levels (rendered as tabs if configured) followed by alignment columns
+/// (always rendered as spaces so that aligned code lines up no matter how wide a tab is displayed).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct Indent {
+    blocks: usize,
+    align: usize,
+}
+
+impl Indent {
+    /// Indentation that aligns `columns` past this one
+    fn aligned(self, columns: usize) -> Indent {
+        Indent {
+            blocks: self.blocks,
+            align: self.align + columns,
+        }
+    }
+
+    /// Indentation for a block nested within this one. A block opened at the start of its line gets a new
+    /// block level, but a block opened after other code on its line can only be reached with alignment.
+    fn nested(self, indent_width: usize) -> Indent {
+        if self.align == 0 {
+            Indent {
+                blocks: self.blocks + 1,
+                align: 0,
+            }
+        } else {
+            self.aligned(indent_width)
+        }
+    }
+}
 
 pub struct Formatter<'i> {
     config: &'i FusionConfig,
@@ -24,7 +61,7 @@ impl<'i> Formatter<'i> {
     }
 
     pub fn format(&mut self, exprs: &Vec<Expr>) {
-        self.visit_exprs(exprs, 0);
+        self.visit_exprs(exprs, Indent::default());
     }
 
     pub fn finish(self) -> String {
@@ -34,20 +71,20 @@ impl<'i> Formatter<'i> {
             .fold(String::new(), |l, r| l + r + "\n")
     }
 
-    fn visit_exprs(&mut self, exprs: &Vec<Expr>, next_indent: usize) {
+    fn visit_exprs(&mut self, exprs: &Vec<Expr>, next_indent: Indent) {
         for expr in exprs {
             self.visit_expr(expr, next_indent);
         }
     }
 
-    fn visit_expr(&mut self, expr: &Expr, next_indent: usize) {
+    fn visit_expr(&mut self, expr: &Expr, next_indent: Indent) {
         match expr {
             Expr::Atomic(data) => self.visit_atomic(data),
             Expr::Clob(data) => self.visit_clob(data, next_indent),
             Expr::CommentBlock(data) => self.visit_comment_block(data, next_indent),
             Expr::CommentLine(data) => self.visit_comment_line(data, next_indent),
             Expr::List(data) => self.visit_list(data),
-            Expr::MultilineString(data) => self.visit_multiline_string(data),
+            Expr::MultilineString(data) => self.visit_multiline_string(data, false),
             Expr::Newlines(data) => self.visit_newlines(data, next_indent),
             Expr::SExpr(data) => self.visit_sexpr(data),
             Expr::Struct(data) => self.visit_struct(data),
@@ -69,13 +106,13 @@ impl<'i> Formatter<'i> {
         }
     }
 
-    fn visit_clob(&mut self, data: &ClobData, next_indent: usize) {
+    fn visit_clob(&mut self, data: &ClobData, next_indent: Indent) {
         self.visit_annotations(&data.annotations);
         self.output.push_str("{{");
 
         let continuation_indent = match (&data.clobs[..]).count_items_before_newline() {
-            0 => next_indent + 1,
-            _ => find_cursor_pos(&self.output) + 1,
+            0 => next_indent.aligned(1),
+            _ => self.cursor_indent().aligned(1),
         };
         for expr in &data.clobs {
             if !expr.is_newlines() && !already_has_whitespace_before_cursor(&self.output) {
@@ -99,20 +136,25 @@ impl<'i> Formatter<'i> {
         self.output.push_str("'''");
     }
 
-    fn visit_comment_block(&mut self, data: &NonAnnotatedStringListData, _next_indent: usize) {
-        let continuation_indent = find_cursor_pos(&self.output) + 1;
+    fn visit_comment_block(&mut self, data: &NonAnnotatedStringListData, _next_indent: Indent) {
+        let continuation_indent = self.cursor_indent().aligned(1);
+        let lines = if self.config.reflow_comments {
+            self.reflow_block_comment(&data.value, continuation_indent)
+        } else {
+            Cow::Borrowed(&data.value[..])
+        };
         self.output.push_str("/*");
-        if data.value.len() == 1 {
+        if lines.len() == 1 {
             self.output.push(' ');
-            self.output.push_str(data.value[0].trim());
+            self.output.push_str(lines[0].trim());
             self.output.push(' ');
         } else {
-            for i in 0..data.value.len() {
-                let line = &data.value[i];
-                if i > 0 && line.trim().is_empty() && i == data.value.len() - 1 {
+            for i in 0..lines.len() {
+                let line = &lines[i];
+                if i > 0 && line.trim().is_empty() && i == lines.len() - 1 {
                     break;
                 } else if i > 0 {
-                    self.output.push_str(&repeat(' ', continuation_indent));
+                    self.output.push_str(&self.render(continuation_indent));
                     self.output.push('*');
                 }
                 if !line.trim().is_empty() {
@@ -122,35 +164,118 @@ impl<'i> Formatter<'i> {
                 self.output.push('\n');
             }
             if last_is_one_of(&self.output, &['\n']) {
-                self.output.push_str(&repeat(' ', continuation_indent));
+                self.output.push_str(&self.render(continuation_indent));
             }
         }
         self.output.push_str("*/");
     }
 
-    fn visit_comment_line(&mut self, data: &NonAnnotatedStringData, next_indent: usize) {
-        self.output.push_str(&data.value);
-        self.output.push_str(&newline(0, next_indent));
+    /// Rewraps the lines of a block comment, moving the text onto its own lines between `/*` and `*/`
+    /// whenever the comment spans multiple lines or doesn't fit on one line.
+    fn reflow_block_comment<'a>(&self, lines: &'a [String], continuation_indent: Indent) -> Cow<'a, [String]> {
+        // Text is indented past the `* ` at the start of each continuation line
+        let width = self.reflow_width(self.columns(continuation_indent) + 2);
+        if let [line] = lines {
+            // Single line comments also need room for `/* ` and ` */`
+            if line.trim().chars().count() + 3 <= width {
+                return Cow::Borrowed(lines);
+            }
+        }
+        let first = lines.iter().position(|line| !line.trim().is_empty()).unwrap_or(0);
+        let last = lines.iter().rposition(|line| !line.trim().is_empty()).unwrap_or(0);
+        let mut reflowed = vec![String::new()];
+        reflowed.extend(reflow(&lines[first..=last], width));
+        reflowed.push(String::new());
+        Cow::Owned(reflowed)
     }
 
-    fn visit_multiline_string(&mut self, data: &MultilineStringData) {
+    fn visit_comment_line(&mut self, data: &NonAnnotatedStringData, next_indent: Indent) {
+        if self.config.reflow_comments && !self.cursor_is_after_code() {
+            let indent = self.cursor_indent();
+            let (marker, lines) = split_line_comment(&data.value);
+            let width = self.reflow_width(self.columns(indent) + marker.len() + 1);
+            for (i, line) in reflow(&lines, width).iter().enumerate() {
+                if i > 0 {
+                    self.output.push_str(&self.newline(1, indent));
+                }
+                self.output.push_str(marker);
+                if !line.is_empty() {
+                    self.output.push(' ');
+                    self.output.push_str(line);
+                }
+            }
+        } else if self.config.reflow_comments {
+            // Trailing comments stay on their line, but still get their spacing normalized
+            let (marker, lines) = split_line_comment(&data.value);
+            self.output.push_str(marker);
+            if !lines[0].is_empty() {
+                self.output.push(' ');
+                self.output.push_str(&lines[0]);
+            }
+        } else {
+            self.output.push_str(&data.value);
+        }
+        self.output.push_str(&self.newline(0, next_indent));
+    }
+
+    fn cursor_is_after_code(&self) -> bool {
+        let line_start = self.output.rfind('\n').map_or(0, |index| index + 1);
+        !self.output[line_start..].trim().is_empty()
+    }
+
+    fn reflow_width(&self, text_column: usize) -> usize {
+        self.config.max_width.saturating_sub(text_column).max(MIN_REFLOW_WIDTH)
+    }
+
+    /// Determines the indentation of the cursor's position, treating the leading tabs
+    /// of the current line as block levels and everything after them as alignment
+    fn cursor_indent(&self) -> Indent {
+        let line_start = self.output.rfind('\n').map_or(0, |index| index + 1);
+        let blocks = self.output[line_start..].chars().take_while(|chr| *chr == '\t').count();
+        let column = find_cursor_pos(&self.output, self.config.indent_width);
+        Indent {
+            blocks,
+            align: column.saturating_sub(blocks * self.config.indent_width),
+        }
+    }
+
+    fn columns(&self, indent: Indent) -> usize {
+        indent.blocks * self.config.indent_width + indent.align
+    }
+
+    fn render(&self, indent: Indent) -> String {
+        if self.config.use_tabs {
+            repeat('\t', indent.blocks) + &repeat(' ', indent.align)
+        } else {
+            repeat(' ', self.columns(indent))
+        }
+    }
+
+    fn newline(&self, newline_count: usize, indent: Indent) -> String {
+        repeat('\n', newline_count) + &self.render(indent)
+    }
+
+    fn visit_multiline_string(&mut self, data: &MultilineStringData, is_docstring: bool) {
         self.visit_annotations(&data.annotations);
-        let continuation_indent = find_cursor_pos(&self.output);
+        let continuation_indent = self.render(self.cursor_indent());
         self.output.push_str("'''");
-        let value = if self.config.format_multiline_string_contents {
-            format_indented_multiline(&trim_indent(&data.value), continuation_indent)
+        let value = if is_docstring && self.config.format_docstrings {
+            format_docstring(&data.value, &continuation_indent)
+        } else if self.config.format_multiline_string_contents {
+            format_indented_multiline(&trim_indent(&data.value), &continuation_indent)
         } else {
             data.value.clone()
         };
         self.output.push_str(value.trim_end_matches([' ', '\t']));
         if last_is_one_of(&self.output, &['\n']) {
-            self.output.push_str(&repeat(' ', continuation_indent));
+            self.output.push_str(&continuation_indent);
         }
         self.output.push_str("'''");
     }
 
-    fn visit_newlines(&mut self, data: &NewlinesData, next_indent: usize) {
-        self.output.push_str(&newline(data.newline_count as usize, next_indent));
+    fn visit_newlines(&mut self, data: &NewlinesData, next_indent: Indent) {
+        self.output
+            .push_str(&self.newline(data.newline_count as usize, next_indent));
     }
 
     // Complicated logic for determining whitespace between s-expression members due to
@@ -183,14 +308,20 @@ impl<'i> Formatter<'i> {
 
     fn visit_sexpr(&mut self, data: &ListData) {
         self.visit_annotations(&data.annotations);
-        let opening_indent = find_cursor_pos(&self.output);
+        let opening_indent = self.cursor_indent();
         self.output.push('(');
 
         let bound = Formatter::bind_whitespace(&data.items);
         if !bound.is_empty() {
             let continuation_indent = calculate_continuation_indent(self.config, &data.items, opening_indent);
-            for (item, add_space) in bound {
-                self.visit_expr(item, continuation_indent);
+            let docstring_index = find_docstring_index(self.config, &data.items);
+            for (i, (item, add_space)) in bound.into_iter().enumerate() {
+                match item {
+                    Expr::MultilineString(string) if docstring_index == Some(i) => {
+                        self.visit_multiline_string(string, true);
+                    }
+                    _ => self.visit_expr(item, continuation_indent),
+                }
                 if add_space {
                     self.output.push(' ');
                 }
@@ -201,10 +332,10 @@ impl<'i> Formatter<'i> {
 
     fn visit_list(&mut self, data: &ListData) {
         self.visit_annotations(&data.annotations);
+        let opening_indent = self.cursor_indent();
         self.output.push('[');
         if !data.items.is_empty() {
-            let opening_indent = find_cursor_pos(&self.output) - 1;
-            let continuation_indent = opening_indent + 1;
+            let continuation_indent = opening_indent.aligned(1);
             for i in 0..data.items.len() {
                 let item = &data.items[i];
                 if !item.is_newlines() && last_is_one_of(&self.output, &[',']) {
@@ -234,10 +365,10 @@ impl<'i> Formatter<'i> {
     fn visit_struct(&mut self, data: &ListData) {
         self.visit_annotations(&data.annotations);
 
-        let empty_continuation = find_cursor_pos(&self.output);
-        let key_continuation = empty_continuation + 1;
-        let nested_struct_continuation = key_continuation + 3;
-        let value_continuation = key_continuation + 3;
+        let empty_continuation = self.cursor_indent();
+        let key_continuation = empty_continuation.aligned(1);
+        let nested_struct_continuation = key_continuation.aligned(3);
+        let value_continuation = key_continuation.aligned(3);
 
         self.output.push('{');
         for i in 0..data.items.len() {
@@ -261,23 +392,36 @@ impl<'i> Formatter<'i> {
                 if last_is_one_of(&self.output, &[':', '/']) || value.is_comment() {
                     self.output.push(' ');
                 }
-                self.visit_expr(value, 0);
+                self.visit_expr(value, Indent::default());
                 if value.is_value() && data.items[(i + 1)..].iter().any(Expr::is_value) {
                     self.output.push(',');
                 }
             }
         }
-        if !last_is_one_of(&self.output, &['{', '}', ' ', '\n']) {
+        if !last_is_one_of(&self.output, &['{', '}', ' ', '\t', '\n']) {
             self.output.push(' ');
         }
         self.output.push('}');
     }
 }
 
-fn newline(newline_count: usize, indent: usize) -> String {
-    let mut output = repeat('\n', newline_count);
-    output.push_str(&repeat(' ', indent));
-    output
+/// Finds the index within `exprs` of a docstring, which is the third value of a form
+/// starting with one of the configured docstring symbols, so long as a body follows it.
+/// For example, `(define (foo) '''docs''' body)` or `(define foo '''docs''' value)`.
+fn find_docstring_index(config: &FusionConfig, exprs: &[Expr]) -> Option<usize> {
+    let mut values = exprs.iter().enumerate().filter(|(_, expr)| expr.is_value());
+    let (_, first) = values.next()?;
+    if !first
+        .symbol_value()
+        .is_some_and(|symbol| config.docstring_symbols.contains(symbol))
+    {
+        return None;
+    }
+    let (index, docstring) = values.nth(1)?;
+    match (docstring, values.next()) {
+        (Expr::MultilineString(_), Some(_)) => Some(index),
+        _ => None,
+    }
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -291,7 +435,7 @@ enum IndentType {
     EndOfOpening,
     /// (foo (bar)
     ///      (baz)) // <-- this indent type
-    EndOfOpeningSymbol(usize),
+    EndOfOpeningSymbol(Indent),
     /// (define (foo)
     ///   (baz)) // <-- this indent type
     /// Or:
@@ -302,7 +446,7 @@ enum IndentType {
     Undetermined,
 }
 
-fn calculate_continuation_indent(config: &FusionConfig, exprs: &[Expr], next_indent: usize) -> usize {
+fn calculate_continuation_indent(config: &FusionConfig, exprs: &[Expr], next_indent: Indent) -> Indent {
     // Figure out what indentation would be without any config
     let mut indent_type = match exprs.count_items_before_newline() {
         0 => IndentType::EndOfOpening,
@@ -316,7 +460,7 @@ fn calculate_continuation_indent(config: &FusionConfig, exprs: &[Expr], next_ind
         if first.is_symbol() {
             let symbol_value = first.symbol_value().unwrap();
             if indent_type != IndentType::Fixed {
-                indent_type = IndentType::EndOfOpeningSymbol(next_indent + symbol_value.len() + 2);
+                indent_type = IndentType::EndOfOpeningSymbol(next_indent.aligned(symbol_value.width() + 2));
             }
             if config.fixed_indent_symbols.contains(symbol_value) {
                 // Symbol configured to always use fixed indent
@@ -340,8 +484,8 @@ fn calculate_continuation_indent(config: &FusionConfig, exprs: &[Expr], next_ind
 
     // Translate indentation type into numbers
     match indent_type {
-        IndentType::EndOfOpening => next_indent + 1,
-        IndentType::Fixed => next_indent + 2,
+        IndentType::EndOfOpening => next_indent.aligned(1),
+        IndentType::Fixed => next_indent.nested(config.indent_width),
         IndentType::EndOfOpeningSymbol(indent) => indent,
         IndentType::Undetermined => unreachable!(),
     }
@@ -1,24 +1,37 @@
 // Copyright Ion Fusion contributors. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 
+mod comment;
 mod fixup;
 mod formatter;
 
 use crate::ast::Expr;
 use crate::config::FusionConfig;
+use crate::file::FusionFile;
 use crate::format::formatter::Formatter;
+use crate::layout::TextLayout;
+use std::borrow::Cow;
 
 /// Formats the given AST into a String using the provided `FusionConfig`
 pub fn format(fusion_config: &FusionConfig, ast: &Vec<Expr>) -> String {
     let mut formatter = Formatter::new(fusion_config);
+    let mut ast = Cow::Borrowed(ast);
     if fusion_config.newline_fix_up_mode() {
-        formatter.format(&fixup::fixup_ast(ast));
-    } else {
-        formatter.format(ast);
+        ast = Cow::Owned(fixup::fixup_ast(&ast));
     }
+    if fusion_config.reflow_comments {
+        ast = Cow::Owned(comment::join_line_comments(&ast));
+    }
+    formatter.format(&ast);
     formatter.finish()
 }
 
+/// Formats the given `FusionFile` into a String, using the line endings, byte order mark,
+/// and final newline required by the provided `FusionConfig`
+pub fn format_file(fusion_config: &FusionConfig, file: &FusionFile) -> String {
+    TextLayout::expected(fusion_config, &file.contents).apply(&format(fusion_config, &file.ast))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -66,6 +79,17 @@ mod tests {
         );
     }
 
+    #[test]
+    fn docstring() {
+        let mut config = new_default_config();
+        config.format_docstrings = true;
+        test!(
+            &config,
+            "../../format_tests/docstring.input.fusion",
+            "../../format_tests/docstring.formatted.fusion"
+        );
+    }
+
     #[test]
     fn multiline_string() {
         let mut config = new_default_config();
@@ -77,6 +101,30 @@ mod tests {
         );
     }
 
+    #[test]
+    fn comment_reflow() {
+        let mut config = new_default_config();
+        config.reflow_comments = true;
+        config.max_width = 60;
+        test!(
+            &config,
+            "../../format_tests/comment_reflow.input.fusion",
+            "../../format_tests/comment_reflow.formatted.fusion"
+        );
+    }
+
+    #[test]
+    fn tabs() {
+        let mut config = new_default_config();
+        config.use_tabs = true;
+        config.indent_width = 4;
+        test!(
+            &config,
+            "../../format_tests/tabs.input.fusion",
+            "../../format_tests/tabs.formatted.fusion"
+        );
+    }
+
     #[test]
     fn multiline_string_no_change_whitespace() {
         test!(
@@ -85,6 +133,15 @@ mod tests {
         );
     }
 
+    #[test]
+    fn crlf_and_bom() {
+        let config = new_default_config();
+        let file = FusionFileContent::new("test".into(), "\u{FEFF}(foo\r\nbar)\r\n\r\n".into())
+            .parse(&config)
+            .unwrap_or_else(|error| panic!("Error: {}", error));
+        assert_eq!("\u{FEFF}(foo\r\n  bar)\r\n", format_file(&config, &file));
+    }
+
     #[test]
     fn simple_continuations() {
         test!(
@@ -0,0 +1,220 @@
+// Copyright Ion Fusion contributors. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+use crate::config::FusionConfig;
+use std::fmt;
+
+const BOM: char = '\u{FEFF}';
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+}
+
+impl fmt::Display for LineEnding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LineEnding::Lf => f.write_str("LF"),
+            LineEnding::CrLf => f.write_str("CRLF"),
+        }
+    }
+}
+
+/// The line endings, byte order mark, and final newline of a file's text
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TextLayout {
+    pub line_ending: LineEnding,
+    pub bom: bool,
+    pub final_newline: bool,
+}
+
+impl TextLayout {
+    /// Detects the layout of the given text. If the text mixes line endings, the most common one wins.
+    pub fn detect(contents: &str) -> TextLayout {
+        let (lf, crlf) = count_line_endings(contents);
+        TextLayout {
+            line_ending: if crlf > lf { LineEnding::CrLf } else { LineEnding::Lf },
+            bom: contents.starts_with(BOM),
+            final_newline: contents.ends_with('\n'),
+        }
+    }
+
+    /// Determines the layout that the formatted version of `contents` should have
+    pub fn expected(fusion_config: &FusionConfig, contents: &str) -> TextLayout {
+        let detected = TextLayout::detect(contents);
+        TextLayout {
+            line_ending: fusion_config.configured_line_ending().unwrap_or(detected.line_ending),
+            bom: detected.bom && fusion_config.preserve_bom,
+            final_newline: fusion_config.final_newline,
+        }
+    }
+
+    /// Applies this layout to `text`, which is expected to only use LF line endings
+    pub fn apply(&self, text: &str) -> String {
+        let mut output = String::with_capacity(text.len() + 1);
+        if self.bom {
+            output.push(BOM);
+        }
+        let text = text.trim_end_matches('\n');
+        match self.line_ending {
+            LineEnding::Lf => output.push_str(text),
+            LineEnding::CrLf => output.push_str(&text.replace('\n', "\r\n")),
+        }
+        if self.final_newline && !text.is_empty() {
+            output.push_str(self.line_ending.as_str());
+        }
+        output
+    }
+}
+
+/// Removes the byte order mark and converts all line endings to LF so that
+/// text can be compared without regard to its layout
+pub fn normalize(contents: &str) -> String {
+    contents.strip_prefix(BOM).unwrap_or(contents).replace("\r\n", "\n")
+}
+
+/// A way in which the layout of a file differs from the one required by the config
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LayoutViolation {
+    LineEnding { expected: LineEnding, found: LineEnding },
+    MixedLineEndings { expected: LineEnding },
+    UnexpectedBom,
+    FinalNewline { expected: bool },
+}
+
+impl fmt::Display for LayoutViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LayoutViolation::LineEnding { expected, found } => {
+                write!(f, "uses {found} line endings, but {expected} is expected")
+            }
+            LayoutViolation::MixedLineEndings { expected } => {
+                write!(f, "mixes LF and CRLF line endings, but only {expected} is expected")
+            }
+            LayoutViolation::UnexpectedBom => f.write_str("starts with a byte order mark"),
+            LayoutViolation::FinalNewline { expected: true } => f.write_str("doesn't end with a single newline"),
+            LayoutViolation::FinalNewline { expected: false } => f.write_str("ends with a newline"),
+        }
+    }
+}
+
+/// Checks `contents` against the layout required by the config
+pub fn check(fusion_config: &FusionConfig, contents: &str) -> Vec<LayoutViolation> {
+    let detected = TextLayout::detect(contents);
+    let expected = TextLayout::expected(fusion_config, contents);
+    let mut violations = Vec::new();
+
+    let (lf, crlf) = count_line_endings(contents);
+    if lf > 0 && crlf > 0 {
+        violations.push(LayoutViolation::MixedLineEndings {
+            expected: expected.line_ending,
+        });
+    } else if lf + crlf > 0 && detected.line_ending != expected.line_ending {
+        violations.push(LayoutViolation::LineEnding {
+            expected: expected.line_ending,
+            found: detected.line_ending,
+        });
+    }
+    if detected.bom && !expected.bom {
+        violations.push(LayoutViolation::UnexpectedBom);
+    }
+    let text = normalize(contents);
+    let has_single_final_newline = text.ends_with('\n') && !text.ends_with("\n\n");
+    if !text.trim().is_empty() && has_single_final_newline != expected.final_newline {
+        violations.push(LayoutViolation::FinalNewline {
+            expected: expected.final_newline,
+        });
+    }
+    violations
+}
+
+/// Counts the (LF, CRLF) line endings in `contents`
+fn count_line_endings(contents: &str) -> (usize, usize) {
+    let total = contents.matches('\n').count();
+    let crlf = contents.matches("\r\n").count();
+    (total - crlf, crlf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{LineEndingMode, new_default_config};
+
+    #[test]
+    fn detect() {
+        let layout = |line_ending, bom, final_newline| TextLayout {
+            line_ending,
+            bom,
+            final_newline,
+        };
+        assert_eq!(layout(LineEnding::Lf, false, false), TextLayout::detect("foo"));
+        assert_eq!(layout(LineEnding::Lf, false, true), TextLayout::detect("foo\nbar\n"));
+        assert_eq!(
+            layout(LineEnding::CrLf, false, true),
+            TextLayout::detect("foo\r\nbar\r\n")
+        );
+        assert_eq!(
+            layout(LineEnding::CrLf, true, false),
+            TextLayout::detect("\u{FEFF}a\r\nb\r\nc\nd")
+        );
+    }
+
+    #[test]
+    fn apply() {
+        let config = new_default_config();
+        assert_eq!("foo\nbar\n", TextLayout::expected(&config, "").apply("foo\nbar\n\n"));
+        assert_eq!(
+            "\u{FEFF}foo\r\nbar\r\n",
+            TextLayout::expected(&config, "\u{FEFF}x\r\n").apply("foo\nbar\n")
+        );
+
+        let mut config = new_default_config();
+        config.line_ending = LineEndingMode::Crlf;
+        config.preserve_bom = false;
+        config.final_newline = false;
+        assert_eq!(
+            "foo\r\nbar",
+            TextLayout::expected(&config, "\u{FEFF}x\n").apply("foo\nbar\n")
+        );
+    }
+
+    #[test]
+    fn check_violations() {
+        let mut config = new_default_config();
+        assert_eq!(Vec::<LayoutViolation>::new(), check(&config, "\u{FEFF}foo\r\nbar\r\n"));
+        assert_eq!(
+            vec![LayoutViolation::MixedLineEndings {
+                expected: LineEnding::CrLf
+            }],
+            check(&config, "a\r\nb\r\nc\n")
+        );
+        assert_eq!(
+            vec![LayoutViolation::FinalNewline { expected: true }],
+            check(&config, "foo\n\n")
+        );
+
+        config.line_ending = LineEndingMode::Lf;
+        config.preserve_bom = false;
+        config.final_newline = false;
+        assert_eq!(
+            vec![
+                LayoutViolation::LineEnding {
+                    expected: LineEnding::Lf,
+                    found: LineEnding::CrLf
+                },
+                LayoutViolation::UnexpectedBom,
+                LayoutViolation::FinalNewline { expected: false },
+            ],
+            check(&config, "\u{FEFF}foo\r\nbar\r\n")
+        );
+    }
+}
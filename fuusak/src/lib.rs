@@ -1,9 +1,11 @@
 pub mod ast;
+pub mod checkstyle;
 pub mod config;
 pub mod diff_util;
 pub mod error;
 pub mod file;
 pub mod format;
+pub mod layout;
 pub mod lexer;
 pub mod parser;
 pub mod span;
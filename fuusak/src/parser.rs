@@ -9,14 +9,18 @@ use crate::error::Error;
 use crate::lexer::{FPair, FPairs, FusionLexer, Rule};
 use crate::span::ShortSpan;
 use crate::string_util::count_newlines;
-use fuusak_err_macro::{err_generic, err_spanned};
+use fuusak_err_macro::err_spanned;
 use pest::Parser;
 use std::path::Path;
 
 pub type ParseResult = Result<Vec<Expr>, Error>;
 
 pub fn parse<P: AsRef<Path>>(file_name: P, source: &str, config: &FusionConfig) -> ParseResult {
-    parse_str(source, config).map_err(|error| err_generic!("{}{}", file_name.as_ref().display(), error))
+    parse_str(source, config).map_err(|error| {
+        error
+            .locate(source)
+            .map_message(|message| format!("{}{}", file_name.as_ref().display(), message))
+    })
 }
 
 pub fn parse_str(source: &str, config: &FusionConfig) -> ParseResult {
@@ -241,6 +245,7 @@ fn visit_pair(pair: FPair<'_>, config: &FusionConfig) -> ParseResult {
         | Rule::BLOB_INNER
         | Rule::BLOB_INNER_CHAR
         | Rule::block_comment
+        | Rule::BOM
         | Rule::DECIMAL_INT
         | Rule::script
         | Rule::HEX_INT
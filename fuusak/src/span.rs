@@ -2,10 +2,11 @@
 // SPDX-License-Identifier: Apache-2.0
 use derive_new::new;
 use pest::Span;
+use serde::{Deserialize, Serialize};
 use std::fmt;
 
 // Copyable alternative to Pest's Span
-#[derive(new, Clone, Copy, PartialEq, Eq)]
+#[derive(new, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub struct ShortSpan {
     pub start: usize,
     pub end: usize,
@@ -23,3 +24,24 @@ impl fmt::Debug for ShortSpan {
         write!(f, "[Span({}->{})]", self.start, self.end)
     }
 }
+
+impl ShortSpan {
+    /// Returns the 1-based line and column (counting characters) that this span starts at in `contents`
+    pub fn line_col(&self, contents: &str) -> (usize, usize) {
+        let before = &contents[..self.start];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        (
+            before.matches('\n').count() + 1,
+            before[line_start..].chars().count() + 1,
+        )
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_line_col() {
+    let contents = "(a)\n  (bé c)\n";
+    assert_eq!((1, 1), ShortSpan::new(0, 1).line_col(contents));
+    assert_eq!((2, 3), ShortSpan::new(6, 7).line_col(contents));
+    assert_eq!((2, 7), ShortSpan::new(11, 12).line_col(contents));
+}
@@ -1,6 +1,7 @@
 // Copyright Ion Fusion contributors. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
 use regex::Regex;
+use unicode_width::UnicodeWidthChar;
 
 pub fn count_newlines(input: &str) -> usize {
     let newline_regex = Regex::new(r"\r\n?|\n").unwrap();
@@ -21,25 +22,35 @@ fn test_repeat() {
     assert_eq!("####", repeat('#', 4));
 }
 
-pub fn find_cursor_pos(value: &str) -> usize {
-    match value.rfind('\n') {
-        Some(index) => (value.len() - index) - 1,
-        None => value.len(),
-    }
+/// Finds the visual column at the end of `value`. Tabs advance to the next multiple of `tab_width`,
+/// and wide characters (such as CJK ideographs) take up two columns.
+pub fn find_cursor_pos(value: &str, tab_width: usize) -> usize {
+    let tab_width = tab_width.max(1);
+    let line = value.rfind('\n').map_or(value, |index| &value[(index + 1)..]);
+    line.chars().fold(0, |column, chr| match chr {
+        '\t' => (column / tab_width + 1) * tab_width,
+        _ => column + chr.width().unwrap_or(0),
+    })
 }
 
 #[cfg(test)]
 #[test]
 fn test_find_cursor_pos() {
-    assert_eq!(2, find_cursor_pos("  "));
-    assert_eq!(2, find_cursor_pos("   \n  "));
-    assert_eq!(0, find_cursor_pos("   \n"));
+    assert_eq!(2, find_cursor_pos("  ", 2));
+    assert_eq!(2, find_cursor_pos("   \n  ", 2));
+    assert_eq!(0, find_cursor_pos("   \n", 2));
+    assert_eq!(4, find_cursor_pos("\t\t", 2));
+    assert_eq!(8, find_cursor_pos("\t\t", 4));
+    assert_eq!(4, find_cursor_pos("ab\t", 4));
+    assert_eq!(6, find_cursor_pos("\t  ", 4));
+    assert_eq!(5, find_cursor_pos("(日本", 2));
+    assert_eq!(2, find_cursor_pos("(é", 2));
 }
 
 pub fn already_has_whitespace_before_cursor(value: &str) -> bool {
     let value = value.as_bytes();
     let index = value.len().cast_signed() - 1;
-    index > 0 && (value[index.cast_unsigned()] == b' ' || value[index.cast_unsigned()] == b'\n')
+    index > 0 && matches!(value[index.cast_unsigned()], b' ' | b'\t' | b'\n')
 }
 
 #[cfg(test)]
@@ -49,6 +60,7 @@ fn test_already_has_whitespace_before_cursor() {
     assert!(already_has_whitespace_before_cursor("  "));
     assert!(already_has_whitespace_before_cursor("foo\n"));
     assert!(already_has_whitespace_before_cursor("foo "));
+    assert!(already_has_whitespace_before_cursor("foo\n\t"));
     assert!(!already_has_whitespace_before_cursor("foo"));
 }
 
@@ -122,13 +134,12 @@ fn test_trim_indent() {
     assert_eq!("\nfoo\n  bar\nbaz\n", &trim_indent("\n  foo\n    bar\n  baz\n"));
 }
 
-pub fn format_indented_multiline(value: &str, continuation_indent: usize) -> String {
-    let indent = repeat(' ', continuation_indent);
+pub fn format_indented_multiline(value: &str, indent: &str) -> String {
     let mut output = String::new();
     let mut indent_next = false;
     for chr in value.chars() {
         if indent_next && chr != '\n' {
-            output.push_str(&indent);
+            output.push_str(indent);
             indent_next = false;
         }
 
@@ -143,13 +154,66 @@ pub fn format_indented_multiline(value: &str, continuation_indent: usize) -> Str
 #[cfg(test)]
 #[test]
 fn test_format_indented_multiline() {
-    assert_eq!("foo", &format_indented_multiline("foo", 3));
-    assert_eq!("foo\n   bar", &format_indented_multiline("foo\nbar", 3));
-    assert_eq!("foo\n\n\n   bar", &format_indented_multiline("foo\n\n\nbar", 3));
+    assert_eq!("foo", &format_indented_multiline("foo", "   "));
+    assert_eq!("foo\n   bar", &format_indented_multiline("foo\nbar", "   "));
+    assert_eq!("foo\n\n\n   bar", &format_indented_multiline("foo\n\n\nbar", "   "));
     assert_eq!(
         "foo\n   bar\n     baz\n   bin",
-        &format_indented_multiline("foo\nbar\n  baz\nbin", 3)
+        &format_indented_multiline("foo\nbar\n  baz\nbin", "   ")
+    );
+    assert_eq!("foo\n\t bar", &format_indented_multiline("foo\nbar", "\t "));
+}
+
+/// Normalizes the contents of a docstring. Text on the line with the opening quotes is kept there,
+/// the remaining lines have their common indentation replaced with `indent` spaces, trailing whitespace is
+/// trimmed, and leading, trailing and repeated blank lines are dropped so paragraphs are separated by exactly
+/// one blank line. If the closing quotes were on their own line, the result ends with a newline.
+pub fn format_docstring(value: &str, indent: &str) -> String {
+    let mut lines = value.lines().map(str::trim_end);
+    let first = lines.next().unwrap_or_default().trim_start();
+    let rest: Vec<&str> = lines.collect();
+    let min_indent = rest
+        .iter()
+        .filter(|line| !line.is_empty())
+        .map(|line| indent_len(line))
+        .min()
+        .unwrap_or(0);
+
+    let mut output = first.to_string();
+    let mut has_text = !first.is_empty();
+    let mut pending_blank = false;
+    for line in rest {
+        if line.is_empty() {
+            pending_blank = has_text;
+            continue;
+        }
+        output.push('\n');
+        if pending_blank {
+            output.push('\n');
+            pending_blank = false;
+        }
+        output.push_str(indent);
+        output.push_str(&line[min_indent..]);
+        has_text = true;
+    }
+    let closes_on_own_line = value.rsplit('\n').next().is_some_and(|last| last.trim().is_empty());
+    if value.contains('\n') && closes_on_own_line {
+        output.push('\n');
+    }
+    output
+}
+
+#[cfg(test)]
+#[test]
+fn test_format_docstring() {
+    assert_eq!("Foo.", &format_docstring("Foo.  ", "  "));
+    assert_eq!("Foo.\n  more\n", &format_docstring("Foo.\n      more\n      ", "  "));
+    assert_eq!(
+        "\n  foo\n\n  bar\n    baz\n",
+        &format_docstring("\n\n    foo  \n\n\n    bar\n      baz\n\n  ", "  ")
     );
+    assert_eq!("\n\tfoo\n", &format_docstring("\nfoo\n", "\t"));
+    assert_eq!("", &format_docstring("", "  "));
 }
 
 pub fn last_is_one_of(value: &str, chars: &[char]) -> bool {
@@ -0,0 +1,128 @@
+// Copyright Ion Fusion contributors. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+use fuusak::config::FusionConfig;
+use fuusak::error::Error;
+use fuusak_err_macro::err_generic;
+use std::collections::BTreeMap;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use xxhash_rust::xxh3::Xxh3;
+
+pub const CACHE_FILE_NAME: &str = ".fuusak-cache";
+const CACHE_HEADER: &str = "fuusak-cache v1";
+
+/// Remembers the content hashes of files that are already known to adhere to the configured style,
+/// so that `format-all` and `checkstyle-all` can skip them when they haven't changed since the last run.
+///
+/// Entries are keyed on the effective config of each file and the fuusak version as well as the file's contents,
+/// so changing either means that the file will be processed again.
+pub struct StyleCache {
+    path: Option<PathBuf>,
+    entries: Mutex<BTreeMap<PathBuf, u64>>,
+}
+
+impl StyleCache {
+    /// Loads the cache stored in `directory`. A missing, unreadable, or stale cache results in an empty one.
+    pub fn load(directory: &Path) -> StyleCache {
+        let path = directory.join(CACHE_FILE_NAME);
+        let entries = fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| parse_entries(&contents))
+            .unwrap_or_default();
+        StyleCache {
+            path: Some(path),
+            entries: Mutex::new(entries),
+        }
+    }
+
+    /// Creates a cache that never has any hits and is never saved
+    pub fn disabled() -> StyleCache {
+        StyleCache {
+            path: None,
+            entries: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn is_clean(&self, file_name: &Path, fusion_config: &FusionConfig, contents: &str) -> bool {
+        let entries = self.entries.lock().unwrap();
+        entries.get(file_name) == Some(&hash_entry(fusion_config, contents))
+    }
+
+    pub fn mark_clean(&self, file_name: &Path, fusion_config: &FusionConfig, contents: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.insert(file_name.to_path_buf(), hash_entry(fusion_config, contents));
+    }
+
+    pub fn mark_dirty(&self, file_name: &Path) {
+        self.entries.lock().unwrap().remove(file_name);
+    }
+
+    pub fn save(&self) -> Result<(), Error> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let mut output = format!("{CACHE_HEADER}\n");
+        for (file_name, hash) in self.entries.lock().unwrap().iter() {
+            output.push_str(&format!("{hash:016x} {}\n", file_name.display()));
+        }
+        fs::write(path, output).map_err(|err| err_generic!("Failed to write cache {}: {}", path.display(), err))
+    }
+}
+
+fn hash_entry(fusion_config: &FusionConfig, contents: &str) -> u64 {
+    let mut hasher = Xxh3::new();
+    env!("CARGO_PKG_VERSION").hash(&mut hasher);
+    fusion_config.hash_style(&mut hasher);
+    contents.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn parse_entries(contents: &str) -> Option<BTreeMap<PathBuf, u64>> {
+    let mut lines = contents.lines();
+    if lines.next()? != CACHE_HEADER {
+        return None;
+    }
+    lines
+        .map(|line| {
+            let (hash, file_name) = line.split_once(' ')?;
+            Some((PathBuf::from(file_name), u64::from_str_radix(hash, 16).ok()?))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fuusak::config::new_default_config;
+    use tempfile::TempDir;
+
+    #[test]
+    fn round_trip() {
+        let temp_dir = TempDir::new().unwrap();
+        let directory = temp_dir.path();
+        let config = new_default_config();
+
+        let cache = StyleCache::load(directory);
+        assert!(!cache.is_clean(Path::new("a.fusion"), &config, "(foo)"));
+        cache.mark_clean(Path::new("a.fusion"), &config, "(foo)");
+        cache.mark_clean(Path::new("b c.fusion"), &config, "(bar)");
+        cache.save().unwrap();
+
+        let cache = StyleCache::load(directory);
+        assert!(cache.is_clean(Path::new("a.fusion"), &config, "(foo)"));
+        assert!(cache.is_clean(Path::new("b c.fusion"), &config, "(bar)"));
+        assert!(!cache.is_clean(Path::new("a.fusion"), &config, "(foo )"));
+
+        let mut changed_config = new_default_config();
+        changed_config.indent_width = 4;
+        assert!(!cache.is_clean(Path::new("a.fusion"), &changed_config, "(foo)"));
+
+        // Settings that don't affect formatting don't invalidate the cache
+        let mut changed_package = new_default_config();
+        changed_package.package.module_roots.push("other".into());
+        changed_package.exclude.push("*.tmp".into());
+        assert!(cache.is_clean(Path::new("a.fusion"), &changed_package, "(foo)"));
+    }
+}
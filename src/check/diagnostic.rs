@@ -0,0 +1,788 @@
+// Copyright Ion Fusion contributors. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+use crate::index::{
+    FusionIndex, Module, RequireForm, RequireType, Resolution, Resolutions, Script, top_level_definitions,
+};
+use fuusak::ast::Expr;
+use fuusak::file::FusionFile;
+use fuusak::span::ShortSpan;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A problem found in an indexed file, located by the line and column it starts at
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub file_name: PathBuf,
+    pub line: usize,
+    pub column: usize,
+    pub severity: Severity,
+    pub message: String,
+    /// A secondary location in the same file, such as an earlier definition that conflicts with this one
+    pub note: Option<Note>,
+    /// An edit to the file that fixes the problem, if there's an obvious one
+    pub fix: Option<Edit>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Note {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+/// Replaces the text at `span` with `replacement`
+#[derive(Clone, Debug)]
+pub struct Edit {
+    pub span: ShortSpan,
+    pub replacement: String,
+}
+
+impl Edit {
+    /// Removes the text at `span` along with the whitespace that separates it from its neighbors,
+    /// or the whole line if `span` is the only thing on it
+    pub fn removal(contents: &str, span: ShortSpan) -> Edit {
+        let line_start = contents[..span.start].rfind('\n').map_or(0, |index| index + 1);
+        let line_end = contents[span.end..]
+            .find('\n')
+            .map_or(contents.len(), |index| span.end + index + 1);
+        let (start, end) =
+            if contents[line_start..span.start].trim().is_empty() && contents[span.end..line_end].trim().is_empty() {
+                (line_start, line_end)
+            } else {
+                let before = contents[..span.start].trim_end();
+                if before.ends_with(['(', '[']) {
+                    // The first item of a form keeps its opening delimiter, so remove the whitespace after it
+                    let after = &contents[span.end..];
+                    (span.start, span.end + after.len() - after.trim_start().len())
+                } else {
+                    (before.len(), span.end)
+                }
+            };
+        Edit {
+            span: ShortSpan::new(start, end),
+            replacement: String::new(),
+        }
+    }
+}
+
+/// Applies the edits to `contents`, skipping any that overlap an edit that's already applied.
+/// Returns the number of edits applied.
+pub fn apply_edits(contents: &mut String, mut edits: Vec<Edit>) -> usize {
+    // Apply from the end of the file so that earlier spans stay valid
+    edits.sort_by_key(|edit| Reverse(edit.span.start));
+    let mut applied = 0;
+    let mut applied_start = contents.len();
+    for edit in edits {
+        if edit.span.end <= applied_start {
+            contents.replace_range(edit.span.start..edit.span.end, &edit.replacement);
+            applied_start = edit.span.start;
+            applied += 1;
+        }
+    }
+    applied
+}
+
+impl Diagnostic {
+    pub fn new(file: &FusionFile, span: ShortSpan, severity: Severity, message: String) -> Diagnostic {
+        let (line, column) = span.line_col(&file.contents);
+        Diagnostic {
+            file_name: file.file_name.clone(),
+            line,
+            column,
+            severity,
+            message,
+            note: None,
+            fix: None,
+        }
+    }
+
+    pub fn with_note(mut self, file: &FusionFile, span: ShortSpan, message: String) -> Diagnostic {
+        let (line, column) = span.line_col(&file.contents);
+        self.note = Some(Note { line, column, message });
+        self
+    }
+
+    pub fn with_fix(mut self, fix: Edit) -> Diagnostic {
+        self.fix = Some(fix);
+        self
+    }
+
+    /// Makes the file name relative to `path`, if it's within it
+    pub fn relative_to(mut self, path: &Path) -> Diagnostic {
+        if let Ok(relative) = self.file_name.strip_prefix(path) {
+            self.file_name = relative.to_path_buf();
+        }
+        self
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}: {}",
+            self.file_name.display(),
+            self.line,
+            self.column,
+            self.severity,
+            self.message
+        )?;
+        if let Some(note) = &self.note {
+            write!(
+                f,
+                "\n  {}:{}:{}: note: {}",
+                self.file_name.display(),
+                note.line,
+                note.column,
+                note.message
+            )?;
+        }
+        Ok(())
+    }
+}
+
+pub fn module_diagnostics(fusion_index: &FusionIndex, module: &Module) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let language_exists = module_declaration(fusion_index, module, &mut diagnostics);
+    // Without its language, nearly every identifier in a module would be unbound
+    if language_exists {
+        unbound_identifiers(&module.file, &module.resolutions, &mut diagnostics);
+    }
+    duplicate_definitions(module, &mut diagnostics);
+    conflicting_imports(fusion_index, module, &mut diagnostics);
+    duplicate_provides(module, &mut diagnostics);
+    unknown_modules(fusion_index, &module.file, &module.requires, &mut diagnostics);
+    missing_imports(&module.file, &module.requires, &mut diagnostics);
+    unused_requires(module, &mut diagnostics);
+    unused_bindings(&module.file, &module.resolutions, Some(module), &mut diagnostics);
+    diagnostics.sort_by_key(|diagnostic| (diagnostic.line, diagnostic.column));
+    diagnostics
+}
+
+pub fn script_diagnostics(fusion_index: &FusionIndex, script: &Script) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for (index, (file, resolutions)) in script.files.iter().zip(&script.resolutions).enumerate() {
+        let start = diagnostics.len();
+        // A script's requires don't record which of its files they're in, but tests only have one file
+        if index == 0 {
+            unknown_modules(fusion_index, file, &script.requires, &mut diagnostics);
+        }
+        unbound_identifiers(file, resolutions, &mut diagnostics);
+        unused_bindings(file, resolutions, None, &mut diagnostics);
+        diagnostics[start..].sort_by_key(|diagnostic| (diagnostic.line, diagnostic.column));
+    }
+    diagnostics
+}
+
+/// Reports symbols that aren't bound locally, defined in the file, imported, or provided by the language
+fn unbound_identifiers(file: &FusionFile, resolutions: &Resolutions, diagnostics: &mut Vec<Diagnostic>) {
+    for (span, resolution) in resolutions {
+        let name = &file.contents[span.start..span.end];
+        match resolution {
+            Resolution::Unbound => diagnostics.push(Diagnostic::new(
+                file,
+                *span,
+                Severity::Error,
+                format!("unbound identifier `{name}`"),
+            )),
+            // The bindings of unknown modules aren't known, so these might well be bound
+            Resolution::Unknown { modules } => diagnostics.push(Diagnostic::new(
+                file,
+                *span,
+                Severity::Warning,
+                format!("`{name}` isn't bound unless {} provides it", modules.join(" or ")),
+            )),
+            _ => {}
+        }
+    }
+}
+
+/// Checks that a module file has a single top-level `module` form, whose name matches the file's base name
+/// and whose language exists. Returns false if the language doesn't exist.
+fn module_declaration(fusion_index: &FusionIndex, module: &Module, diagnostics: &mut Vec<Diagnostic>) -> bool {
+    let file = &module.file;
+    let forms: Vec<&Expr> = file.ast.iter().filter(|expr| expr.is_value()).collect();
+    let module_form = forms.iter().find_map(|form| {
+        form.sexpr_value()
+            .filter(|sexpr| sexpr.item_iter().next().and_then(Expr::stripped_symbol_value) == Some("module"))
+    });
+    let Some(module_form) = module_form else {
+        diagnostics.push(Diagnostic::new(
+            file,
+            ShortSpan::new(0, 0),
+            Severity::Error,
+            format!("module file for {} has no `module` form", module.name),
+        ));
+        return true;
+    };
+    for form in forms.iter().filter(|form| form.span() != module_form.span) {
+        diagnostics.push(
+            Diagnostic::new(
+                file,
+                form.span(),
+                Severity::Error,
+                "a module file must contain exactly one top-level `module` form".into(),
+            )
+            .with_note(file, module_form.span, "the module is declared here".into()),
+        );
+    }
+
+    let mut items = module_form.item_iter().skip(1);
+    if let Some(name_expr) = items.next()
+        && let Some(declared) = name_expr
+            .stripped_symbol_value()
+            .or_else(|| name_expr.string_value().map(String::as_str))
+    {
+        let expected = module.name.rsplit('/').next().unwrap_or_default();
+        if declared != expected {
+            diagnostics.push(
+                Diagnostic::new(
+                    file,
+                    name_expr.span(),
+                    Severity::Error,
+                    format!("module is declared as `{declared}`, but its file name makes it `{expected}`"),
+                )
+                .with_fix(Edit {
+                    span: name_expr.span(),
+                    // Keep the declaration's form, quoting symbols that aren't identifiers
+                    replacement: match name_expr.string_value() {
+                        Some(_) => format!("\"{}\"", escape(expected, '"')),
+                        None if is_identifier(expected) => expected.to_string(),
+                        None => format!("'{}'", escape(expected, '\'')),
+                    },
+                }),
+            );
+        }
+    }
+    if let Some(language_expr) = items.next()
+        && fusion_index
+            .get_module(&module.language)
+            .is_none_or(|language| fusion_index.is_unknown_module(&language))
+    {
+        diagnostics.push(Diagnostic::new(
+            file,
+            language_expr.span(),
+            Severity::Error,
+            format!("language module {} doesn't exist", module.language),
+        ));
+        return false;
+    }
+    true
+}
+
+/// Returns true if `name` can be written as a symbol without quotes
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|first| first.is_ascii_alphabetic() || first == '_' || first == '$')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+        && !matches!(name, "null" | "true" | "false" | "nan")
+}
+
+/// Escapes backslashes and `quote` for writing `text` between quotes
+fn escape(text: &str, quote: char) -> String {
+    text.chars()
+        .flat_map(|c| match c == '\\' || c == quote {
+            true => vec!['\\', c],
+            false => vec![c],
+        })
+        .collect()
+}
+
+/// Reports top-level definitions of a name that's already defined, or that's imported by a require
+fn duplicate_definitions(module: &Module, diagnostics: &mut Vec<Diagnostic>) {
+    let file = &module.file;
+    let imports: Vec<BTreeMap<String, ShortSpan>> = module.requires.iter().map(|require| require.imports()).collect();
+    let mut defined: BTreeMap<&str, ShortSpan> = BTreeMap::new();
+    for definition in top_level_definitions(&file.ast) {
+        let Some(name) = definition.stripped_symbol_value() else {
+            continue;
+        };
+        if let Some(first) = defined.get(name) {
+            diagnostics.push(
+                Diagnostic::new(
+                    file,
+                    definition.span(),
+                    Severity::Error,
+                    format!("`{name}` is already defined"),
+                )
+                .with_note(file, *first, format!("`{name}` is first defined here")),
+            );
+            continue;
+        }
+        defined.insert(name, definition.span());
+        if let Some((require, _)) = module
+            .requires
+            .iter()
+            .zip(&imports)
+            .find(|(_, imports)| imports.contains_key(name))
+        {
+            let module_name = require.module.name.clone();
+            diagnostics.push(
+                Diagnostic::new(
+                    file,
+                    definition.span(),
+                    Severity::Error,
+                    format!("`{name}` is defined here but also imported from {module_name}"),
+                )
+                .with_note(file, require.span, format!("`{name}` is imported here")),
+            );
+        }
+    }
+}
+
+/// Reports names that two requires import from different modules. Modules from the binding catalog are skipped,
+/// since their bindings can't be told apart from the ones they reexport.
+fn conflicting_imports(fusion_index: &FusionIndex, module: &Module, diagnostics: &mut Vec<Diagnostic>) {
+    let file = &module.file;
+    let mut imported: BTreeMap<String, (String, ShortSpan)> = BTreeMap::new();
+    for require in &module.requires {
+        let required = &require.module;
+        if fusion_index.is_stub_module(required) {
+            continue;
+        }
+        for name in require.imports().into_keys() {
+            match imported.get(&name) {
+                Some((first_module, first_span)) if *first_module != required.name => {
+                    diagnostics.push(
+                        Diagnostic::new(
+                            file,
+                            require.span,
+                            Severity::Error,
+                            format!("`{name}` is imported from both {first_module} and {}", required.name),
+                        )
+                        .with_note(
+                            file,
+                            *first_span,
+                            format!("`{name}` is first imported from {first_module} here"),
+                        ),
+                    );
+                }
+                Some(_) => {}
+                None => {
+                    imported.insert(name, (required.name.clone(), require.span));
+                }
+            }
+        }
+    }
+}
+
+/// Reports names that are provided more than once
+fn duplicate_provides(module: &Module, diagnostics: &mut Vec<Diagnostic>) {
+    let file = &module.file;
+    let mut provided: BTreeMap<&str, ShortSpan> = BTreeMap::new();
+    for origin in &module.provided_names {
+        if let Some(first) = provided.get(origin.name.as_str()) {
+            diagnostics.push(
+                Diagnostic::new(
+                    file,
+                    origin.originates_from,
+                    Severity::Error,
+                    format!("`{}` is provided more than once", origin.name),
+                )
+                .with_note(file, *first, format!("`{}` is first provided here", origin.name)),
+            );
+        } else {
+            provided.insert(&origin.name, origin.originates_from);
+        }
+    }
+}
+
+/// Reports requires of modules that aren't in the module paths or the binding catalog. Those that must be part of
+/// the package are errors, while others might come from somewhere that isn't configured.
+fn unknown_modules(
+    fusion_index: &FusionIndex,
+    file: &FusionFile,
+    requires: &[RequireForm],
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    for require in requires {
+        if fusion_index.is_missing_module(&require.module) {
+            diagnostics.push(Diagnostic::new(
+                file,
+                require.span,
+                Severity::Error,
+                format!("module {} not found in the module paths", require.module.name),
+            ));
+        } else if fusion_index.is_unknown_module(&require.module) {
+            diagnostics.push(Diagnostic::new(
+                file,
+                require.span,
+                Severity::Warning,
+                format!(
+                    "module {} isn't in the module paths or the binding catalog, so its bindings are unknown",
+                    require.module.name
+                ),
+            ));
+        }
+    }
+}
+
+/// Reports names that requires list explicitly but that their modules don't provide
+fn missing_imports(file: &FusionFile, requires: &[RequireForm], diagnostics: &mut Vec<Diagnostic>) {
+    for require in requires {
+        let module_name = require.module.name.clone();
+        for (name, span) in require.missing_names() {
+            diagnostics.push(Diagnostic::new(
+                file,
+                span,
+                Severity::Error,
+                format!("`{name}` is not provided by {module_name}"),
+            ));
+        }
+    }
+}
+
+/// Reports requires that none of the module's references resolve through, and names listed in `only_in`
+/// that are never used. Each is fixed by removing the unused spec or name.
+fn unused_requires(module: &Module, diagnostics: &mut Vec<Diagnostic>) {
+    let file = &module.file;
+    for (index, require) in module.requires.iter().enumerate() {
+        let used_origins: BTreeSet<ShortSpan> = module
+            .resolutions
+            .values()
+            .filter_map(|resolution| match resolution {
+                Resolution::Imported {
+                    origin,
+                    require: Some(require),
+                    ..
+                } if *require == index => Some(*origin),
+                _ => None,
+            })
+            .collect();
+        let module_name = require.module.name.clone();
+        // An unknown module might provide the names that aren't known to come from anywhere else
+        let maybe_used = require.module.is_unknown()
+            && module.resolutions.values().any(
+                |resolution| matches!(resolution, Resolution::Unknown { modules } if modules.contains(&module_name)),
+            );
+        if maybe_used {
+            continue;
+        }
+        // Names that the module doesn't provide are already reported as errors
+        let missing: BTreeSet<ShortSpan> = require.missing_names().into_iter().map(|(_, span)| span).collect();
+        if used_origins.is_empty() && missing.is_empty() {
+            // Remove the whole `(require ...)` form when this is its only spec
+            let is_only_spec = module
+                .requires
+                .iter()
+                .filter(|other| other.form_span == require.form_span)
+                .count()
+                == 1;
+            let removed = if is_only_spec { require.form_span } else { require.span };
+            diagnostics.push(
+                Diagnostic::new(
+                    file,
+                    require.span,
+                    Severity::Warning,
+                    format!("unused require of {module_name}"),
+                )
+                .with_fix(Edit::removal(&file.contents, removed)),
+            );
+        } else if let RequireType::Names(_, names) = &require.required {
+            for name in names.iter().filter(|name| {
+                !used_origins.contains(&name.originates_from) && !missing.contains(&name.originates_from)
+            }) {
+                diagnostics.push(
+                    Diagnostic::new(
+                        file,
+                        name.originates_from,
+                        Severity::Warning,
+                        format!("`{}` is required from {module_name} but never used", name.name),
+                    )
+                    .with_fix(Edit::removal(&file.contents, name.originates_from)),
+                );
+            }
+        }
+    }
+}
+
+/// Reports parameters and local bindings that are never referenced, unless their names start with `_`.
+/// For modules, definitions that are neither referenced nor provided are reported as well.
+fn unused_bindings(
+    file: &FusionFile,
+    resolutions: &Resolutions,
+    module: Option<&Module>,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let referenced: BTreeSet<ShortSpan> = resolutions
+        .iter()
+        .filter_map(|(span, resolution)| match resolution {
+            Resolution::Local(binding) | Resolution::Module(binding) if binding != span => Some(*binding),
+            _ => None,
+        })
+        .collect();
+    for (span, resolution) in resolutions {
+        let name = &file.contents[span.start..span.end];
+        if referenced.contains(span) || name.starts_with('_') {
+            continue;
+        }
+        match resolution {
+            Resolution::Local(binding) if binding == span => {
+                diagnostics.push(Diagnostic::new(
+                    file,
+                    *span,
+                    Severity::Warning,
+                    format!("unused local binding `{name}`"),
+                ));
+            }
+            Resolution::Module(binding) if binding == span => {
+                let Some(module) = module else { continue };
+                if !module.provides.values().any(|origin| origin == span) {
+                    diagnostics.push(Diagnostic::new(
+                        file,
+                        *span,
+                        Severity::Warning,
+                        format!("`{name}` is defined but never used or provided"),
+                    ));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::index::{Catalog, FusionIndex, FusionLoader};
+    use fuusak::config::new_default_config;
+
+    #[test]
+    fn unbound_identifier_diagnostics() {
+        let config = new_default_config();
+        let fusion_index = FusionIndex::new(
+            &PathBuf::from("./"),
+            vec![PathBuf::from("index_tests/unbound/test_files")],
+            Catalog::bundled(),
+        )
+        .unwrap();
+        let fusion_loader = FusionLoader::new(&config, &fusion_index);
+        let package_path = PathBuf::from("./").canonicalize().unwrap();
+        let diagnostics = |file_name: &str| -> Vec<String> {
+            let module = fusion_loader
+                .load_module_file(file_name)
+                .unwrap_or_else(|err| panic!("\n{err}"));
+            module_diagnostics(&fusion_index.read(), &module)
+                .into_iter()
+                .map(|diagnostic| diagnostic.relative_to(&package_path).to_string())
+                .collect()
+        };
+        // The binding catalog lists everything that /fusion provides, so misspelled names are unbound
+        assert_eq!(
+            vec![
+                "index_tests/unbound/test_files/main.fusion:7:7: error: unbound identifier `dispaly`",
+                "index_tests/unbound/test_files/main.fusion:9:40: error: unbound identifier `not_provided`",
+            ],
+            diagnostics("index_tests/unbound/test_files/main.fusion")
+        );
+        assert_eq!(
+            vec![
+                "index_tests/unbound/test_files/misspelled.fusion:2:31: error: `string_apend` is not provided by \
+                 /fusion",
+                "index_tests/unbound/test_files/misspelled.fusion:3:15: error: unbound identifier `string_apend`",
+            ],
+            diagnostics("index_tests/unbound/test_files/misspelled.fusion")
+        );
+        // A language from the package provides exactly what it lists, so everything else is unbound
+        assert_eq!(
+            vec![
+                "index_tests/unbound/test_files/strict.fusion:1:2: error: unbound identifier `module`",
+                "index_tests/unbound/test_files/strict.fusion:2:10: error: unbound identifier `gret`",
+            ],
+            diagnostics("index_tests/unbound/test_files/strict.fusion")
+        );
+    }
+
+    #[test]
+    fn unknown_module_diagnostics() {
+        let config = new_default_config();
+        let fusion_index = FusionIndex::new(
+            &PathBuf::from("./"),
+            vec![PathBuf::from("index_tests/dependents/test_files")],
+            Catalog::bundled(),
+        )
+        .unwrap();
+        let fusion_loader = FusionLoader::new(&config, &fusion_index);
+        let module = fusion_loader
+            .load_module("/orphan")
+            .unwrap_or_else(|err| panic!("\n{err}"));
+        let package_path = PathBuf::from("./").canonicalize().unwrap();
+        let diagnostics: Vec<String> = module_diagnostics(&fusion_index.read(), &module)
+            .into_iter()
+            .map(|diagnostic| diagnostic.relative_to(&package_path).to_string())
+            .collect();
+        assert_eq!(
+            vec![
+                "index_tests/dependents/test_files/orphan.fusion:2:12: warning: unused require of /mid",
+                "index_tests/dependents/test_files/orphan.fusion:3:12: error: module /removed not found in the module \
+                 paths",
+                "index_tests/dependents/test_files/orphan.fusion:3:12: warning: unused require of /removed",
+                "index_tests/dependents/test_files/orphan.fusion:4:12: warning: module /elsewhere/lib isn't in the \
+                 module paths or the binding catalog, so its bindings are unknown",
+                "index_tests/dependents/test_files/orphan.fusion:4:12: warning: unused require of /elsewhere/lib",
+            ],
+            diagnostics
+        );
+    }
+
+    #[test]
+    fn unused_warnings_and_fixes() {
+        let config = new_default_config();
+        let fusion_index = FusionIndex::new(
+            &PathBuf::from("./"),
+            vec![PathBuf::from("index_tests/unused/test_files")],
+            Catalog::bundled(),
+        )
+        .unwrap();
+        let fusion_loader = FusionLoader::new(&config, &fusion_index);
+        let module = fusion_loader
+            .load_module_file("index_tests/unused/test_files/main.fusion")
+            .unwrap_or_else(|err| panic!("\n{err}"));
+        let diagnostics = module_diagnostics(&fusion_index.read(), &module);
+        let messages: Vec<String> = diagnostics
+            .iter()
+            .map(|diagnostic| format!("{}:{}: {}", diagnostic.line, diagnostic.column, diagnostic.message))
+            .collect();
+        assert_eq!(
+            vec![
+                "2:30: `b` is required from /lib but never used",
+                "3:12: unused require of /other",
+                "5:20: unused local binding `x`",
+                "6:12: `helper` is defined but never used or provided",
+                "6:29: unused local binding `unused`",
+                "8:38: unused local binding `m`",
+            ],
+            messages
+        );
+
+        let mut contents = module.file.contents.clone();
+        let fixes: Vec<Edit> = diagnostics
+            .into_iter()
+            .filter_map(|diagnostic| diagnostic.fix)
+            .collect();
+        assert_eq!(2, apply_edits(&mut contents, fixes));
+        assert!(
+            contents.starts_with("(module main \"/fusion\"\n  (require (only_in \"/lib\" a))\n\n  (define"),
+            "{contents}"
+        );
+    }
+
+    #[test]
+    fn conflicting_definitions() {
+        let config = new_default_config();
+        let fusion_index = FusionIndex::new(
+            &PathBuf::from("./"),
+            vec![PathBuf::from("index_tests/conflicts/test_files")],
+            Catalog::bundled(),
+        )
+        .unwrap();
+        let fusion_loader = FusionLoader::new(&config, &fusion_index);
+        let module = fusion_loader
+            .load_module_file("index_tests/conflicts/test_files/main.fusion")
+            .unwrap_or_else(|err| panic!("\n{err}"));
+        let package_path = PathBuf::from("./").canonicalize().unwrap();
+        let diagnostics: Vec<String> = module_diagnostics(&fusion_index.read(), &module)
+            .into_iter()
+            .filter(|diagnostic| diagnostic.severity == Severity::Error)
+            .map(|diagnostic| diagnostic.relative_to(&package_path).to_string())
+            .collect();
+        let file = "index_tests/conflicts/test_files/main.fusion";
+        assert_eq!(
+            vec![
+                format!(
+                    "{file}:3:12: error: `shared` is imported from both /one and /two\n  \
+                     {file}:2:12: note: `shared` is first imported from /one here"
+                ),
+                format!("{file}:5:12: error: `f` is already defined\n  {file}:4:12: note: `f` is first defined here"),
+                format!(
+                    "{file}:6:11: error: `x` is defined here but also imported from /one\n  \
+                     {file}:2:12: note: `x` is imported here"
+                ),
+                format!(
+                    "{file}:7:14: error: `f` is provided more than once\n  {file}:7:12: note: `f` is first provided here"
+                ),
+            ],
+            diagnostics
+        );
+    }
+
+    #[test]
+    fn module_declarations() {
+        let config = new_default_config();
+        let fusion_index = FusionIndex::new(
+            &PathBuf::from("./"),
+            vec![PathBuf::from("index_tests/declaration/test_files")],
+            Catalog::bundled(),
+        )
+        .unwrap();
+        let fusion_loader = FusionLoader::new(&config, &fusion_index);
+        let diagnostics = |name: &str| {
+            let module = fusion_loader.load_module(name).unwrap_or_else(|err| panic!("\n{err}"));
+            module_diagnostics(&fusion_index.read(), &module)
+        };
+
+        let renamed = diagnostics("/renamed");
+        assert_eq!(1, renamed.len());
+        assert_eq!(
+            "module is declared as `old_name`, but its file name makes it `renamed`",
+            renamed[0].message
+        );
+        let fixed = |name: &str| {
+            let fixes = diagnostics(name)
+                .into_iter()
+                .filter_map(|diagnostic| diagnostic.fix)
+                .collect();
+            let mut contents = fusion_index
+                .read()
+                .get_module(&name.into())
+                .unwrap()
+                .file
+                .contents
+                .clone();
+            apply_edits(&mut contents, fixes);
+            contents
+        };
+        assert!(fixed("/renamed").starts_with("(module renamed \"/fusion\"\n"));
+        // Names that aren't identifiers must be quoted, and string names stay strings
+        assert!(fixed("/my-mod").starts_with("(module 'my-mod' \"/fusion\"\n"));
+        assert!(fixed("/string_name").starts_with("(module \"string_name\" \"/fusion\"\n"));
+
+        // Identifiers aren't reported as unbound when the language is missing
+        let no_language: Vec<String> = diagnostics("/no_language")
+            .iter()
+            .map(|diagnostic| format!("{}:{}: {}", diagnostic.line, diagnostic.column, diagnostic.message))
+            .collect();
+        assert_eq!(
+            vec!["1:21: language module /missing/language doesn't exist"],
+            no_language
+        );
+
+        let two_forms: Vec<String> = diagnostics("/two_forms")
+            .iter()
+            .map(|diagnostic| format!("{}:{}: {}", diagnostic.line, diagnostic.column, diagnostic.message))
+            .collect();
+        assert_eq!(
+            vec!["2:1: a module file must contain exactly one top-level `module` form"],
+            two_forms
+        );
+    }
+}
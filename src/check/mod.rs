@@ -1,33 +1,42 @@
 // Copyright Ion Fusion contributors. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
-use crate::index::{self, FusionIndexCell, FusionLoader};
+use crate::index::{self, Dependent, FusionIndexCell, FusionLoader, ModuleCell, ScriptCell};
 use colorful::{Color, Colorful};
 use fuusak::config::FusionConfig;
 use fuusak::error::Error;
+use fuusak::file::find_files;
 use fuusak_err_macro::err_generic;
 use notify_debouncer_full::{
     Debouncer, FileIdCache, new_debouncer,
     notify::{
         EventKind, RecursiveMode, Watcher,
-        event::{DataChange, ModifyKind},
+        event::{ModifyKind, RenameMode},
     },
 };
 use rand::distr::{Distribution, Uniform};
-use std::collections::{HashMap, HashSet, hash_map::Entry};
-use std::env;
+use rayon::prelude::*;
+use std::collections::{BTreeMap, HashMap, HashSet, hash_map::Entry};
+use std::fs;
 use std::path::{Path, PathBuf};
 use std::sync::mpsc::channel;
 use std::time::Duration;
 
-pub fn check_correctness_watch(fusion_config: &FusionConfig) -> Result<bool, Error> {
+mod diagnostic;
+
+pub use diagnostic::*;
+
+pub fn check_correctness_watch(fusion_config: &FusionConfig, use_cache: bool) -> Result<bool, Error> {
     // Start by indexing the entire package
-    let current_package_path =
-        env::current_dir().map_err(|err| err_generic!("failed to determine current working directory: {}", err))?;
-    let fusion_index = index::load_index(fusion_config, &current_package_path)?;
+    let current_package_path = fusion_config.package.directory()?;
+    let fusion_index = index::load_index(fusion_config, &current_package_path, use_cache, false, false)?;
 
     // Now set up a file watcher on the directories relevant to this package
     let watch_paths = build_watch_paths(&current_package_path, fusion_config);
     let file_references = build_references(&current_package_path, &fusion_index, &watch_paths);
+    print_diagnostics(
+        &current_package_path,
+        &package_diagnostics(&current_package_path, &fusion_index, &watch_paths),
+    );
 
     let (tx, rx) = channel();
     let mut debouncer = new_debouncer(Duration::from_millis(50), None, tx)
@@ -37,6 +46,8 @@ pub fn check_correctness_watch(fusion_config: &FusionConfig) -> Result<bool, Err
         watch_path(&mut debouncer, path)?;
     }
 
+    let mut package_watch = PackageWatch::new(fusion_config, current_package_path, fusion_index, file_references);
+
     // Watch for file system changes
     println!("Successfully indexed all resources used by this package. Watching for filesystem changes now...\n");
     loop {
@@ -46,40 +57,29 @@ pub fn check_correctness_watch(fusion_config: &FusionConfig) -> Result<bool, Err
         {
             Ok(events) => {
                 for event in events {
+                    // Events were dropped, so the index can't be trusted anymore
+                    if event.need_rescan() {
+                        println!("Missed some file system events. Restarting check-correctness-watch...");
+                        return Ok(true);
+                    }
+                    let Some(path) = event.paths.first() else {
+                        continue;
+                    };
                     match event.kind {
-                        EventKind::Modify(ModifyKind::Data(DataChange::Content)) => {
-                            let path = event.paths.first().expect("a changed file path to be present");
-
-                            // If the file is referenced by the index and is relevant to this package
-                            if let Some(reference) = file_references.get(path) {
-                                let fusion_loader = FusionLoader::new(fusion_config, &fusion_index);
-                                match reference {
-                                    // If it's a module file, reload it
-                                    Reference::Module(name) => {
-                                        match fusion_loader.reload_module_file(name.into(), path) {
-                                            Ok(_) => youre_awesome(),
-                                            Err(err) => error_occurred(&current_package_path, path, &err),
-                                        }
-                                    }
-                                    // If it's referenced by a bunch of scripts, reload all of them
-                                    Reference::Scripts(names) => reload_scripts(&fusion_index, &fusion_loader, names),
-                                }
-                            } else {
-                                println!("Ignoring change to {}", path.display());
+                        EventKind::Modify(ModifyKind::Data(_)) => package_watch.file_changed(path),
+                        EventKind::Create(_) => package_watch.file_created(path),
+                        EventKind::Remove(_) => package_watch.file_removed(path),
+                        EventKind::Modify(ModifyKind::Name(mode)) => match (mode, event.paths.get(1)) {
+                            (RenameMode::Both, Some(to)) => {
+                                package_watch.file_removed(path);
+                                package_watch.file_created(to);
                             }
-                        }
-                        EventKind::Modify(ModifyKind::Name(_)) => {
-                            println!(
-                                "Proper handling of file renames is unimplemented. Restarting check-correctness-watch..."
-                            );
-                            return Ok(true);
-                        }
-                        EventKind::Remove(_) => {
-                            println!(
-                                "Proper handling of file deletions is unimplemented. Restarting check-correctness-watch..."
-                            );
-                            return Ok(true);
-                        }
+                            (RenameMode::From, _) => package_watch.file_removed(path),
+                            (RenameMode::To, _) => package_watch.file_created(path),
+                            // Without more to go on, whether the path still exists tells which side of a rename it is
+                            _ if path.exists() => package_watch.file_created(path),
+                            _ => package_watch.file_removed(path),
+                        },
                         _ => {}
                     }
                 }
@@ -94,38 +94,352 @@ pub fn check_correctness_watch(fusion_config: &FusionConfig) -> Result<bool, Err
     }
 }
 
-fn reload_scripts(fusion_index: &FusionIndexCell, fusion_loader: &FusionLoader<'_>, names: &HashSet<String>) {
+/// Keeps the index of the watched package up to date as its files are changed, created, renamed and removed
+struct PackageWatch<'a> {
+    fusion_config: &'a FusionConfig,
+    package_path: PathBuf,
+    module_roots: Vec<PathBuf>,
+    test_roots: Vec<PathBuf>,
+    fusion_index: FusionIndexCell,
+    file_references: HashMap<PathBuf, Reference>,
+}
+
+impl PackageWatch<'_> {
+    fn new(
+        fusion_config: &FusionConfig,
+        package_path: PathBuf,
+        fusion_index: FusionIndexCell,
+        file_references: HashMap<PathBuf, Reference>,
+    ) -> PackageWatch<'_> {
+        let package = &fusion_config.package;
+        PackageWatch {
+            fusion_config,
+            module_roots: package.module_roots.iter().map(|p| package_path.join(p)).collect(),
+            test_roots: package.test_roots.iter().map(|p| package_path.join(p)).collect(),
+            package_path,
+            fusion_index,
+            file_references,
+        }
+    }
+
+    fn file_changed(&mut self, path: &Path) {
+        let fusion_loader = FusionLoader::new(self.fusion_config, &self.fusion_index);
+        match self.file_references.get(path) {
+            // If it's a module file, reload it along with everything that depends on it
+            Some(Reference::Module(name)) => {
+                reload_module(&self.package_path, &self.fusion_index, &fusion_loader, name, path)
+            }
+            // If it's referenced by a bunch of scripts, reload all of them
+            Some(Reference::Scripts(names)) => {
+                reload_scripts(&self.package_path, &self.fusion_index, &fusion_loader, names)
+            }
+            // A file that failed to load when it was created gets another chance
+            None if self.is_package_file(path) => self.file_created(path),
+            None => println!("Ignoring change to {}", path.display()),
+        }
+    }
+
+    fn file_created(&mut self, path: &Path) {
+        // A directory that's created or renamed brings along every file in it
+        if path.is_dir() {
+            match find_files(path, ".fusion") {
+                Ok(files) => files.iter().for_each(|file| self.file_created(file)),
+                Err(err) => error_occurred(&self.package_path, path, &err),
+            }
+            return;
+        }
+        if self.file_references.contains_key(path) {
+            return self.file_changed(path);
+        }
+        if !self.is_package_file(path) {
+            return;
+        }
+        let fusion_loader = FusionLoader::new(self.fusion_config, &self.fusion_index);
+        if self.module_roots.iter().any(|root| path.starts_with(root)) {
+            // Modules that required it before it existed can now be checked against it
+            match fusion_loader.load_module_file(path) {
+                Ok(module) => {
+                    let name = module.name.clone();
+                    let diagnostics = module_diagnostics(&self.fusion_index.read(), &module);
+                    self.file_references
+                        .insert(path.to_path_buf(), Reference::Module(name.clone()));
+                    recheck_dependents(
+                        &self.package_path,
+                        &self.fusion_index,
+                        &fusion_loader,
+                        &name,
+                        diagnostics,
+                    );
+                }
+                Err(err) => error_occurred(&self.package_path, path, &err),
+            }
+        } else {
+            match fusion_loader.load_test_file(path) {
+                Ok(script) => {
+                    self.file_references.insert(
+                        path.to_path_buf(),
+                        Reference::Scripts(HashSet::from([script.name.clone()])),
+                    );
+                    if print_diagnostics(
+                        &self.package_path,
+                        &script_diagnostics(&self.fusion_index.read(), &script),
+                    ) {
+                        youre_awesome();
+                    }
+                }
+                Err(err) => error_occurred(&self.package_path, path, &err),
+            }
+        }
+    }
+
+    fn file_removed(&mut self, path: &Path) {
+        // A directory that's removed or renamed can't be told from a file anymore, but its files are under its path
+        let removed_files: Vec<PathBuf> = self
+            .file_references
+            .keys()
+            .filter(|file| file.starts_with(path))
+            .cloned()
+            .collect();
+        let mut removed_modules = Vec::new();
+        for file in removed_files {
+            match self.file_references.remove(&file) {
+                Some(Reference::Module(name)) => {
+                    self.fusion_index.write().remove_module(&name);
+                    println!("Removed module: {name}");
+                    removed_modules.push(name);
+                }
+                Some(Reference::Scripts(names)) => {
+                    for name in names {
+                        self.fusion_index.write().remove_script(&name);
+                        println!("Removed test: {name}");
+                    }
+                }
+                None => {}
+            }
+        }
+        // Everything that depends on a removed module is rechecked, so that its requires get reported. This happens
+        // once everything is removed, so that modules removed along with it aren't rechecked.
+        let fusion_loader = FusionLoader::new(self.fusion_config, &self.fusion_index);
+        for name in removed_modules {
+            recheck_dependents(
+                &self.package_path,
+                &self.fusion_index,
+                &fusion_loader,
+                &name,
+                Vec::new(),
+            );
+        }
+    }
+
+    /// Whether the path is a Fusion file in one of the package's module or test roots
+    fn is_package_file(&self, path: &Path) -> bool {
+        path.is_file()
+            && path.extension().is_some_and(|extension| extension == "fusion")
+            && self
+                .module_roots
+                .iter()
+                .chain(&self.test_roots)
+                .any(|root| path.starts_with(root))
+    }
+}
+
+/// Reloads a changed module, then rechecks everything that depends on it
+fn reload_module(
+    package_path: &Path,
+    fusion_index: &FusionIndexCell,
+    fusion_loader: &FusionLoader<'_>,
+    name: &str,
+    path: &Path,
+) {
+    let module = match fusion_loader.reload_module_file(name.into(), path) {
+        Ok(module) => module,
+        Err(err) => return error_occurred(package_path, path, &err),
+    };
+    let diagnostics = module_diagnostics(&fusion_index.read(), &module);
+    recheck_dependents(package_path, fusion_index, fusion_loader, name, diagnostics);
+}
+
+/// Reloads every module and script that depends on the named module (dependencies first), so that they're
+/// checked against its current provides, then prints their diagnostics along with the given ones
+fn recheck_dependents(
+    package_path: &Path,
+    fusion_index: &FusionIndexCell,
+    fusion_loader: &FusionLoader<'_>,
+    name: &str,
+    mut diagnostics: Vec<Diagnostic>,
+) {
+    let mut success = true;
+    let dependents = fusion_index.read().transitive_dependents(name);
+    for dependent in dependents {
+        match dependent {
+            Dependent::Module(dependent_name) => {
+                let Some(dependent_module) = fusion_index.read().get_module(&dependent_name) else {
+                    continue;
+                };
+                let file_name = package_path.join(&dependent_module.file.file_name);
+                match fusion_loader.reload_module_file(dependent_name, &file_name) {
+                    Ok(module) => diagnostics.extend(module_diagnostics(&fusion_index.read(), &module)),
+                    Err(err) => {
+                        error_occurred(package_path, &file_name, &err);
+                        success = false;
+                    }
+                }
+            }
+            Dependent::Script(script_name) => match reload_script(fusion_index, fusion_loader, &script_name) {
+                Ok(script) => diagnostics.extend(script_diagnostics(&fusion_index.read(), &script)),
+                Err(err) => {
+                    println!("{}\n{}\n", "\nError:".color(Color::Red), err);
+                    success = false;
+                }
+            },
+        }
+    }
+    let clean = print_diagnostics(package_path, &diagnostics);
+    if success && clean {
+        youre_awesome();
+    }
+}
+
+fn reload_scripts(
+    package_path: &Path,
+    fusion_index: &FusionIndexCell,
+    fusion_loader: &FusionLoader<'_>,
+    names: &HashSet<String>,
+) {
     let mut success = true;
+    let mut diagnostics = Vec::new();
     for script_name in names {
-        let (modules, globals, file_names) = {
-            let fusion_index = fusion_index.borrow();
-            let script_cell = fusion_index.get_script(script_name).unwrap();
-            let script = script_cell.borrow();
-            (
-                script.top_level_modules.clone(),
-                script.global_bindings.clone(),
-                script
-                    .files
-                    .iter()
-                    .map(|f| f.file_name.clone())
-                    .collect::<Vec<PathBuf>>(),
-            )
-        };
-        match fusion_loader.load_script(script_name.into(), modules, globals, file_names) {
-            Ok(_) => {}
+        match reload_script(fusion_index, fusion_loader, script_name) {
+            Ok(script) => diagnostics.extend(script_diagnostics(&fusion_index.read(), &script)),
             Err(err) => {
                 println!("{}\n{}\n", "\nError:".color(Color::Red), err);
                 success = false;
                 break;
             }
         }
-        println!("Reloaded {script_name}.");
     }
-    if success {
+    if success && print_diagnostics(package_path, &diagnostics) {
         youre_awesome();
     }
 }
 
+/// Reloads a script from the same files, with the same top-level modules and global bindings
+fn reload_script(
+    fusion_index: &FusionIndexCell,
+    fusion_loader: &FusionLoader<'_>,
+    script_name: &str,
+) -> Result<ScriptCell, Error> {
+    let (modules, globals, file_names) = {
+        let fusion_index = fusion_index.read();
+        let script_cell = fusion_index.get_script(&script_name.to_string()).unwrap();
+        let script = script_cell;
+        (
+            script.top_level_modules.clone(),
+            script.global_bindings.clone(),
+            script
+                .files
+                .iter()
+                .map(|f| f.file_name.clone())
+                .collect::<Vec<PathBuf>>(),
+        )
+    };
+    let script = fusion_loader.load_script(script_name.into(), modules, globals, file_names)?;
+    println!("Reloaded {script_name}.");
+    Ok(script)
+}
+
+/// Checks the modules and scripts whose files are in the watched paths, skipping those from module repositories
+fn package_diagnostics(
+    package_path: &Path,
+    fusion_index: &FusionIndexCell,
+    watch_paths: &[PathBuf],
+) -> Vec<Diagnostic> {
+    let is_watched = |file_name: &Path| {
+        let file_name = package_path.join(file_name);
+        watch_paths.iter().any(|path| file_name.starts_with(path))
+    };
+    // Checking doesn't change the index, so everything is checked in parallel against a snapshot of it
+    let fusion_index = fusion_index.snapshot();
+    let modules: Vec<&ModuleCell> = fusion_index
+        .module_iter()
+        .filter(|module| is_watched(&module.file.file_name))
+        .collect();
+    let scripts: Vec<&ScriptCell> = fusion_index
+        .script_iter()
+        .filter(|script| script.files.iter().any(|file| is_watched(&file.file_name)))
+        .collect();
+    let mut diagnostics: Vec<Diagnostic> = modules
+        .par_iter()
+        .flat_map_iter(|module| module_diagnostics(&fusion_index, module))
+        .collect();
+    diagnostics.par_extend(
+        scripts
+            .par_iter()
+            .flat_map_iter(|script| script_diagnostics(&fusion_index, script)),
+    );
+    diagnostics
+}
+
+/// Prints the diagnostics, returning true if none of them are errors
+fn print_diagnostics(package_path: &Path, diagnostics: &[Diagnostic]) -> bool {
+    if diagnostics.is_empty() {
+        return true;
+    }
+    println!();
+    for diagnostic in diagnostics {
+        let color = match diagnostic.severity {
+            Severity::Error => Color::Red,
+            Severity::Warning => Color::Yellow,
+        };
+        println!(
+            "{}",
+            diagnostic.clone().relative_to(package_path).to_string().color(color)
+        );
+    }
+    println!();
+    diagnostics
+        .iter()
+        .all(|diagnostic| diagnostic.severity != Severity::Error)
+}
+
+/// Checks every module and test script of the package once, printing what's found. If `fix` is set,
+/// the diagnostics that have a fix are fixed instead of printed. Returns true if there were no errors.
+pub fn lint(fusion_config: &FusionConfig, fix: bool, use_cache: bool) -> Result<bool, Error> {
+    let current_package_path = fusion_config.package.directory()?;
+    let fusion_index = index::load_index(fusion_config, &current_package_path, use_cache, false, false)?;
+    let package_paths = build_watch_paths(&current_package_path, fusion_config);
+    let mut diagnostics = package_diagnostics(&current_package_path, &fusion_index, &package_paths);
+    if fix {
+        let (fixable, unfixable) = diagnostics.into_iter().partition(|diagnostic| diagnostic.fix.is_some());
+        apply_fixes(&current_package_path, fixable)?;
+        diagnostics = unfixable;
+    }
+    if diagnostics.is_empty() {
+        println!("No problems found.");
+    }
+    Ok(print_diagnostics(&current_package_path, &diagnostics))
+}
+
+/// Applies the fixes of the diagnostics to their files
+fn apply_fixes(package_path: &Path, diagnostics: Vec<Diagnostic>) -> Result<(), Error> {
+    let mut fixes_by_file: BTreeMap<PathBuf, Vec<Edit>> = BTreeMap::new();
+    for diagnostic in diagnostics {
+        if let Some(fix) = diagnostic.fix {
+            fixes_by_file.entry(diagnostic.file_name).or_default().push(fix);
+        }
+    }
+    for (file_name, fixes) in fixes_by_file {
+        let path = package_path.join(&file_name);
+        let mut contents =
+            fs::read_to_string(&path).map_err(|err| err_generic!("Failed to read {}: {}", path.display(), err))?;
+        let applied = apply_edits(&mut contents, fixes);
+        fs::write(&path, contents).map_err(|err| err_generic!("Failed to write {}: {}", path.display(), err))?;
+        let relative = path.strip_prefix(package_path).unwrap_or(&path);
+        println!("Fixed {applied} problem(s) in {}", relative.display());
+    }
+    Ok(())
+}
+
 const AWESOME_MESSAGES: &[&str] = &[
     "You're awesome!",
     "Wow, that just worked!",
@@ -159,7 +473,7 @@ fn youre_awesome() {
 }
 
 fn error_occurred(package_path: &Path, path: &Path, err: &Error) {
-    let relative = path.strip_prefix(package_path).unwrap();
+    let relative = path.strip_prefix(package_path).unwrap_or(path);
     println!(
         "{}\n{}\n",
         format!("\nError in {}:", relative.display()).color(Color::Red),
@@ -167,9 +481,16 @@ fn error_occurred(package_path: &Path, path: &Path, err: &Error) {
     );
 }
 
-fn build_watch_paths(package_path: &Path, _config: &FusionConfig) -> Vec<PathBuf> {
-    let paths = vec!["fusion/src", "ftst"];
-    paths.into_iter().map(|path| package_path.join(path)).collect()
+/// Returns the package's module and test roots. Module repositories aren't watched, since they're read-only.
+fn build_watch_paths(package_path: &Path, config: &FusionConfig) -> Vec<PathBuf> {
+    let package = &config.package;
+    package
+        .module_roots
+        .iter()
+        .chain(&package.test_roots)
+        .map(|path| package_path.join(path))
+        .filter(|path| path.exists())
+        .collect()
 }
 
 #[derive(Debug)]
@@ -185,16 +506,14 @@ fn build_references(
 ) -> HashMap<PathBuf, Reference> {
     let mut references = HashMap::new();
 
-    for module in fusion_index.borrow().module_iter() {
-        let module = module.borrow();
+    for module in fusion_index.read().module_iter() {
         let file_name = package_path.join(&module.file.file_name);
         if watch_paths.iter().any(|path| file_name.strip_prefix(path).is_ok()) {
             assert!(!references.contains_key(&file_name));
             references.insert(file_name, Reference::Module(module.name.clone()));
         }
     }
-    for script in fusion_index.borrow().script_iter() {
-        let script = script.borrow();
+    for script in fusion_index.read().script_iter() {
         for file in &script.files {
             let file_name = package_path.join(&file.file_name);
             if watch_paths.iter().any(|path| file_name.strip_prefix(path).is_ok()) {
@@ -222,3 +541,80 @@ fn watch_path<T: Watcher, C: FileIdCache>(debouncer: &mut Debouncer<T, C>, path:
         .watch(path, RecursiveMode::Recursive)
         .map_err(|err| err_generic!("Failed to watch {:?}: {}", path, err))
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fuusak::config::new_default_config;
+    use tempfile::TempDir;
+
+    fn errors(fusion_index: &FusionIndexCell, name: &str) -> Vec<String> {
+        let module = fusion_index.read().get_module(&name.into()).unwrap();
+        module_diagnostics(&fusion_index.read(), &module)
+            .into_iter()
+            .filter(|diagnostic| diagnostic.severity == Severity::Error)
+            .map(|diagnostic| diagnostic.message)
+            .collect()
+    }
+
+    #[test]
+    fn directory_events() {
+        let temp_dir = TempDir::new().unwrap();
+        let directory = temp_dir.path().canonicalize().unwrap();
+        let source_path = directory.join("src");
+        fs::create_dir_all(source_path.join("lib")).unwrap();
+        for name in ["a", "b"] {
+            fs::write(
+                source_path.join(format!("lib/{name}.fusion")),
+                format!("(module {name} \"/fusion\"\n  (require \"/fusion/experimental/defpub\")\n  (defpub {name}_value 1))\n"),
+            )
+            .unwrap();
+        }
+        fs::write(
+            source_path.join("user.fusion"),
+            "(module user \"/fusion\"\n  (require \"/lib/a\")\n  (displayln a_value))\n",
+        )
+        .unwrap();
+        let mut config = new_default_config();
+        config.package.module_roots = vec!["src".into()];
+        config.package.test_roots = Vec::new();
+        let fusion_index = index::load_index(&config, &directory, false, true, false).unwrap();
+        let watch_paths = build_watch_paths(&directory, &config);
+        let file_references = build_references(&directory, &fusion_index, &watch_paths);
+        let mut package_watch = PackageWatch::new(&config, directory.clone(), fusion_index.clone(), file_references);
+        let referenced = |package_watch: &PackageWatch<'_>, directory: &str| -> Vec<String> {
+            let mut modules: Vec<String> = package_watch
+                .file_references
+                .iter()
+                .filter(|(path, _)| path.starts_with(source_path.join(directory)))
+                .filter_map(|(_, reference)| match reference {
+                    Reference::Module(name) => Some(name.clone()),
+                    Reference::Scripts(_) => None,
+                })
+                .collect();
+            modules.sort();
+            modules
+        };
+        assert_eq!(vec!["/lib/a", "/lib/b"], referenced(&package_watch, "lib"));
+        assert!(errors(&fusion_index, "/user").is_empty());
+
+        // Renaming a directory removes every module in it, and adds them back under their new names
+        fs::rename(source_path.join("lib"), source_path.join("moved")).unwrap();
+        package_watch.file_removed(&source_path.join("lib"));
+        package_watch.file_created(&source_path.join("moved"));
+        assert!(referenced(&package_watch, "lib").is_empty());
+        assert_eq!(vec!["/moved/a", "/moved/b"], referenced(&package_watch, "moved"));
+        // Its directory is gone, but a module that was removed from the package is still an error to require
+        assert_eq!(
+            vec!["module /lib/a not found in the module paths"],
+            errors(&fusion_index, "/user")
+        );
+
+        fs::rename(source_path.join("moved"), source_path.join("lib")).unwrap();
+        package_watch.file_removed(&source_path.join("moved"));
+        package_watch.file_created(&source_path.join("lib"));
+        assert!(referenced(&package_watch, "moved").is_empty());
+        assert_eq!(vec!["/lib/a", "/lib/b"], referenced(&package_watch, "lib"));
+        assert!(errors(&fusion_index, "/user").is_empty());
+    }
+}
@@ -0,0 +1,470 @@
+// Copyright Ion Fusion contributors. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+use crate::index::FusionIndex;
+use clap::ValueEnum;
+use fuusak::error::Error;
+use fuusak_err_macro::err_generic;
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt::Write;
+
+/// Output formats for the dependency graph
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum GraphFormat {
+    /// Graphviz DOT
+    #[default]
+    Dot,
+    /// Mermaid flowchart
+    Mermaid,
+    /// JSON lists of nodes and edges
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum NodeKind {
+    Module,
+    /// A module that's stubbed from the binding catalog
+    Catalog,
+    Script,
+}
+
+impl NodeKind {
+    fn name(self) -> &'static str {
+        match self {
+            NodeKind::Module => "module",
+            NodeKind::Catalog => "catalog",
+            NodeKind::Script => "script",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum EdgeKind {
+    Require,
+    /// From a module to its language, or from a script to one of its top-level modules
+    Language,
+}
+
+impl EdgeKind {
+    fn name(self) -> &'static str {
+        match self {
+            EdgeKind::Require => "require",
+            EdgeKind::Language => "language",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Edge {
+    from: String,
+    to: String,
+    kind: EdgeKind,
+}
+
+/// The modules and scripts of an index, with an edge from each one to every module that it depends on
+pub struct DependencyGraph {
+    nodes: BTreeMap<String, NodeKind>,
+    edges: BTreeSet<Edge>,
+}
+
+impl DependencyGraph {
+    pub fn new(fusion_index: &FusionIndex) -> DependencyGraph {
+        let mut graph = DependencyGraph {
+            nodes: BTreeMap::new(),
+            edges: BTreeSet::new(),
+        };
+        for module in fusion_index.module_iter() {
+            let kind = match fusion_index.is_stub_module(module) {
+                true => NodeKind::Catalog,
+                false => NodeKind::Module,
+            };
+            graph.nodes.insert(module.name.clone(), kind);
+            if !module.language.is_empty() {
+                graph.add_edge(&module.name, &module.language, EdgeKind::Language);
+            }
+            for require in &module.requires {
+                graph.add_edge(&module.name, &require.module.name, EdgeKind::Require);
+            }
+        }
+        for script in fusion_index.script_iter() {
+            graph.nodes.insert(script.name.clone(), NodeKind::Script);
+            for top_level in &script.top_level_modules {
+                graph.add_edge(&script.name, top_level, EdgeKind::Language);
+            }
+            for require in &script.requires {
+                graph.add_edge(&script.name, &require.module.name, EdgeKind::Require);
+            }
+        }
+        // Languages of modules from the binding catalog aren't indexed until something else needs them
+        for edge in &graph.edges {
+            graph.nodes.entry(edge.to.clone()).or_insert(NodeKind::Catalog);
+        }
+        graph
+    }
+
+    fn add_edge(&mut self, from: &str, to: &str, kind: EdgeKind) {
+        self.edges.insert(Edge {
+            from: from.into(),
+            to: to.into(),
+            kind,
+        });
+    }
+
+    /// Keeps only `root` and what it depends on, or what depends on it if `reverse` is set. If `depth` is given,
+    /// only what's at most that many edges away from `root` is kept.
+    pub fn around(&self, root: &str, reverse: bool, depth: Option<usize>) -> Result<DependencyGraph, Error> {
+        if !self.nodes.contains_key(root) {
+            return Err(err_generic!("{} is not a module or test of this package", root));
+        }
+        let mut distances = BTreeMap::from([(root, 0)]);
+        let mut edges = BTreeSet::new();
+        let mut queue = VecDeque::from([root]);
+        while let Some(node) = queue.pop_front() {
+            let distance = distances[node];
+            if depth.is_some_and(|depth| distance >= depth) {
+                continue;
+            }
+            for edge in &self.edges {
+                let (near, far) = match reverse {
+                    true => (&edge.to, &edge.from),
+                    false => (&edge.from, &edge.to),
+                };
+                if near != node {
+                    continue;
+                }
+                edges.insert(edge.clone());
+                if !distances.contains_key(far.as_str()) {
+                    distances.insert(far, distance + 1);
+                    queue.push_back(far);
+                }
+            }
+        }
+        Ok(DependencyGraph {
+            nodes: distances
+                .into_keys()
+                .map(|name| (name.to_string(), self.nodes[name]))
+                .collect(),
+            edges,
+        })
+    }
+
+    /// Renders the graph, marking the edges that are part of a cycle
+    pub fn render(&self, format: GraphFormat) -> String {
+        let cycle_edges = self.cycle_edges();
+        match format {
+            GraphFormat::Dot => self.render_dot(&cycle_edges),
+            GraphFormat::Mermaid => self.render_mermaid(&cycle_edges),
+            GraphFormat::Json => {
+                let mut output = serde_json::to_string_pretty(&self.render_json(&cycle_edges)).unwrap();
+                output.push('\n');
+                output
+            }
+        }
+    }
+
+    fn render_dot(&self, cycle_edges: &BTreeSet<&Edge>) -> String {
+        let mut output = String::from("digraph dependencies {\n    rankdir=LR;\n    node [shape=box];\n");
+        for (name, kind) in &self.nodes {
+            let attributes = match kind {
+                NodeKind::Module => "",
+                NodeKind::Catalog => " [style=dashed]",
+                NodeKind::Script => " [shape=note]",
+            };
+            writeln!(output, "    {}{attributes};", dot_id(name)).unwrap();
+        }
+        for edge in &self.edges {
+            let mut attributes = Vec::new();
+            if edge.kind == EdgeKind::Language {
+                attributes.push("style=dashed");
+            }
+            if cycle_edges.contains(edge) {
+                attributes.push("color=red");
+            }
+            write!(output, "    {} -> {}", dot_id(&edge.from), dot_id(&edge.to)).unwrap();
+            if !attributes.is_empty() {
+                write!(output, " [{}]", attributes.join(", ")).unwrap();
+            }
+            output.push_str(";\n");
+        }
+        output.push_str("}\n");
+        output
+    }
+
+    fn render_mermaid(&self, cycle_edges: &BTreeSet<&Edge>) -> String {
+        let ids: BTreeMap<&str, String> = self
+            .nodes
+            .keys()
+            .enumerate()
+            .map(|(index, name)| (name.as_str(), format!("n{index}")))
+            .collect();
+        let mut output = String::from("flowchart LR\n");
+        for (name, kind) in &self.nodes {
+            let label = name.replace('"', "#quot;");
+            let node = match kind {
+                NodeKind::Module => format!("[\"{label}\"]"),
+                NodeKind::Catalog => format!("([\"{label}\"])"),
+                NodeKind::Script => format!("[/\"{label}\"/]"),
+            };
+            writeln!(output, "    {}{node}", ids[name.as_str()]).unwrap();
+        }
+        let mut cycle_links = Vec::new();
+        for (index, edge) in self.edges.iter().enumerate() {
+            let arrow = match edge.kind {
+                EdgeKind::Require => "-->",
+                EdgeKind::Language => "-.->",
+            };
+            writeln!(
+                output,
+                "    {} {arrow} {}",
+                ids[edge.from.as_str()],
+                ids[edge.to.as_str()]
+            )
+            .unwrap();
+            if cycle_edges.contains(edge) {
+                cycle_links.push(index.to_string());
+            }
+        }
+        if !cycle_links.is_empty() {
+            writeln!(output, "    linkStyle {} stroke:red", cycle_links.join(",")).unwrap();
+        }
+        output
+    }
+
+    fn render_json(&self, cycle_edges: &BTreeSet<&Edge>) -> Value {
+        let nodes: Vec<Value> = self
+            .nodes
+            .iter()
+            .map(|(name, kind)| json!({ "name": name, "kind": kind.name() }))
+            .collect();
+        let edges: Vec<Value> = self
+            .edges
+            .iter()
+            .map(|edge| {
+                json!({
+                    "from": edge.from,
+                    "to": edge.to,
+                    "kind": edge.kind.name(),
+                    "cycle": cycle_edges.contains(edge),
+                })
+            })
+            .collect();
+        json!({ "nodes": nodes, "edges": edges })
+    }
+
+    /// Finds the edges that are part of a cycle, which are those within a strongly connected component
+    /// (found with Tarjan's algorithm) of more than one node, as well as edges from a node to itself
+    fn cycle_edges(&self) -> BTreeSet<&Edge> {
+        let mut successors: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for edge in &self.edges {
+            successors.entry(&edge.from).or_default().push(&edge.to);
+        }
+        let mut tarjan = Tarjan {
+            successors: &successors,
+            index: BTreeMap::new(),
+            low_link: BTreeMap::new(),
+            stack: Vec::new(),
+            on_stack: BTreeSet::new(),
+            components: BTreeMap::new(),
+            component_sizes: Vec::new(),
+        };
+        for name in self.nodes.keys() {
+            if !tarjan.index.contains_key(name.as_str()) {
+                tarjan.visit(name);
+            }
+        }
+        self.edges
+            .iter()
+            .filter(|edge| {
+                let component = tarjan.components.get(edge.from.as_str());
+                component.is_some()
+                    && component == tarjan.components.get(edge.to.as_str())
+                    && (edge.from == edge.to || tarjan.component_sizes[*component.unwrap()] > 1)
+            })
+            .collect()
+    }
+}
+
+struct Tarjan<'a> {
+    successors: &'a BTreeMap<&'a str, Vec<&'a str>>,
+    index: BTreeMap<&'a str, usize>,
+    low_link: BTreeMap<&'a str, usize>,
+    stack: Vec<&'a str>,
+    on_stack: BTreeSet<&'a str>,
+    /// The component that each node belongs to
+    components: BTreeMap<&'a str, usize>,
+    component_sizes: Vec<usize>,
+}
+
+impl<'a> Tarjan<'a> {
+    fn visit(&mut self, node: &'a str) {
+        let index = self.index.len();
+        self.index.insert(node, index);
+        self.low_link.insert(node, index);
+        self.stack.push(node);
+        self.on_stack.insert(node);
+        for &successor in self.successors.get(node).into_iter().flatten() {
+            if !self.index.contains_key(successor) {
+                self.visit(successor);
+                let low_link = self.low_link[node].min(self.low_link[successor]);
+                self.low_link.insert(node, low_link);
+            } else if self.on_stack.contains(successor) {
+                let low_link = self.low_link[node].min(self.index[successor]);
+                self.low_link.insert(node, low_link);
+            }
+        }
+        if self.low_link[node] == index {
+            let component = self.component_sizes.len();
+            let mut size = 0;
+            while let Some(member) = self.stack.pop() {
+                self.on_stack.remove(member);
+                self.components.insert(member, component);
+                size += 1;
+                if member == node {
+                    break;
+                }
+            }
+            self.component_sizes.push(size);
+        }
+    }
+}
+
+fn dot_id(name: &str) -> String {
+    format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::index::{Catalog, FusionIndex, FusionLoader, load_index};
+    use fuusak::config::new_default_config;
+    use std::path::{Path, PathBuf};
+
+    fn dependents_graph() -> DependencyGraph {
+        let config = new_default_config();
+        let fusion_index = FusionIndex::new(
+            &PathBuf::from("./"),
+            vec![PathBuf::from("index_tests/dependents/test_files")],
+            Catalog::bundled(),
+        )
+        .unwrap();
+        FusionLoader::new(&config, &fusion_index)
+            .load_module("/top")
+            .unwrap_or_else(|err| panic!("\n{err}"));
+        DependencyGraph::new(&fusion_index.read())
+    }
+
+    #[test]
+    fn dot() {
+        let expected = r#"digraph dependencies {
+    rankdir=LR;
+    node [shape=box];
+    "/base";
+    "/fusion" [style=dashed];
+    "/fusion/experimental/defpub" [style=dashed];
+    "/fusion/private/kernel" [style=dashed];
+    "/mid";
+    "/top";
+    "/base" -> "/fusion" [style=dashed];
+    "/base" -> "/fusion/experimental/defpub";
+    "/fusion" -> "/fusion/private/kernel" [style=dashed];
+    "/fusion/experimental/defpub" -> "/fusion" [style=dashed];
+    "/mid" -> "/base";
+    "/mid" -> "/fusion" [style=dashed];
+    "/mid" -> "/fusion/experimental/defpub";
+    "/top" -> "/base";
+    "/top" -> "/fusion" [style=dashed];
+    "/top" -> "/fusion/experimental/defpub";
+    "/top" -> "/mid";
+}
+"#;
+        assert_eq!(expected, dependents_graph().render(GraphFormat::Dot));
+    }
+
+    #[test]
+    fn cyclic_package() {
+        let mut config = new_default_config();
+        config.package.module_roots = vec!["test_files".into()];
+        config.package.test_roots = Vec::new();
+        let fusion_index = load_index(&config, Path::new("index_tests/cycle"), false, true, true)
+            .unwrap_or_else(|err| panic!("\n{err}"));
+        let graph = DependencyGraph::new(&fusion_index.read());
+        let cycle_edges: Vec<(&str, &str)> = graph
+            .cycle_edges()
+            .into_iter()
+            .map(|edge| (edge.from.as_str(), edge.to.as_str()))
+            .collect();
+        assert_eq!(vec![("/a", "/b"), ("/b", "/c"), ("/c", "/a")], cycle_edges);
+        assert!(
+            graph
+                .render(GraphFormat::Dot)
+                .contains("    \"/c\" -> \"/a\" [color=red];\n")
+        );
+        assert!(graph.render(GraphFormat::Dot).contains("    \"/b\" -> \"/fusion\";\n"));
+    }
+
+    #[test]
+    fn filtering() {
+        let graph = dependents_graph();
+        let nodes = |graph: DependencyGraph| graph.nodes.into_keys().collect::<Vec<String>>();
+        assert_eq!(
+            vec!["/base", "/mid", "/top"],
+            nodes(graph.around("/base", true, None).unwrap())
+        );
+        assert_eq!(
+            vec!["/base", "/mid", "/top"],
+            nodes(graph.around("/base", true, Some(1)).unwrap())
+        );
+        assert_eq!(
+            vec!["/base", "/fusion", "/fusion/experimental/defpub", "/mid"],
+            nodes(graph.around("/mid", false, Some(1)).unwrap())
+        );
+        assert_eq!(
+            vec![
+                "/base",
+                "/fusion",
+                "/fusion/experimental/defpub",
+                "/fusion/private/kernel",
+                "/mid"
+            ],
+            nodes(graph.around("/mid", false, None).unwrap())
+        );
+        assert!(graph.around("/nope", false, None).is_err());
+    }
+
+    #[test]
+    fn cycles() {
+        let mut graph = DependencyGraph {
+            nodes: ["/a", "/b", "/c", "/d"]
+                .into_iter()
+                .map(|name| (name.to_string(), NodeKind::Module))
+                .collect(),
+            edges: BTreeSet::new(),
+        };
+        graph.add_edge("/a", "/b", EdgeKind::Require);
+        graph.add_edge("/b", "/c", EdgeKind::Require);
+        graph.add_edge("/c", "/a", EdgeKind::Language);
+        graph.add_edge("/c", "/d", EdgeKind::Require);
+        let expected = r#"flowchart LR
+    n0["/a"]
+    n1["/b"]
+    n2["/c"]
+    n3["/d"]
+    n0 --> n1
+    n1 --> n2
+    n2 -.-> n0
+    n2 --> n3
+    linkStyle 0,1,2 stroke:red
+"#;
+        assert_eq!(expected, graph.render(GraphFormat::Mermaid));
+
+        let json: Value = serde_json::from_str(&graph.render(GraphFormat::Json)).unwrap();
+        let cycle: Vec<bool> = json["edges"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|edge| edge["cycle"].as_bool().unwrap())
+            .collect();
+        assert_eq!(vec![true, true, true, false], cycle);
+        assert_eq!("language", json["edges"][2]["kind"]);
+    }
+}
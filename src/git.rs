@@ -0,0 +1,90 @@
+// Copyright Ion Fusion contributors. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+use fuusak::error::Error;
+use fuusak_err_macro::err_generic;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Finds the files of the git repository that contains `directory` that have been added or modified relative to
+/// `revision`, including uncommitted and untracked (but not ignored) files. Paths are canonical, so that they can
+/// be compared with paths given in any form.
+pub fn changed_files(directory: &Path, revision: &str) -> Result<BTreeSet<PathBuf>, Error> {
+    let output = run_git(directory, &["rev-parse", "--show-toplevel"])?;
+    let root = PathBuf::from(output.trim_end_matches('\n'));
+    let mut changed = BTreeSet::new();
+    for args in [
+        &["diff", "--name-only", "-z", "--diff-filter=d", revision, "--"][..],
+        &["ls-files", "-z", "--others", "--exclude-standard"],
+    ] {
+        // Paths are NUL-separated, since git otherwise quotes paths with unusual characters
+        for path in run_git(&root, args)?.split('\0').filter(|path| !path.is_empty()) {
+            let path = root
+                .join(path)
+                .canonicalize()
+                .map_err(|err| err_generic!("Failed to resolve {}: {}", path, err))?;
+            changed.insert(path);
+        }
+    }
+    Ok(changed)
+}
+
+/// Runs git in `directory`, returning its output
+fn run_git(directory: &Path, args: &[&str]) -> Result<String, Error> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(directory)
+        .output()
+        .map_err(|err| err_generic!("Failed to run git: {}", err))?;
+    if !output.status.success() {
+        return Err(err_generic!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn git(directory: &Path, args: &[&str]) {
+        let status = Command::new("git")
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(directory)
+            .status()
+            .unwrap();
+        assert!(status.success(), "git {args:?} failed");
+    }
+
+    #[test]
+    fn unusual_paths() {
+        let temp_dir = TempDir::new().unwrap();
+        let directory = temp_dir.path();
+        fs::create_dir(directory.join("sub")).unwrap();
+        for name in ["top.fusion", "sub/änderung.fusion"] {
+            fs::write(directory.join(name), "(a)\n").unwrap();
+        }
+        git(directory, &["init", "-q"]);
+        git(directory, &["add", "."]);
+        git(directory, &["commit", "-q", "-m", "initial"]);
+        fs::write(directory.join("top.fusion"), "(b)\n").unwrap();
+        fs::write(directory.join("sub/änderung.fusion"), "(b)\n").unwrap();
+        fs::write(directory.join("sub/neu fichier.fusion"), "(c)\n").unwrap();
+
+        let root = directory.canonicalize().unwrap();
+        let expected = BTreeSet::from([
+            root.join("sub/neu fichier.fusion"),
+            root.join("sub/änderung.fusion"),
+            root.join("top.fusion"),
+        ]);
+        assert_eq!(expected, changed_files(directory, "HEAD").unwrap());
+        // Files outside of the directory are found too, since they can be selected with `../` paths
+        assert_eq!(expected, changed_files(&directory.join("sub"), "HEAD").unwrap());
+    }
+}
@@ -0,0 +1,258 @@
+// Copyright Ion Fusion contributors. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+use fuusak::config::PackageConfig;
+use fuusak::error::Error;
+use fuusak_err_macro::err_generic;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+/// Version of the catalog file format that this build of fuusak understands
+pub const CATALOG_VERSION: u32 = 1;
+
+const BUNDLED_CATALOG: &str = include_str!("catalog.toml");
+
+/// Describes the modules of the Fusion standard library, which usually aren't available as source,
+/// so that the index can treat them as stub modules.
+#[derive(Debug)]
+pub struct Catalog {
+    modules: BTreeMap<String, CatalogModule>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CatalogModule {
+    pub name: String,
+    pub language: String,
+    /// Modules (earlier in the catalog) whose bindings this module also provides
+    #[serde(default)]
+    pub reexports: Vec<String>,
+    #[serde(default)]
+    pub bindings: BTreeMap<String, CatalogBinding>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CatalogBinding {
+    pub kind: BindingKind,
+    pub arity: Option<Arity>,
+    pub doc: Option<String>,
+}
+
+impl fmt::Display for CatalogBinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.kind)?;
+        if let Some(arity) = self.arity {
+            write!(f, ", arity {arity}")?;
+        }
+        if let Some(doc) = &self.doc {
+            write!(f, ": {doc}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BindingKind {
+    Syntax,
+    Procedure,
+}
+
+impl fmt::Display for BindingKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BindingKind::Syntax => write!(f, "syntax"),
+            BindingKind::Procedure => write!(f, "procedure"),
+        }
+    }
+}
+
+/// Number of arguments a procedure or syntactic form accepts, written as `"2"`, `"1+"`, or `"1-3"`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Arity {
+    pub min: usize,
+    /// `None` if any number of additional arguments is accepted
+    pub max: Option<usize>,
+}
+
+impl TryFrom<String> for Arity {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Arity, String> {
+        let parse = |count: &str| {
+            count
+                .parse::<usize>()
+                .map_err(|_| format!("invalid arity {value:?}: expected \"N\", \"N+\", or \"N-M\""))
+        };
+        let arity = if let Some(min) = value.strip_suffix('+') {
+            Arity {
+                min: parse(min)?,
+                max: None,
+            }
+        } else if let Some((min, max)) = value.split_once('-') {
+            Arity {
+                min: parse(min)?,
+                max: Some(parse(max)?),
+            }
+        } else {
+            let count = parse(&value)?;
+            Arity {
+                min: count,
+                max: Some(count),
+            }
+        };
+        if arity.max.is_some_and(|max| max < arity.min) {
+            return Err(format!("invalid arity {value:?}: maximum is less than minimum"));
+        }
+        Ok(arity)
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.max {
+            None => write!(f, "{}+", self.min),
+            Some(max) if max == self.min => write!(f, "{max}"),
+            Some(max) => write!(f, "{}-{}", self.min, max),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TomlCatalog {
+    version: u32,
+    modules: Vec<CatalogModule>,
+}
+
+impl Catalog {
+    /// Loads the catalog named by the `catalog` package setting, or the bundled one if it isn't set
+    pub fn load(package: &PackageConfig, package_path: &Path) -> Result<Catalog, Error> {
+        if package.catalog.is_empty() {
+            return Ok(Catalog::bundled());
+        }
+        let path = package_path.join(&package.catalog);
+        let contents = std::fs::read_to_string(&path)
+            .map_err(|err| err_generic!("Failed to read binding catalog {}: {}", path.display(), err))?;
+        Catalog::parse(&contents).map_err(|err| err_generic!("Invalid binding catalog {}: {}", path.display(), err))
+    }
+
+    /// The catalog that's shipped with fuusak
+    pub fn bundled() -> Catalog {
+        Catalog::parse(BUNDLED_CATALOG).expect("well-formed bundled catalog")
+    }
+
+    fn parse(contents: &str) -> Result<Catalog, Error> {
+        let toml: TomlCatalog = toml::from_str(contents).map_err(|err| err_generic!("{}", err))?;
+        if toml.version != CATALOG_VERSION {
+            return Err(err_generic!(
+                "unsupported catalog version {}; expected version {}",
+                toml.version,
+                CATALOG_VERSION
+            ));
+        }
+        let mut modules = BTreeMap::new();
+        for module in toml.modules {
+            if let Some(missing) = module.reexports.iter().find(|name| !modules.contains_key(*name)) {
+                return Err(err_generic!(
+                    "module {} reexports {}, which must be listed before it",
+                    module.name,
+                    missing
+                ));
+            }
+            if module.language.is_empty() {
+                return Err(err_generic!("module {} has no language", module.name));
+            }
+            if modules.contains_key(&module.name) {
+                return Err(err_generic!("module {} is listed more than once", module.name));
+            }
+            modules.insert(module.name.clone(), module);
+        }
+        Ok(Catalog { modules })
+    }
+
+    pub fn get_module(&self, name: &str) -> Option<&CatalogModule> {
+        self.modules.get(name)
+    }
+
+    pub fn module_iter(&self) -> impl Iterator<Item = &CatalogModule> {
+        self.modules.values()
+    }
+
+    /// Returns the module that defines the binding that `module_name` provides as `name`, following its reexports
+    pub fn defining_module(&self, module_name: &str, name: &str) -> Option<&CatalogModule> {
+        let module = self.modules.get(module_name)?;
+        if module.bindings.contains_key(name) {
+            return Some(module);
+        }
+        // Later reexports take precedence, as in `exports`
+        module
+            .reexports
+            .iter()
+            .rev()
+            .find_map(|reexported| self.defining_module(reexported, name))
+    }
+
+    /// Returns every binding that `module_name` provides, including those it reexports
+    pub fn exports(&self, module_name: &str) -> BTreeMap<&str, &CatalogBinding> {
+        let mut exports = BTreeMap::new();
+        if let Some(module) = self.modules.get(module_name) {
+            for reexported in &module.reexports {
+                exports.extend(self.exports(reexported));
+            }
+            exports.extend(module.bindings.iter().map(|(name, binding)| (name.as_str(), binding)));
+        }
+        exports
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn bundled_catalog() {
+        let catalog = Catalog::bundled();
+        let exports = catalog.exports("/fusion");
+        assert_eq!(BindingKind::Syntax, exports["define"].kind);
+        assert_eq!(BindingKind::Procedure, exports["+"].kind);
+        assert_eq!("0+", exports["+"].arity.unwrap().to_string());
+        assert!(catalog.exports("/fusion/private/kernel").contains_key("lambda"));
+        assert!(!catalog.exports("/fusion/private/kernel").contains_key("+"));
+    }
+
+    #[test]
+    fn fusion_coverage() {
+        let checklist = std::fs::read_to_string("index_tests/catalog/fusion_exports.txt").unwrap();
+        let expected: BTreeSet<&str> = checklist
+            .lines()
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .collect();
+        let catalog = Catalog::bundled();
+        let exports: BTreeSet<&str> = catalog.exports("/fusion").into_keys().collect();
+        let missing: Vec<_> = expected.difference(&exports).collect();
+        assert!(missing.is_empty(), "the catalog's /fusion doesn't list {missing:?}");
+        let unlisted: Vec<_> = exports.difference(&expected).collect();
+        assert!(unlisted.is_empty(), "fusion_exports.txt doesn't list {unlisted:?}");
+    }
+
+    #[test]
+    fn invalid_catalogs() {
+        let error = |contents: &str| Catalog::parse(contents).err().unwrap().to_string();
+        let module = "version = 1\n[[modules]]\nname = \"/a\"\nlanguage = \"/a\"\n";
+        assert!(error("version = 2\nmodules = []\n").contains("unsupported catalog version 2"));
+        assert!(
+            error(&format!(
+                "{module}bindings = {{ f = {{ kind = \"procedure\", arity = \"3-1\" }} }}\n"
+            ))
+            .contains("maximum is less than minimum")
+        );
+        assert!(error(&format!("{module}reexports = [\"/b\"]\n")).contains("must be listed before it"));
+        assert!(error(&format!("{module}size = 1\n")).contains("unknown field `size`"));
+        assert!(error("version = 1\n[[modules]]\nname = \"/a\"\nlanguage = \"\"\n").contains("has no language"));
+    }
+}
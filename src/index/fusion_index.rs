@@ -1,29 +1,66 @@
 // Copyright Ion Fusion contributors. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
-use crate::index::{Module, ModuleCell, ScriptCell};
+use crate::index::{Catalog, Module, ModuleCell, Resolutions, ScriptCell};
 use fuusak::error::Error;
 use fuusak::file::FusionFile;
+use fuusak::span::ShortSpan;
 use fuusak_err_macro::err_generic;
-use std::cell::RefCell;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt;
 use std::path::{Path, PathBuf};
-use std::rc::Rc;
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
-pub const TOP_LEVEL_MODULE_NAME: &str = "/fusion/private/kernel";
+/// A handle to an index that can be shared between threads. Those that only need to look things up without
+/// seeing later changes can take a snapshot instead of holding on to the lock.
+#[derive(Clone)]
+pub struct FusionIndexCell(Arc<RwLock<FusionIndex>>);
 
-pub type FusionIndexCell = Rc<RefCell<FusionIndex>>;
+impl FusionIndexCell {
+    pub fn read(&self) -> RwLockReadGuard<'_, FusionIndex> {
+        // The index is only changed by inserting or removing whole modules and scripts, so it's consistent
+        // even if a thread panicked while holding the lock
+        self.0.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, FusionIndex> {
+        self.0.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Returns a copy of the index as it is now. Modules and scripts are shared rather than copied.
+    pub fn snapshot(&self) -> FusionIndex {
+        self.read().clone()
+    }
+}
 
+#[derive(Clone)]
 pub struct FusionIndex {
     current_package_path: PathBuf,
     module_paths: Vec<PathBuf>,
     modules: BTreeMap<String, ModuleCell>,
     scripts: BTreeMap<String, ScriptCell>,
+    /// For each module, the modules and scripts that require it or use it as their language
+    dependents: BTreeMap<String, BTreeSet<Dependent>>,
+    /// Modules that were removed from the package, and haven't been loaded from a file again since
+    removed_modules: BTreeSet<String>,
+    catalog: Arc<Catalog>,
+}
+
+/// A module or script that depends on a module
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Dependent {
+    Module(String),
+    Script(String),
 }
 
 impl FusionIndex {
-    pub fn new(current_package_path: &Path, module_paths: Vec<PathBuf>) -> Result<FusionIndexCell, Error> {
-        let result = Rc::new(RefCell::new(FusionIndex {
+    // Indexes are always shared, so they're created behind a handle
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(
+        current_package_path: &Path,
+        module_paths: Vec<PathBuf>,
+        catalog: Catalog,
+    ) -> Result<FusionIndexCell, Error> {
+        let result = FusionIndexCell(Arc::new(RwLock::new(FusionIndex {
             current_package_path: current_package_path
                 .canonicalize()
                 .map_err(|err| err_generic!("failed to canonicalize path: {}", err))?,
@@ -36,29 +73,77 @@ impl FusionIndex {
                 .collect::<Result<Vec<PathBuf>, Error>>()?,
             modules: BTreeMap::new(),
             scripts: BTreeMap::new(),
-        }));
-        println!("Module repository initialized with paths:");
-        for path in &result.borrow().module_paths {
-            println!("  {}", path.display());
-        }
+            dependents: BTreeMap::new(),
+            removed_modules: BTreeSet::new(),
+            catalog: Arc::new(catalog),
+        })));
         Ok(result)
     }
 
+    pub fn module_paths(&self) -> &[PathBuf] {
+        &self.module_paths
+    }
+
     pub fn current_package_path(&self) -> &Path {
         &self.current_package_path
     }
 
-    pub fn get_root_module(&mut self) -> ModuleCell {
-        if !self.modules.contains_key(TOP_LEVEL_MODULE_NAME) {
-            self.put_module(Module::new(
-                TOP_LEVEL_MODULE_NAME.into(),
-                TOP_LEVEL_MODULE_NAME.into(),
-                FusionFile::empty_file(),
-                Vec::new(),
-                BTreeMap::new(),
-            ));
+    pub fn catalog(&self) -> &Catalog {
+        &self.catalog
+    }
+
+    /// Returns true if `module` was created from the binding catalog, or for a module that couldn't be found,
+    /// rather than loaded from a file
+    pub fn is_stub_module(&self, module: &Module) -> bool {
+        module.is_stub()
+    }
+
+    /// Returns true if `module` couldn't be found in the module paths or the binding catalog
+    pub fn is_unknown_module(&self, module: &Module) -> bool {
+        module.is_unknown()
+    }
+
+    /// Returns true if `module` couldn't be found even though it must be part of the package: it was removed
+    /// from the package, or the directory that its file would be in exists in one of the module paths
+    pub fn is_missing_module(&self, module: &Module) -> bool {
+        let relative_path = Path::new(module.name.trim_start_matches('/'));
+        module.is_unknown()
+            && (self.removed_modules.contains(&module.name)
+                || self.module_paths.iter().any(|path| {
+                    path.join(relative_path)
+                        .parent()
+                        .is_some_and(|directory| directory.is_dir())
+                }))
+    }
+
+    /// Returns the stub module for `name` from the binding catalog, creating it if it's not indexed yet.
+    /// Modules that aren't in the catalog get a stub without any bindings.
+    pub fn get_stub_module(&mut self, name: &str) -> ModuleCell {
+        if let Some(module) = self.modules.get(name) {
+            return module.clone();
         }
-        self.modules.get(TOP_LEVEL_MODULE_NAME).unwrap().clone()
+        let provides = self
+            .catalog
+            .exports(name)
+            .into_keys()
+            .map(|binding| (binding.to_string(), ShortSpan::new(0, 0)))
+            .collect();
+        let language = self
+            .catalog
+            .get_module(name)
+            .map(|catalog_module| catalog_module.language.clone())
+            .unwrap_or_default();
+        let module = Module::new(
+            name.into(),
+            language,
+            FusionFile::empty_file(),
+            Vec::new(),
+            provides,
+            Vec::new(),
+            Resolutions::new(),
+        );
+        self.put_module(module.clone());
+        module
     }
 
     pub fn module_iter(&self) -> impl Iterator<Item = &'_ ModuleCell> {
@@ -74,8 +159,26 @@ impl FusionIndex {
     }
 
     pub fn put_module(&mut self, module: ModuleCell) {
-        let name = module.borrow().name.clone();
-        self.modules.insert(name, module);
+        let (name, dependencies) = { (module.name.clone(), module.dependencies()) };
+        if !module.is_stub() {
+            self.removed_modules.remove(&name);
+        }
+        if let Some(previous) = self.modules.insert(name.clone(), module) {
+            let previous_dependencies = previous.dependencies();
+            self.remove_dependent(&previous_dependencies, &Dependent::Module(name.clone()));
+        }
+        self.add_dependent(dependencies, Dependent::Module(name));
+    }
+
+    /// Removes a module, while keeping track of what depends on it so that dependents can be rechecked
+    pub fn remove_module(&mut self, name: &str) -> Option<ModuleCell> {
+        let module = self.modules.remove(name)?;
+        if !module.is_stub() {
+            self.removed_modules.insert(name.into());
+        }
+        let dependencies = module.dependencies();
+        self.remove_dependent(&dependencies, &Dependent::Module(name.into()));
+        Some(module)
     }
 
     pub fn get_script(&self, name: &String) -> Option<ScriptCell> {
@@ -83,8 +186,54 @@ impl FusionIndex {
     }
 
     pub fn put_script(&mut self, script: ScriptCell) {
-        let name = script.borrow().name.clone();
-        self.scripts.insert(name, script);
+        let (name, dependencies) = { (script.name.clone(), script.dependencies()) };
+        if let Some(previous) = self.scripts.insert(name.clone(), script) {
+            let previous_dependencies = previous.dependencies();
+            self.remove_dependent(&previous_dependencies, &Dependent::Script(name.clone()));
+        }
+        self.add_dependent(dependencies, Dependent::Script(name));
+    }
+
+    pub fn remove_script(&mut self, name: &str) -> Option<ScriptCell> {
+        let script = self.scripts.remove(name)?;
+        let dependencies = script.dependencies();
+        self.remove_dependent(&dependencies, &Dependent::Script(name.into()));
+        Some(script)
+    }
+
+    fn add_dependent(&mut self, dependencies: BTreeSet<String>, dependent: Dependent) {
+        for dependency in dependencies {
+            self.dependents.entry(dependency).or_default().insert(dependent.clone());
+        }
+    }
+
+    fn remove_dependent(&mut self, dependencies: &BTreeSet<String>, dependent: &Dependent) {
+        for dependency in dependencies {
+            if let Some(dependents) = self.dependents.get_mut(dependency) {
+                dependents.remove(dependent);
+            }
+        }
+    }
+
+    /// Returns the modules and scripts that depend on `module_name` directly or indirectly, in an order where
+    /// each one comes after the modules it depends on, so that they can be reloaded in that order
+    pub fn transitive_dependents(&self, module_name: &str) -> Vec<Dependent> {
+        let mut visited = BTreeSet::new();
+        let mut post_order = Vec::new();
+        self.visit_dependents(module_name, &mut visited, &mut post_order);
+        post_order.reverse();
+        post_order
+    }
+
+    fn visit_dependents(&self, module_name: &str, visited: &mut BTreeSet<Dependent>, post_order: &mut Vec<Dependent>) {
+        for dependent in self.dependents.get(module_name).into_iter().flatten() {
+            if visited.insert(dependent.clone()) {
+                if let Dependent::Module(name) = dependent {
+                    self.visit_dependents(name, visited, post_order);
+                }
+                post_order.push(dependent.clone());
+            }
+        }
     }
 
     pub fn find_module_file(&self, module_name: &str) -> Option<PathBuf> {
@@ -117,6 +266,12 @@ impl FusionIndex {
     }
 }
 
+impl fmt::Debug for FusionIndexCell {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.read().fmt(f)
+    }
+}
+
 #[allow(clippy::missing_fields_in_debug)]
 impl fmt::Debug for FusionIndex {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
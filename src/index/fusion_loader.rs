@@ -1,14 +1,19 @@
 // Copyright Ion Fusion contributors. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
-use crate::index::{FusionIndexCell, Module, ModuleCell, Origin, RequireForm, RequireType, Script, ScriptCell};
-use fuusak::ast::{AtomicType, Expr, ListData};
+use crate::index::{
+    DEFINING_FORMS, FusionIndexCell, Imports, Module, ModuleCell, Origin, RequireForm, RequireType, Script, ScriptCell,
+    resolve,
+};
+use fuusak::ast::{Expr, ListData};
 use fuusak::config::FusionConfig;
 use fuusak::error::Error;
 use fuusak::file::{FusionFile, find_files};
 use fuusak::span::ShortSpan;
 use fuusak_err_macro::{err_generic, err_spanned};
+use rayon::prelude::*;
 use std::borrow::Cow;
-use std::collections::BTreeMap;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
 use std::path::{Path, PathBuf};
 use std::string::ToString;
 
@@ -16,6 +21,20 @@ pub struct FusionLoader<'i> {
     config: &'i FusionConfig,
     index: FusionIndexCell,
     current_package_path: PathBuf,
+    /// Modules whose files are being processed, outermost first, which is used to detect circular requires
+    loading: RefCell<Vec<LoadingModule>>,
+    /// Doesn't print what's loaded, for commands whose output is machine readable
+    silent: bool,
+    /// Loads modules that require themselves in a cycle instead of failing
+    allow_cycles: bool,
+}
+
+/// A module that is still being loaded, and where it requires the module it's currently waiting on
+struct LoadingModule {
+    name: String,
+    file_name: PathBuf,
+    contents: String,
+    require_span: Option<ShortSpan>,
 }
 
 impl<'i> FusionLoader<'i> {
@@ -25,71 +44,262 @@ impl<'i> FusionLoader<'i> {
             index: fusion_index.clone(),
             // Retain a copy of the current_package_path so that we can load modules while
             // using it without running into runtime memory ownership issues.
-            current_package_path: fusion_index.borrow().current_package_path().into(),
+            current_package_path: fusion_index.read().current_package_path().into(),
+            loading: RefCell::new(Vec::new()),
+            silent: false,
+            allow_cycles: false,
         }
     }
 
-    pub fn load_configured_paths(&self, _config: &FusionConfig) -> Result<(), Error> {
+    /// Stops the loader from printing the modules and tests that it loads
+    pub fn silent(mut self) -> FusionLoader<'i> {
+        self.silent = true;
+        self
+    }
+
+    /// Lets modules require themselves in a cycle, for commands that only need to know what requires what. The
+    /// require that closes a cycle gets an empty stub of the module that's still being loaded, so the bindings
+    /// of the modules in a cycle aren't resolved properly.
+    pub fn allow_cycles(mut self) -> FusionLoader<'i> {
+        self.allow_cycles = true;
+        self
+    }
+
+    /// Creates a loader like this one, to load modules on another thread
+    fn worker(
+        config: &'i FusionConfig,
+        fusion_index: &FusionIndexCell,
+        silent: bool,
+        allow_cycles: bool,
+    ) -> FusionLoader<'i> {
+        FusionLoader {
+            silent,
+            allow_cycles,
+            ..FusionLoader::new(config, fusion_index)
+        }
+    }
+
+    pub fn load_configured_paths(&self, config: &FusionConfig) -> Result<(), Error> {
+        let package = &config.package;
         // Load modules
-        let module_path = self.current_package_path.join("fusion/src");
-        if module_path.exists() {
-            let fusion_file_paths = find_files(module_path, ".fusion")?;
-            for file_path in &fusion_file_paths {
-                self.load_module_file(file_path)?;
+        let mut module_file_paths = Vec::new();
+        for module_root in &package.module_roots {
+            let module_path = self.current_package_path.join(module_root);
+            if module_path.exists() {
+                module_file_paths.extend(find_files(module_path, ".fusion")?);
             }
         }
-        // Load tests
-        let test_path = self.current_package_path.join("ftst");
-        if test_path.exists() {
-            let fusion_file_paths = find_files(test_path, ".fusion")?;
-            for file_path in &fusion_file_paths {
-                let relative_path = file_path.strip_prefix(&self.current_package_path).unwrap();
-                let test_name = relative_path.to_string_lossy();
-                self.load_script(
-                    test_name.to_string(),
-                    vec!["/fusion".into()],
-                    Vec::new(),
-                    vec![relative_path.to_path_buf()],
-                )?;
-                println!("Loaded test: {test_name}");
+        self.load_module_files(module_file_paths)?;
+        // Load tests, skipping those that were restored from the index cache
+        let mut test_file_paths = Vec::new();
+        for test_root in &package.test_roots {
+            let test_path = self.current_package_path.join(test_root);
+            if test_path.exists() {
+                test_file_paths.extend(
+                    find_files(test_path, ".fusion")?
+                        .into_iter()
+                        .filter(|file_path| self.index.read().get_script(&self.test_name(file_path)).is_none()),
+                );
             }
         }
+        let (index, silent, allow_cycles) = (&self.index, self.silent, self.allow_cycles);
+        first_error(
+            test_file_paths
+                .par_iter()
+                .map(|file_path| FusionLoader::worker(config, index, silent, allow_cycles).load_test_file(file_path))
+                .collect(),
+        )?;
         Ok(())
     }
 
+    /// Loads the modules in the given files, other than those that are already indexed. All the files are parsed
+    /// in parallel up front. They're then processed in rounds, where each round processes the modules whose
+    /// requires among these files were processed in an earlier round, in parallel.
+    ///
+    /// Parsing is nearly all of the work, so that's where the parallelism pays off. The rounds are what let each
+    /// file be parsed only once: processing a module loads whatever it requires, which would otherwise go back to
+    /// the file and parse it again on demand. The `load_benchmark` test times this on a generated package.
+    fn load_module_files(&self, file_paths: Vec<PathBuf>) -> Result<(), Error> {
+        let mut pending = BTreeMap::new();
+        for file_path in file_paths {
+            let file_path = self.resolve_full_file_path(&file_path).into_owned();
+            let module_name = self.determine_module_name(&file_path)?;
+            if self.index.read().get_module(&module_name).is_none() {
+                pending.insert(module_name, file_path);
+            }
+        }
+        let (config, index, silent, allow_cycles) = (self.config, &self.index, self.silent, self.allow_cycles);
+        let parsed: Vec<(String, FusionFile)> = first_error(
+            pending
+                .into_par_iter()
+                .map(|(module_name, file_path)| Ok((module_name, parse_module_file(config, &file_path)?)))
+                .collect(),
+        )?;
+
+        let module_names: BTreeSet<String> = parsed.iter().map(|(module_name, _)| module_name.clone()).collect();
+        let mut remaining: BTreeMap<String, (FusionFile, BTreeSet<String>)> = parsed
+            .into_iter()
+            .map(|(module_name, file)| {
+                let mut dependencies = required_module_names(&file);
+                dependencies.retain(|dependency| module_names.contains(dependency) && *dependency != module_name);
+                (module_name, (file, dependencies))
+            })
+            .collect();
+        while !remaining.is_empty() {
+            let ready: Vec<String> = remaining
+                .iter()
+                .filter(|(_, (_, dependencies))| dependencies.iter().all(|name| !remaining.contains_key(name)))
+                .map(|(module_name, _)| module_name.clone())
+                .collect();
+            if ready.is_empty() {
+                // What's left requires itself in a cycle, which loading one module at a time reports
+                for (module_name, (file, _)) in remaining {
+                    if self.index.read().get_module(&module_name).is_none() {
+                        self.index_module_file(module_name, file)?;
+                    }
+                }
+                break;
+            }
+            let round: Vec<(String, FusionFile)> = ready
+                .into_iter()
+                .map(|module_name| {
+                    let (file, _) = remaining.remove(&module_name).unwrap();
+                    (module_name, file)
+                })
+                .collect();
+            first_error(
+                round
+                    .into_par_iter()
+                    .map(|(module_name, file)| {
+                        // A module that another one required without it being noticed up front is already loaded
+                        if index.read().get_module(&module_name).is_some() {
+                            return Ok(());
+                        }
+                        FusionLoader::worker(config, index, silent, allow_cycles)
+                            .index_module_file(module_name, file)
+                            .map(|_| ())
+                    })
+                    .collect(),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Loads a test file as a script named by its path relative to the package, using the configured top-level modules
+    pub fn load_test_file(&self, file_path: &Path) -> Result<ScriptCell, Error> {
+        let relative_path = file_path.strip_prefix(&self.current_package_path).unwrap_or(file_path);
+        let test_name = self.test_name(file_path);
+        let script = self.load_script(
+            test_name.clone(),
+            self.config.package.test_top_level_modules.clone(),
+            Vec::new(),
+            vec![relative_path.to_path_buf()],
+        )?;
+        if !self.silent {
+            println!("Loaded test: {test_name}");
+        }
+        Ok(script)
+    }
+
     pub fn load_module_file<P: AsRef<Path>>(&self, file_path: P) -> Result<ModuleCell, Error> {
         let file_path = self.resolve_full_file_path(file_path.as_ref());
         let module_name = self.determine_module_name(&file_path)?;
-        if let Some(module) = self.index.borrow().get_module(&module_name) {
+        // A stub that was created before the file existed is replaced by the module in the file
+        if let Some(module) = self.index.read().get_module(&module_name)
+            && !module.is_stub()
+        {
             return Ok(module);
         }
+        if self.allow_cycles && self.loading.borrow().iter().any(|loading| loading.name == module_name) {
+            return Ok(self.index.write().get_stub_module(&module_name));
+        }
+        self.check_for_cycle(&module_name)?;
 
         self.reload_module_file(module_name, file_path.as_ref())
     }
 
     pub fn reload_module_file(&self, module_name: String, file_path: &Path) -> Result<ModuleCell, Error> {
-        let file = FusionFile::load(self.config, file_path)
-            .map_err(|err| err_generic!("failed to load {:?}: {}", file_path, err))?;
-
-        let module = self.process_file(module_name, file)?;
-        self.index.borrow_mut().put_module(module.clone());
+        let file = parse_module_file(self.config, file_path)?;
+        self.index_module_file(module_name, file)
+    }
 
-        println!("Loaded module: {}", module.borrow().name);
+    /// Processes a parsed module file and puts the module into the index
+    fn index_module_file(&self, module_name: String, file: FusionFile) -> Result<ModuleCell, Error> {
+        self.loading.borrow_mut().push(LoadingModule {
+            name: module_name.clone(),
+            file_name: file.file_name.clone(),
+            contents: file.contents.clone(),
+            require_span: None,
+        });
+        let module = self.process_file(module_name, file);
+        self.loading.borrow_mut().pop();
+        let module = module?;
+        self.index.write().put_module(module.clone());
+
+        if !self.silent {
+            println!("Loaded module: {}", module.name);
+        }
         Ok(module)
     }
 
+    fn test_name(&self, file_path: &Path) -> String {
+        let relative_path = file_path.strip_prefix(&self.current_package_path).unwrap_or(file_path);
+        relative_path.to_string_lossy().into()
+    }
+
+    /// Loads a module from the module paths, falling back to a stub for it. Modules that aren't in the binding
+    /// catalog either get a stub that doesn't provide anything, so the names that might come from them are unknown.
     pub fn load_module(&self, module_name: &str) -> Result<ModuleCell, Error> {
-        if module_name == "/fusion/private/kernel" {
-            return Ok(self.index.borrow_mut().get_root_module());
+        let module_file_name = self.index.read().find_module_file(module_name);
+        match module_file_name {
+            Some(module_file_name) => self.load_module_file(module_file_name),
+            None => Ok(self.index.write().get_stub_module(module_name)),
+        }
+    }
+
+    /// Loads a module that the file being processed requires (or uses as its language) at `span`
+    fn load_required_module(&self, module_name: &str, span: ShortSpan) -> Result<ModuleCell, Error> {
+        if let Some(loading) = self.loading.borrow_mut().last_mut() {
+            loading.require_span = Some(span);
         }
+        self.load_module(module_name)
+    }
 
-        let module_file_name = self.index.borrow().find_module_file(module_name).ok_or_else(|| {
-            err_generic!(
-                "cannot load module named {}: no module file found in module paths",
-                module_name
-            )
-        })?;
-        self.load_module_file(module_file_name)
+    /// Fails if `module_name` is still being loaded, which means that it (indirectly) requires itself
+    fn check_for_cycle(&self, module_name: &str) -> Result<(), Error> {
+        let loading = self.loading.borrow();
+        let Some(start) = loading.iter().position(|module| module.name == module_name) else {
+            return Ok(());
+        };
+        let chain = &loading[start..];
+        let names: Vec<&str> = chain
+            .iter()
+            .map(|module| module.name.as_str())
+            .chain([module_name])
+            .collect();
+        let mut message = format!("circular module dependency: {}", names.join(" → "));
+        for (module, required) in chain.iter().zip(&names[1..]) {
+            let file_name = module
+                .file_name
+                .strip_prefix(&self.current_package_path)
+                .unwrap_or(&module.file_name);
+            let (line, column) = module
+                .require_span
+                .map_or((1, 1), |span| span.line_col(&module.contents));
+            message.push_str(&format!(
+                "\n  {} requires {} at {}:{}:{}",
+                module.name,
+                required,
+                file_name.display(),
+                line,
+                column
+            ));
+        }
+        let span = chain.last().and_then(|module| module.require_span);
+        Err(match span {
+            Some(span) => err_spanned!(span, "{}", message),
+            None => err_generic!("{}", message),
+        })
     }
 
     pub fn load_script(
@@ -99,9 +309,10 @@ impl<'i> FusionLoader<'i> {
         global_bindings: Vec<String>,
         file_names: Vec<PathBuf>,
     ) -> Result<ScriptCell, Error> {
-        for top_level in &top_level_modules {
-            self.load_module(top_level)?;
-        }
+        let top_level_cells = top_level_modules
+            .iter()
+            .map(|top_level| self.load_module(top_level))
+            .collect::<Result<Vec<ModuleCell>, Error>>()?;
 
         let files = file_names
             .into_iter()
@@ -114,17 +325,25 @@ impl<'i> FusionLoader<'i> {
             })
             .collect::<Result<Vec<FusionFile>, Error>>()?;
 
+        let mut requires = Vec::new();
+        let mut resolutions = Vec::new();
         for file in &files {
             let mut processed = ProcessedFile::new();
             for expr in &file.ast {
                 self.visit_expr(&mut processed, expr, false)
                     .map_err(|err: Error| err.resolve_spanned(&file.file_name, &file.contents))?;
             }
-            drop(processed);
+            let imports = Imports {
+                requires: &processed.requires,
+                languages: &top_level_cells,
+                globals: &global_bindings,
+            };
+            resolutions.push(resolve(&file.ast, &imports));
+            requires.extend(processed.requires);
         }
 
-        let script = Script::new(name, top_level_modules, global_bindings, files);
-        self.index.borrow_mut().put_script(script.clone());
+        let script = Script::new(name, top_level_modules, global_bindings, files, requires, resolutions);
+        self.index.write().put_script(script.clone());
         Ok(script)
     }
 
@@ -137,7 +356,7 @@ impl<'i> FusionLoader<'i> {
     }
 
     fn determine_module_name(&self, file_path: &Path) -> Result<String, Error> {
-        let module_repo = self.index.borrow();
+        let module_repo = self.index.read();
         let parent_path = module_repo
             .find_parent_path(file_path)
             .ok_or_else(|| err_generic!("failed to find parent path of {:?}", file_path))?;
@@ -160,8 +379,25 @@ impl<'i> FusionLoader<'i> {
                 .map_err(|err: Error| err.resolve_spanned(&file.file_name, &file.contents))?;
         }
 
-        let (language, requires, provides) = processed.dissolve();
-        Ok(Module::new(module_name, language, file, requires, provides))
+        let (language, requires, provides, provided_names) = processed
+            .dissolve()
+            .map_err(|err| err.resolve_spanned(&file.file_name, &file.contents))?;
+        let languages: Vec<ModuleCell> = self.index.read().get_module(&language).into_iter().collect();
+        let imports = Imports {
+            requires: &requires,
+            languages: &languages,
+            globals: &[],
+        };
+        let resolutions = resolve(&file.ast, &imports);
+        Ok(Module::new(
+            module_name,
+            language,
+            file,
+            requires,
+            provides,
+            provided_names,
+            resolutions,
+        ))
     }
 
     fn visit_expr(&self, processed: &mut ProcessedFile, expr: &Expr, quoted: bool) -> Result<(), Error> {
@@ -200,12 +436,12 @@ impl<'i> FusionLoader<'i> {
             } else {
                 match function_call.as_str() {
                     "define" => Self::visit_define(processed, items),
-                    "define_syntax" | "defpub" | "defpub_j" | "defpub_syntax" => Self::visit_defpub(processed, items),
+                    name if DEFINING_FORMS.contains(&name) => Self::visit_defpub(processed, items),
                     "module" => self.visit_module(processed, sexpr.span, items)?,
                     "provide" => Self::visit_provide(processed, items)?,
                     "quasiquote" => self.visit_quasiquote(processed, items)?,
                     "quote" => {}
-                    "require" => self.visit_require(processed, items)?,
+                    "require" => self.visit_require(processed, sexpr.span, items)?,
                     _ => visit_items(first_value, items)?,
                 }
             }
@@ -220,151 +456,195 @@ impl<'i> FusionLoader<'i> {
         mut rest: impl Iterator<Item = &'i Expr>,
     ) -> Result<(), Error> {
         let _module_name = rest.next().ok_or_else(|| err_spanned!(span, "missing module name"))?;
-        let language = rest
-            .next()
+        let language_expr = rest.next();
+        let language = language_expr
             .and_then(|expr| expr.string_value().map(String::as_str).or(expr.stripped_symbol_value()))
             .ok_or_else(|| err_spanned!(span, "missing module language"))?;
         processed.language = Some(language.to_string());
-        self.load_module(language)?;
+        self.load_required_module(language, language_expr.map_or(span, Expr::span))?;
         for expr in rest {
             self.visit_expr(processed, expr, false)?;
         }
         Ok(())
     }
 
-    fn visit_require(&self, processed: &mut ProcessedFile, rest: impl Iterator<Item = &'i Expr>) -> Result<(), Error> {
+    fn visit_require(
+        &self,
+        processed: &mut ProcessedFile,
+        form_span: ShortSpan,
+        rest: impl Iterator<Item = &'i Expr>,
+    ) -> Result<(), Error> {
         for expr in rest {
-            match expr {
-                Expr::Atomic(data) => match data.typ {
-                    AtomicType::QuotedString => {
-                        let module = self.load_module(&data.value)?;
-                        processed.requires.push(RequireForm::new(module, RequireType::All));
-                        Ok(())
-                    }
-                    _ => Err(err_spanned!(
-                        data.span,
-                        "argument 0 to require must be string or s-expr"
-                    )),
-                },
-                Expr::SExpr(data) => self.visit_require_sexpr(processed, data),
-                _ => Err(err_spanned!(
-                    expr.span(),
-                    "argument 0 to require must be string or s-expr"
-                )),
-            }?;
+            let for_syntax = expr.sexpr_value().filter(|sexpr| {
+                sexpr
+                    .item_iter()
+                    .next()
+                    .and_then(Expr::symbol_value)
+                    .map(String::as_str)
+                    == Some("for_syntax")
+            });
+            if let Some(for_syntax) = for_syntax {
+                for spec in for_syntax.item_iter().skip(1) {
+                    let (module, required) = self.visit_require_spec(spec)?;
+                    processed.requires.push(RequireForm::new(
+                        module,
+                        RequireType::ForSyntax(Box::new(required)),
+                        spec.span(),
+                        form_span,
+                    ));
+                }
+            } else {
+                let (module, required) = self.visit_require_spec(expr)?;
+                processed
+                    .requires
+                    .push(RequireForm::new(module, required, expr.span(), form_span));
+            }
         }
         Ok(())
     }
 
-    fn visit_require_sexpr(&self, processed: &mut ProcessedFile, sexpr: &ListData) -> Result<(), Error> {
+    /// Loads the module that a require spec refers to, and determines which of its bindings the spec imports.
+    /// A spec is either a module name, or a form that restricts or renames the bindings of a nested spec.
+    fn visit_require_spec(&self, expr: &Expr) -> Result<(ModuleCell, RequireType), Error> {
+        if let Some(module_name) = expr.string_value() {
+            return Ok((self.load_required_module(module_name, expr.span())?, RequireType::All));
+        }
+        let Some(sexpr) = expr.sexpr_value() else {
+            return Err(err_spanned!(expr.span(), "require spec must be string or s-expr"));
+        };
         let mut items = sexpr.item_iter();
-        if let Some(first_value) = items.next()
-            && let Some(function_call) = first_value.symbol_value()
-        {
-            return match function_call.as_str() {
-                "only_in" => self.visit_require_only_in(processed, sexpr.span, items),
-                "prefix_in" => Err(err_spanned!(
-                    first_value.span(),
-                    "support for `(require (prefix_in ...))` is not implemented"
-                )),
-                "rename_in" => self.visit_require_rename_in(processed, sexpr.span, items),
-                _ => Err(err_spanned!(first_value.span(), "invalid argument to require")),
-            };
+        let first_value = items
+            .next()
+            .ok_or_else(|| err_spanned!(sexpr.span, "invalid argument to require"))?;
+        let Some(function_call) = first_value.symbol_value() else {
+            return Err(err_spanned!(first_value.span(), "invalid argument to require"));
+        };
+        match function_call.as_str() {
+            "only_in" => {
+                let (module, inner) = self.visit_nested_require_spec(sexpr.span, items.next())?;
+                let names = Self::visit_require_names(items, "only_in")?;
+                Ok((module, RequireType::Names(Box::new(inner), names)))
+            }
+            "except_in" => {
+                let (module, inner) = self.visit_nested_require_spec(sexpr.span, items.next())?;
+                let names = Self::visit_require_names(items, "except_in")?;
+                Ok((module, RequireType::Except(Box::new(inner), names)))
+            }
+            "prefix_in" => {
+                let prefix = items
+                    .next()
+                    .and_then(|expr| {
+                        let prefix = expr.stripped_symbol_value()?;
+                        Some(Origin::new(prefix.to_string(), expr.span()))
+                    })
+                    .ok_or_else(|| err_spanned!(sexpr.span, "prefix_in requires a symbol prefix"))?;
+                let (module, inner) = self.visit_nested_require_spec(sexpr.span, items.next())?;
+                if let Some(extra) = items.next() {
+                    return Err(err_spanned!(extra.span(), "prefix_in expects a single require spec"));
+                }
+                Ok((module, RequireType::Prefixed(Box::new(inner), prefix)))
+            }
+            "rename_in" => {
+                let (module, inner) = self.visit_nested_require_spec(sexpr.span, items.next())?;
+                let mapping = Self::visit_rename_in_mapping(items)?;
+                Ok((module, RequireType::Mapped(Box::new(inner), mapping)))
+            }
+            "for_syntax" => Err(err_spanned!(
+                first_value.span(),
+                "for_syntax must appear directly within require"
+            )),
+            _ => Err(err_spanned!(first_value.span(), "invalid argument to require")),
         }
-        Ok(())
     }
 
-    fn visit_require_only_in(
+    fn visit_nested_require_spec(
         &self,
-        processed: &mut ProcessedFile,
         span: ShortSpan,
-        mut rest: impl Iterator<Item = &'i Expr>,
-    ) -> Result<(), Error> {
-        let module_name = rest
-            .next()
-            .and_then(|expr| expr.string_value())
-            .ok_or_else(|| err_spanned!(span, "missing module name"))?;
-        let module = self.load_module(module_name)?;
-        processed.requires.push(RequireForm::new(
-            module,
-            RequireType::Names(
-                rest.map(|expr| {
-                    let name = expr
-                        .stripped_symbol_value()
-                        .map(ToString::to_string)
-                        .ok_or_else(|| err_spanned!(expr.span(), "non-symbol found in require only_in list"));
-                    name.map(|value| Origin::new(value, expr.span()))
-                })
-                .collect::<Result<Vec<Origin>, Error>>()?
-                .into_iter()
-                .collect(),
-            ),
-        ));
-        Ok(())
+        spec: Option<&Expr>,
+    ) -> Result<(ModuleCell, RequireType), Error> {
+        let spec = spec.ok_or_else(|| err_spanned!(span, "missing module name"))?;
+        self.visit_require_spec(spec)
     }
 
-    fn visit_require_rename_in(
-        &self,
-        processed: &mut ProcessedFile,
-        span: ShortSpan,
-        mut rest: impl Iterator<Item = &'i Expr>,
-    ) -> Result<(), Error> {
-        let module_name = rest
-            .next()
-            .and_then(|expr| expr.string_value())
-            .ok_or_else(|| err_spanned!(span, "missing module name"))?;
-        let module = self.load_module(module_name)?;
-        processed.requires.push(RequireForm::new(
-            module,
-            RequireType::Mapped(
-                rest.map(|expr| {
-                    let pair = expr
-                        .sexpr_value()
-                        .map(|sexpr| {
-                            sexpr
-                                .item_iter()
-                                .map(|expr| {
-                                    expr.stripped_symbol_value()
-                                        .map(ToString::to_string)
-                                        .ok_or_else(|| err_spanned!(expr.span(), "expected string"))
-                                })
-                                .collect::<Result<Vec<String>, Error>>()
+    fn visit_require_names<'e>(rest: impl Iterator<Item = &'e Expr>, form: &str) -> Result<Vec<Origin>, Error> {
+        rest.map(|expr| {
+            let name = expr
+                .stripped_symbol_value()
+                .map(ToString::to_string)
+                .ok_or_else(|| err_spanned!(expr.span(), "non-symbol found in require {} list", form));
+            name.map(|value| Origin::new(value, expr.span()))
+        })
+        .collect()
+    }
+
+    fn visit_rename_in_mapping<'e>(rest: impl Iterator<Item = &'e Expr>) -> Result<BTreeMap<String, Origin>, Error> {
+        rest.map(|expr| {
+            let pair = expr
+                .sexpr_value()
+                .map(|sexpr| {
+                    sexpr
+                        .item_iter()
+                        .map(|expr| {
+                            expr.stripped_symbol_value()
+                                .map(ToString::to_string)
+                                .ok_or_else(|| err_spanned!(expr.span(), "expected string"))
                         })
-                        .ok_or_else(|| err_spanned!(expr.span(), "expected s-expression"))??;
-                    if pair.len() == 2 {
-                        Ok((pair[0].clone(), Origin::new(pair[1].clone(), expr.span())))
-                    } else {
-                        Err(err_spanned!(expr.span(), "invalid rename_in mapping"))
-                    }
+                        .collect::<Result<Vec<String>, Error>>()
                 })
-                .collect::<Result<BTreeMap<String, Origin>, Error>>()?
-                .into_iter()
-                .collect(),
-            ),
-        ));
-        Ok(())
+                .ok_or_else(|| err_spanned!(expr.span(), "expected s-expression"))??;
+            if pair.len() == 2 {
+                Ok((pair[0].clone(), Origin::new(pair[1].clone(), expr.span())))
+            } else {
+                Err(err_spanned!(expr.span(), "invalid rename_in mapping"))
+            }
+        })
+        .collect()
     }
 
     fn visit_provide(processed: &mut ProcessedFile, rest: impl Iterator<Item = &'i Expr>) -> Result<(), Error> {
         for provided in rest {
-            if let Some(name) = provided.stripped_symbol_value() {
+            Self::visit_provide_spec(processed, provided, &BTreeSet::new())?;
+        }
+        Ok(())
+    }
+
+    /// Visits a single provide spec. `excluded` holds the names that enclosing `except_out` forms leave out.
+    fn visit_provide_spec(
+        processed: &mut ProcessedFile,
+        provided: &'i Expr,
+        excluded: &BTreeSet<String>,
+    ) -> Result<(), Error> {
+        if let Some(name) = provided.stripped_symbol_value() {
+            if !excluded.contains(name) {
                 processed.provides.insert(name.into(), provided.span());
-            } else if let Expr::SExpr(sexpr) = provided {
-                let mut items = sexpr.item_iter();
-                if let Some(first_value) = items.next() {
-                    if let Some(function_call) = first_value.symbol_value() {
-                        match function_call.as_str() {
-                            "all_defined_out" => {
-                                processed.all_defined_out = true;
-                            }
-                            "rename_out" => Self::visit_rename_out(processed, first_value.span(), items)?,
-                            _ => return Err(err_spanned!(provided.span(), "expected all_defined_out or rename_out")),
-                        }
-                    } else {
-                        return Err(err_spanned!(provided.span(), "expected all_defined_out or rename_out"));
+                processed.provided_names.push(Origin::new(name.into(), provided.span()));
+            }
+        } else if let Expr::SExpr(sexpr) = provided {
+            let mut items = sexpr.item_iter();
+            let Some(first_value) = items.next() else {
+                return Err(err_spanned!(provided.span(), "unexpected s-expression"));
+            };
+            match first_value.symbol_value().map(String::as_str) {
+                Some("all_defined_out") => processed.all_defined_out.push(excluded.clone()),
+                Some("rename_out") => Self::visit_rename_out(processed, first_value.span(), items, excluded)?,
+                Some("except_out") => {
+                    let spec = items
+                        .next()
+                        .ok_or_else(|| err_spanned!(provided.span(), "except_out requires a provide spec"))?;
+                    let mut excluded = excluded.clone();
+                    for name in items {
+                        let name = name
+                            .stripped_symbol_value()
+                            .ok_or_else(|| err_spanned!(name.span(), "non-symbol found in except_out list"))?;
+                        excluded.insert(name.into());
                     }
-                } else {
-                    return Err(err_spanned!(provided.span(), "unexpected s-expression"));
+                    Self::visit_provide_spec(processed, spec, &excluded)?;
+                }
+                _ => {
+                    return Err(err_spanned!(
+                        provided.span(),
+                        "expected all_defined_out, rename_out or except_out"
+                    ));
                 }
             }
         }
@@ -374,36 +654,38 @@ impl<'i> FusionLoader<'i> {
     fn visit_rename_out(
         processed: &mut ProcessedFile,
         rename_out_span: ShortSpan,
-        mut rest: impl Iterator<Item = &'i Expr>,
+        rest: impl Iterator<Item = &'i Expr>,
+        excluded: &BTreeSet<String>,
     ) -> Result<(), Error> {
-        if let Some(Expr::SExpr(sexpr)) = rest.next() {
+        for pair in rest {
+            let Expr::SExpr(sexpr) = pair else {
+                return Err(err_spanned!(pair.span(), "rename_out expected s-expression"));
+            };
             let mut inner_itr = sexpr.item_iter();
-            let local_name = inner_itr
+            let local = inner_itr
                 .next()
-                .and_then(|expr| expr.symbol_value())
                 .ok_or_else(|| err_spanned!(rename_out_span, "rename_out requires a local name"))?;
-            let provided_name = inner_itr
+            let local_name = local
+                .symbol_value()
+                .ok_or_else(|| err_spanned!(local.span(), "rename_out requires a local name"))?;
+            let provided = inner_itr
                 .next()
-                .and_then(|expr| expr.symbol_value())
                 .ok_or_else(|| err_spanned!(rename_out_span, "rename_out requires a provided name"))?;
-
-            if let Some(defined) = processed.defined.get(local_name) {
-                processed.provides.insert(provided_name.into(), *defined);
-            } else if let Some(required) = processed
-                .requires
-                .iter()
-                .map(|require| (*require).find_origin(local_name))
-                .find(Option::is_some)
-                .flatten()
-            {
-                processed.provides.insert(provided_name.into(), required);
-            } else {
-                unimplemented!()
+            let provided_name = provided
+                .symbol_value()
+                .ok_or_else(|| err_spanned!(provided.span(), "rename_out requires a provided name"))?;
+            if !excluded.contains(provided_name) {
+                processed
+                    .provided_names
+                    .push(Origin::new(provided_name.clone(), provided.span()));
+                processed.renamed_out.push(RenameOut {
+                    local_name: local_name.clone(),
+                    local_span: local.span(),
+                    provided_name: provided_name.clone(),
+                });
             }
-            Ok(())
-        } else {
-            Err(err_spanned!(rename_out_span, "rename_out expected s-expression"))
         }
+        Ok(())
     }
 
     fn visit_define(processed: &mut ProcessedFile, mut rest: impl Iterator<Item = &'i Expr>) {
@@ -423,11 +705,15 @@ impl<'i> FusionLoader<'i> {
         if let Some(arg_list) = rest.next() {
             if let Some(name) = arg_list.symbol_value() {
                 processed.provides.insert(name.into(), arg_list.span());
+                processed.provided_names.push(Origin::new(name.into(), arg_list.span()));
             } else if let Some(sexpr_value) = arg_list.sexpr_value()
                 && let Some(first_arg) = sexpr_value.item_iter().next()
                 && let Some(name) = first_arg.symbol_value()
             {
                 processed.provides.insert(name.into(), first_arg.span());
+                processed
+                    .provided_names
+                    .push(Origin::new(name.into(), first_arg.span()));
             }
         }
     }
@@ -450,29 +736,110 @@ impl<'i> FusionLoader<'i> {
     }
 }
 
+/// Names provided by a module, mapped to where each binding originates
+type Provides = BTreeMap<String, ShortSpan>;
+
 struct ProcessedFile {
     language: Option<String>,
-    all_defined_out: bool,
+    /// The names excluded from each `(all_defined_out)`, which is resolved once every definition is known
+    all_defined_out: Vec<BTreeSet<String>>,
+    renamed_out: Vec<RenameOut>,
     defined: BTreeMap<String, ShortSpan>,
     requires: Vec<RequireForm>,
     provides: BTreeMap<String, ShortSpan>,
+    provided_names: Vec<Origin>,
 }
 
 impl ProcessedFile {
     fn new() -> ProcessedFile {
         ProcessedFile {
             language: None,
-            all_defined_out: false,
+            all_defined_out: Vec::new(),
+            renamed_out: Vec::new(),
             defined: BTreeMap::new(),
             requires: Vec::new(),
             provides: BTreeMap::new(),
+            provided_names: Vec::new(),
+        }
+    }
+
+    /// Resolves the provides that depend on the whole module body, so that a `provide` can come before
+    /// the definitions and requires it refers to
+    fn dissolve(mut self) -> Result<(String, Vec<RequireForm>, Provides, Vec<Origin>), Error> {
+        for (name, span) in &self.defined {
+            if self.all_defined_out.iter().any(|excluded| !excluded.contains(name)) {
+                self.provides.insert(name.clone(), *span);
+            }
+        }
+        for rename in &self.renamed_out {
+            let origin = self.defined.get(&rename.local_name).copied().or_else(|| {
+                self.requires
+                    .iter()
+                    .find_map(|require| require.find_origin(&rename.local_name))
+            });
+            let origin = origin.ok_or_else(|| {
+                err_spanned!(
+                    rename.local_span,
+                    "provided name `{}` is not defined or imported",
+                    rename.local_name
+                )
+            })?;
+            self.provides.insert(rename.provided_name.clone(), origin);
         }
+        Ok((
+            self.language.unwrap_or_default(),
+            self.requires,
+            self.provides,
+            self.provided_names,
+        ))
     }
+}
+
+/// A `(rename_out (local_name provided_name))` pair
+struct RenameOut {
+    local_name: String,
+    local_span: ShortSpan,
+    provided_name: String,
+}
+
+fn parse_module_file(config: &FusionConfig, file_path: &Path) -> Result<FusionFile, Error> {
+    FusionFile::load(config, file_path).map_err(|err| err_generic!("failed to load {:?}: {}", file_path, err))
+}
 
-    fn dissolve(mut self) -> (String, Vec<RequireForm>, BTreeMap<String, ShortSpan>) {
-        if self.all_defined_out {
-            self.provides.extend(self.defined);
+/// Returns the first error of the results of work that was done in parallel, so that which error is reported
+/// doesn't depend on which thread finished first
+fn first_error<T>(results: Vec<Result<T, Error>>) -> Result<Vec<T>, Error> {
+    results.into_iter().collect()
+}
+
+/// Finds the names of the modules that a file requires or uses as its language without processing it, so that
+/// modules can be processed after the modules they depend on
+fn required_module_names(file: &FusionFile) -> BTreeSet<String> {
+    fn visit(expr: &Expr, in_require: bool, names: &mut BTreeSet<String>) {
+        if in_require && let Some(name) = expr.string_value() {
+            names.insert(name.clone());
+        }
+        let Some(sexpr) = expr.sexpr_value() else {
+            return;
+        };
+        let function_call = sexpr.item_iter().next().and_then(Expr::symbol_value);
+        match function_call.map(String::as_str) {
+            Some("quote") => return,
+            Some("module") => {
+                if let Some(language) = sexpr.item_iter().nth(2).and_then(Expr::string_value) {
+                    names.insert(language.clone());
+                }
+            }
+            _ => {}
         }
-        (self.language.unwrap_or_default(), self.requires, self.provides)
+        let in_require = in_require || function_call.is_some_and(|name| name == "require");
+        for item in sexpr.item_iter() {
+            visit(item, in_require, names);
+        }
+    }
+    let mut names = BTreeSet::new();
+    for expr in &file.ast {
+        visit(expr, false, &mut names);
     }
+    names
 }
@@ -0,0 +1,389 @@
+// Copyright Ion Fusion contributors. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+use crate::index::{
+    FusionIndex, FusionIndexCell, Module, ModuleCell, Origin, RequireForm, RequireType, Resolution, Resolutions,
+    Script, ScriptCell,
+};
+use fuusak::ast::Expr;
+use fuusak::config::FusionConfig;
+use fuusak::error::Error;
+use fuusak::file::FusionFile;
+use fuusak::span::ShortSpan;
+use fuusak_err_macro::err_generic;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File, Metadata};
+use std::hash::{Hash, Hasher};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use xxhash_rust::xxh3::{Xxh3, xxh3_64};
+
+pub const INDEX_CACHE_FILE_NAME: &str = ".fuusak-index-cache";
+/// Bumped whenever what's cached changes, so that caches in an older format are thrown away
+const INDEX_CACHE_VERSION: u32 = 2;
+
+/// Remembers the modules and scripts of a package's index, so that the next run only needs to load the files
+/// that changed since, along with the modules and scripts that depend on them.
+///
+/// The cache is thrown away as a whole if it was written by another fuusak version, or with a different config
+/// or binding catalog.
+pub struct IndexCache {
+    path: Option<PathBuf>,
+    key: u64,
+    modules: BTreeMap<String, CachedModule>,
+    scripts: BTreeMap<String, CachedScript>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheContents {
+    version: u32,
+    fuusak_version: String,
+    key: u64,
+    modules: Vec<CachedModule>,
+    scripts: Vec<CachedScript>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedFile {
+    file_name: PathBuf,
+    /// Modification time in nanoseconds since the epoch, which saves hashing files that weren't touched
+    modified: u64,
+    length: u64,
+    hash: u64,
+    contents: String,
+    ast: Vec<Expr>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedRequire {
+    module: String,
+    required: RequireType,
+    span: ShortSpan,
+    form_span: ShortSpan,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedModule {
+    name: String,
+    language: String,
+    file: CachedFile,
+    requires: Vec<CachedRequire>,
+    provides: BTreeMap<String, ShortSpan>,
+    provided_names: Vec<Origin>,
+    /// Resolutions are keyed by span, which JSON objects can't be
+    resolutions: Vec<(ShortSpan, Resolution)>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedScript {
+    name: String,
+    top_level_modules: Vec<String>,
+    global_bindings: Vec<String>,
+    files: Vec<CachedFile>,
+    requires: Vec<CachedRequire>,
+    resolutions: Vec<Vec<(ShortSpan, Resolution)>>,
+}
+
+impl IndexCache {
+    /// Loads the cache stored in `package_path`. A missing, unreadable, or stale cache results in an empty one.
+    pub fn load(package_path: &Path, fusion_config: &FusionConfig) -> IndexCache {
+        let path = package_path.join(INDEX_CACHE_FILE_NAME);
+        let key = cache_key(package_path, fusion_config);
+        let contents = File::open(&path)
+            .ok()
+            .and_then(|file| serde_json::from_reader::<_, CacheContents>(BufReader::new(file)).ok())
+            .filter(|contents| {
+                contents.version == INDEX_CACHE_VERSION
+                    && contents.fuusak_version == env!("CARGO_PKG_VERSION")
+                    && contents.key == key
+            });
+        let (modules, scripts) = contents.map_or_else(Default::default, |contents| {
+            (
+                contents
+                    .modules
+                    .into_iter()
+                    .map(|module| (module.name.clone(), module))
+                    .collect(),
+                contents
+                    .scripts
+                    .into_iter()
+                    .map(|script| (script.name.clone(), script))
+                    .collect(),
+            )
+        });
+        IndexCache {
+            path: Some(path),
+            key,
+            modules,
+            scripts,
+        }
+    }
+
+    /// Creates a cache that never restores anything and is never saved
+    pub fn disabled() -> IndexCache {
+        IndexCache {
+            path: None,
+            key: 0,
+            modules: BTreeMap::new(),
+            scripts: BTreeMap::new(),
+        }
+    }
+
+    /// Puts the cached modules and scripts into the index, unless their files changed or they depend on a module
+    /// that can't be restored. Everything that isn't restored is left for the loader to load as usual.
+    /// Returns how many modules and scripts were restored.
+    pub fn restore(&self, fusion_index: &FusionIndexCell, fusion_config: &FusionConfig) -> (usize, usize) {
+        if self.modules.is_empty() && self.scripts.is_empty() {
+            return (0, 0);
+        }
+        let mut restorer = Restorer {
+            cache: self,
+            fusion_index,
+            package_path: fusion_index.read().current_package_path().to_path_buf(),
+            visiting: BTreeSet::new(),
+            stale: BTreeSet::new(),
+        };
+        let mut restored_modules = 0;
+        for name in self.modules.keys() {
+            if restorer.restore_module(name).is_some() {
+                restored_modules += 1;
+            }
+        }
+        let mut restored_scripts = 0;
+        for script in self.scripts.values() {
+            // Scripts are the package's tests, which always have the configured top-level modules
+            if script.top_level_modules == fusion_config.package.test_top_level_modules
+                && restorer.restore_script(script).is_some()
+            {
+                restored_scripts += 1;
+            }
+        }
+        (restored_modules, restored_scripts)
+    }
+
+    /// Writes every module and script of the index that was loaded from a file to the cache. Files that changed
+    /// since they were loaded are left out, and so they'll be loaded again next time.
+    pub fn save(&self, fusion_index: &FusionIndex) -> Result<(), Error> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let package_path = fusion_index.current_package_path();
+        let mut modules = Vec::new();
+        for module in fusion_index.module_iter() {
+            if fusion_index.is_stub_module(module) {
+                continue;
+            }
+            let Some(file) = cache_file(package_path, &module.file) else {
+                continue;
+            };
+            modules.push(CachedModule {
+                name: module.name.clone(),
+                language: module.language.clone(),
+                file,
+                requires: cache_requires(&module.requires),
+                provides: module.provides.clone(),
+                provided_names: module.provided_names.clone(),
+                resolutions: cache_resolutions(&module.resolutions),
+            });
+        }
+        let mut scripts = Vec::new();
+        for script in fusion_index.script_iter() {
+            let Some(files) = script
+                .files
+                .iter()
+                .map(|file| cache_file(package_path, file))
+                .collect::<Option<Vec<CachedFile>>>()
+            else {
+                continue;
+            };
+            scripts.push(CachedScript {
+                name: script.name.clone(),
+                top_level_modules: script.top_level_modules.clone(),
+                global_bindings: script.global_bindings.clone(),
+                files,
+                requires: cache_requires(&script.requires),
+                resolutions: script.resolutions.iter().map(cache_resolutions).collect(),
+            });
+        }
+
+        let contents = CacheContents {
+            version: INDEX_CACHE_VERSION,
+            fuusak_version: env!("CARGO_PKG_VERSION").into(),
+            key: self.key,
+            modules,
+            scripts,
+        };
+        let file =
+            File::create(path).map_err(|err| err_generic!("Failed to write cache {}: {}", path.display(), err))?;
+        serde_json::to_writer(BufWriter::new(file), &contents)
+            .map_err(|err| err_generic!("Failed to write cache {}: {}", path.display(), err))
+    }
+}
+
+/// Rebuilds modules and scripts from the cache, dependencies first
+struct Restorer<'a> {
+    cache: &'a IndexCache,
+    fusion_index: &'a FusionIndexCell,
+    package_path: PathBuf,
+    /// Modules whose dependencies are being restored, which guards against cycles
+    visiting: BTreeSet<String>,
+    /// Modules that can't be restored
+    stale: BTreeSet<String>,
+}
+
+impl Restorer<'_> {
+    fn restore_module(&mut self, name: &str) -> Option<ModuleCell> {
+        if let Some(module) = self.fusion_index.read().get_module(&name.to_string()) {
+            return Some(module);
+        }
+        if self.stale.contains(name) || !self.visiting.insert(name.into()) {
+            return None;
+        }
+        let module = match self.cache.modules.get(name) {
+            Some(cached) => self.restore_cached_module(cached),
+            // Stub modules are never cached, but a file that now shadows one is new
+            None if self.fusion_index.read().find_module_file(name).is_none() => {
+                Some(self.fusion_index.write().get_stub_module(name))
+            }
+            None => None,
+        };
+        self.visiting.remove(name);
+        if module.is_none() {
+            self.stale.insert(name.into());
+        }
+        module
+    }
+
+    fn restore_cached_module(&mut self, cached: &CachedModule) -> Option<ModuleCell> {
+        // The module's file must still be the one that its name resolves to
+        let module_file = self.fusion_index.read().find_module_file(&cached.name)?;
+        let file_path = self.package_path.join(&cached.file.file_name);
+        if fs::canonicalize(module_file).ok()? != fs::canonicalize(&file_path).ok()? || !self.is_fresh(&cached.file) {
+            return None;
+        }
+        if !cached.language.is_empty() {
+            self.restore_module(&cached.language)?;
+        }
+        let requires = self.restore_requires(&cached.requires)?;
+        let module = Module::new(
+            cached.name.clone(),
+            cached.language.clone(),
+            restore_file(&cached.file),
+            requires,
+            cached.provides.clone(),
+            cached.provided_names.clone(),
+            cached.resolutions.iter().cloned().collect(),
+        );
+        self.fusion_index.write().put_module(module.clone());
+        Some(module)
+    }
+
+    fn restore_script(&mut self, cached: &CachedScript) -> Option<ScriptCell> {
+        if !cached.files.iter().all(|file| self.is_fresh(file)) {
+            return None;
+        }
+        for module in &cached.top_level_modules {
+            self.restore_module(module)?;
+        }
+        let requires = self.restore_requires(&cached.requires)?;
+        let script = Script::new(
+            cached.name.clone(),
+            cached.top_level_modules.clone(),
+            cached.global_bindings.clone(),
+            cached.files.iter().map(restore_file).collect(),
+            requires,
+            cached
+                .resolutions
+                .iter()
+                .map(|resolutions| resolutions.iter().cloned().collect::<Resolutions>())
+                .collect(),
+        );
+        self.fusion_index.write().put_script(script.clone());
+        Some(script)
+    }
+
+    fn restore_requires(&mut self, requires: &[CachedRequire]) -> Option<Vec<RequireForm>> {
+        requires
+            .iter()
+            .map(|require| {
+                Some(RequireForm::new(
+                    self.restore_module(&require.module)?,
+                    require.required.clone(),
+                    require.span,
+                    require.form_span,
+                ))
+            })
+            .collect()
+    }
+
+    /// Returns true if the file is unchanged, going by its modification time and length, or failing that its hash
+    fn is_fresh(&self, file: &CachedFile) -> bool {
+        let path = self.package_path.join(&file.file_name);
+        let Ok(metadata) = fs::metadata(&path) else {
+            return false;
+        };
+        if metadata.len() == file.length && modified_nanos(&metadata) == Some(file.modified) {
+            return true;
+        }
+        fs::read_to_string(&path).is_ok_and(|contents| xxh3_64(contents.as_bytes()) == file.hash)
+    }
+}
+
+/// Hashes what, besides a file's contents, affects how it's indexed
+fn cache_key(package_path: &Path, fusion_config: &FusionConfig) -> u64 {
+    let mut hasher = Xxh3::new();
+    fusion_config.hash(&mut hasher);
+    let catalog = &fusion_config.package.catalog;
+    if !catalog.is_empty() {
+        fs::read_to_string(package_path.join(catalog))
+            .unwrap_or_default()
+            .hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+fn modified_nanos(metadata: &Metadata) -> Option<u64> {
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    u64::try_from(modified.as_nanos()).ok()
+}
+
+/// Returns None if the file on disk no longer has the contents that were indexed
+fn cache_file(package_path: &Path, file: &FusionFile) -> Option<CachedFile> {
+    let path = package_path.join(&file.file_name);
+    let metadata = fs::metadata(&path).ok()?;
+    if fs::read_to_string(&path).ok()? != file.contents {
+        return None;
+    }
+    Some(CachedFile {
+        file_name: file.file_name.clone(),
+        modified: modified_nanos(&metadata)?,
+        length: metadata.len(),
+        hash: xxh3_64(file.contents.as_bytes()),
+        contents: file.contents.clone(),
+        ast: file.ast.clone(),
+    })
+}
+
+fn restore_file(file: &CachedFile) -> FusionFile {
+    FusionFile::new(file.file_name.clone(), file.contents.clone(), file.ast.clone())
+}
+
+fn cache_requires(requires: &[RequireForm]) -> Vec<CachedRequire> {
+    requires
+        .iter()
+        .map(|require| CachedRequire {
+            module: require.module.name.clone(),
+            required: require.required.clone(),
+            span: require.span,
+            form_span: require.form_span,
+        })
+        .collect()
+}
+
+fn cache_resolutions(resolutions: &Resolutions) -> Vec<(ShortSpan, Resolution)> {
+    resolutions
+        .iter()
+        .map(|(span, resolution)| (*span, resolution.clone()))
+        .collect()
+}
@@ -2,28 +2,93 @@
 // SPDX-License-Identifier: Apache-2.0
 use fuusak::config::FusionConfig;
 use fuusak::error::Error;
-use std::path::Path;
+use fuusak_err_macro::err_generic;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
+mod catalog;
 mod fusion_index;
 mod fusion_loader;
+mod index_cache;
 mod module;
+mod resolver;
 mod script;
 
+pub use catalog::*;
 pub use fusion_index::*;
 pub use fusion_loader::*;
+pub use index_cache::*;
 pub use module::*;
+pub use resolver::*;
 pub use script::*;
 
-pub fn load_index(fusion_config: &FusionConfig, package_path: &Path) -> Result<FusionIndexCell, Error> {
-    let mut paths = Vec::new();
-    let module_path = package_path.join("fusion/src");
-    if module_path.exists() {
-        paths.push(module_path);
+/// Indexes the modules and tests of the package at `package_path`, as laid out by the `[package]` config.
+/// If `use_cache` is set, whatever is unchanged since the last run is restored from the package's index cache,
+/// and the cache is updated afterwards. If `silent` is set, nothing is printed besides warnings. If `allow_cycles`
+/// is set, modules that require themselves in a cycle are loaded anyway (see [`FusionLoader::allow_cycles`]).
+pub fn load_index(
+    fusion_config: &FusionConfig,
+    package_path: &Path,
+    use_cache: bool,
+    silent: bool,
+    allow_cycles: bool,
+) -> Result<FusionIndexCell, Error> {
+    let package = &fusion_config.package;
+    let mut paths: Vec<PathBuf> = package
+        .module_roots
+        .iter()
+        .map(|root| package_path.join(root))
+        .filter(|path| path.exists())
+        .collect();
+    // Repositories come after the package's own roots, so that the package's modules shadow theirs
+    for repository in &package.repositories {
+        let path = package_path.join(repository);
+        if !path.is_dir() {
+            return Err(err_generic!("module repository {} doesn't exist", path.display()));
+        }
+        paths.push(path);
     }
 
-    let fusion_index = FusionIndex::new(package_path, paths)?;
-    let fusion_loader = FusionLoader::new(fusion_config, &fusion_index);
+    let catalog = Catalog::load(package, package_path)?;
+    let fusion_index = FusionIndex::new(package_path, paths, catalog)?;
+    if !silent {
+        println!("Module repository initialized with paths:");
+        for path in fusion_index.read().module_paths() {
+            println!("  {}", path.display());
+        }
+    }
+    let start = Instant::now();
+    let cache = match use_cache {
+        true => IndexCache::load(package_path, fusion_config),
+        false => IndexCache::disabled(),
+    };
+    let (restored_modules, restored_scripts) = cache.restore(&fusion_index, fusion_config);
+    if !silent && (restored_modules, restored_scripts) != (0, 0) {
+        println!("Restored {restored_modules} module(s) and {restored_scripts} test(s) from the index cache.");
+    }
+    let mut fusion_loader = FusionLoader::new(fusion_config, &fusion_index);
+    if silent {
+        fusion_loader = fusion_loader.silent();
+    }
+    if allow_cycles {
+        fusion_loader = fusion_loader.allow_cycles();
+    }
     fusion_loader.load_configured_paths(fusion_config)?;
+    if !silent {
+        let index = fusion_index.read();
+        println!(
+            "Indexed {} module(s) and {} test(s) in {:.2?}.",
+            index.module_iter().count(),
+            index.script_iter().count(),
+            start.elapsed()
+        );
+    }
+    // Modules in a cycle were resolved against stubs, and restoring them would hide the cycle from other commands
+    if !allow_cycles {
+        cache
+            .save(&fusion_index.read())
+            .unwrap_or_else(|err| eprintln!("{err}"));
+    }
 
     Ok(fusion_index)
 }
@@ -33,7 +98,7 @@ mod test {
     use super::*;
     use crate::diff_util::human_diff_lines;
     use fuusak::config::new_default_config;
-    use std::path::PathBuf;
+    use tempfile::TempDir;
 
     #[test]
     fn bootstrap_test() {
@@ -41,6 +106,7 @@ mod test {
         let fusion_index = FusionIndex::new(
             &PathBuf::from("./"),
             vec![PathBuf::from("index_tests/bootstrap/test_files")],
+            Catalog::bundled(),
         )
         .unwrap();
 
@@ -59,4 +125,323 @@ mod test {
             panic!("{}", msg);
         }
     }
+
+    #[test]
+    fn package_repositories() {
+        let mut config = new_default_config();
+        config.package.module_roots = vec!["missing".into()];
+        config.package.test_roots = Vec::new();
+        config.package.repositories = vec!["test_files".into()];
+        let package_path = Path::new("index_tests/bootstrap");
+        let fusion_index = load_index(&config, package_path, false, false, false).unwrap();
+        // Modules in repositories are only indexed when they're required
+        assert_eq!(0, fusion_index.read().module_iter().count());
+
+        let fusion_loader = FusionLoader::new(&config, &fusion_index);
+        let module = fusion_loader.load_module("/some_other_mod").unwrap();
+        assert_eq!("/some_other_mod", module.name);
+        assert!(fusion_index.read().get_module(&"/bootstrap/base".into()).is_some());
+        // Modules that can't be found on disk are stubbed from the binding catalog
+        let fusion = fusion_loader.load_module("/fusion").unwrap();
+        assert!(fusion.provides.contains_key("define"));
+        assert!(fusion_index.read().is_stub_module(&fusion));
+        // Modules that aren't in the catalog either get a stub without any bindings
+        let unknown = fusion_loader.load_module("/no/such/module").unwrap();
+        assert!(unknown.provides.is_empty());
+        assert!(fusion_index.read().is_unknown_module(&unknown));
+        assert!(!fusion_index.read().is_unknown_module(&fusion));
+
+        config.package.repositories.push("nonexistent".into());
+        let err = load_index(&config, package_path, false, false, false).err().unwrap();
+        assert!(err.to_string().contains("module repository"), "{err}");
+    }
+
+    #[test]
+    fn require_and_provide_forms() {
+        let config = new_default_config();
+        let fusion_index = FusionIndex::new(
+            &PathBuf::from("./"),
+            vec![PathBuf::from("index_tests/require_forms/test_files")],
+            Catalog::bundled(),
+        )
+        .unwrap();
+        let fusion_loader = FusionLoader::new(&config, &fusion_index);
+        let module = fusion_loader
+            .load_module_file("index_tests/require_forms/test_files/user.fusion")
+            .unwrap_or_else(|err| panic!("\n{err}"));
+
+        let [prefixed, except, renamed, for_syntax] = &module.requires[..] else {
+            panic!("expected four require forms, found {:?}", module.requires);
+        };
+        assert!(prefixed.find_origin("p_a").is_some());
+        assert!(prefixed.find_origin("a").is_none());
+        assert!(prefixed.find_origin("p_c").is_none());
+        assert!(except.find_origin("a").is_none());
+        assert!(except.find_origin("b").is_some());
+        assert!(renamed.find_origin("cc").is_some());
+        assert!(renamed.find_origin("c").is_none());
+        assert!(renamed.find_origin("a").is_some());
+        assert!(matches!(for_syntax.required, RequireType::ForSyntax(_)));
+
+        let provided: Vec<&str> = module.provides.keys().map(String::as_str).collect();
+        assert_eq!(vec!["pa", "visible"], provided);
+
+        let module = fusion_loader
+            .load_module_file("index_tests/require_forms/test_files/defining_forms.fusion")
+            .unwrap_or_else(|err| panic!("\n{err}"));
+        let provided: Vec<&str> = module.provides.keys().map(String::as_str).collect();
+        assert_eq!(vec!["java_procedure", "java_syntax", "value"], provided);
+    }
+
+    #[test]
+    fn provides_resolved_after_module_body() {
+        let config = new_default_config();
+        let fusion_index = FusionIndex::new(
+            &PathBuf::from("./"),
+            vec![PathBuf::from("index_tests/require_forms/test_files")],
+            Catalog::bundled(),
+        )
+        .unwrap();
+        let fusion_loader = FusionLoader::new(&config, &fusion_index);
+        let module = fusion_loader
+            .load_module_file("index_tests/require_forms/test_files/provide_first.fusion")
+            .unwrap_or_else(|err| panic!("\n{err}"));
+        let provided: Vec<String> = module.provides.keys().cloned().collect();
+        assert_eq!(vec!["imported_later", "renamed_later"], provided);
+
+        let err = fusion_loader
+            .load_module_file("index_tests/require_forms/test_files/unknown_provide.fusion")
+            .err()
+            .unwrap();
+        assert!(
+            err.to_string()
+                .contains("provided name `nope` is not defined or imported"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn circular_requires() {
+        let config = new_default_config();
+        let fusion_index = FusionIndex::new(
+            &PathBuf::from("./"),
+            vec![PathBuf::from("index_tests/cycle/test_files")],
+            Catalog::bundled(),
+        )
+        .unwrap();
+        let fusion_loader = FusionLoader::new(&config, &fusion_index);
+        let err = fusion_loader.load_module("/a").err().unwrap().to_string();
+        assert!(err.contains("circular module dependency: /a → /b → /c → /a"), "{err}");
+        assert!(
+            err.contains("/a requires /b at index_tests/cycle/test_files/a.fusion:2:12"),
+            "{err}"
+        );
+        assert!(
+            err.contains("/b requires /c at index_tests/cycle/test_files/b.fusion:3:12"),
+            "{err}"
+        );
+        assert!(
+            err.contains("/c requires /a at index_tests/cycle/test_files/c.fusion:2:12"),
+            "{err}"
+        );
+        assert!(fusion_index.read().get_module(&"/a".into()).is_none());
+
+        // Loading the whole package at once finds the same cycle
+        let mut config = new_default_config();
+        config.package.module_roots = vec!["test_files".into()];
+        config.package.test_roots = Vec::new();
+        let err = load_index(&config, Path::new("index_tests/cycle"), false, false, false)
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(err.contains("circular module dependency: /a → /b → /c → /a"), "{err}");
+    }
+
+    #[test]
+    fn transitive_dependents() {
+        let config = new_default_config();
+        let fusion_index = FusionIndex::new(
+            &PathBuf::from("./"),
+            vec![PathBuf::from("index_tests/dependents/test_files")],
+            Catalog::bundled(),
+        )
+        .unwrap();
+        let fusion_loader = FusionLoader::new(&config, &fusion_index);
+        let top = fusion_loader
+            .load_module("/top")
+            .unwrap_or_else(|err| panic!("\n{err}"));
+        let expected = vec![Dependent::Module("/mid".into()), Dependent::Module("/top".into())];
+        assert_eq!(expected, fusion_index.read().transitive_dependents("/base"));
+        assert!(fusion_index.read().transitive_dependents("/top").is_empty());
+
+        // Reloading a module replaces its dependency edges rather than adding to them
+        fusion_loader
+            .reload_module_file("/mid".into(), Path::new("index_tests/dependents/test_files/mid.fusion"))
+            .unwrap();
+        assert_eq!(expected, fusion_index.read().transitive_dependents("/base"));
+        let missing: Vec<String> = top.requires[2]
+            .missing_names()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(vec!["removed_value"], missing);
+    }
+
+    #[test]
+    fn removed_modules() {
+        let config = new_default_config();
+        let fusion_index = FusionIndex::new(
+            &PathBuf::from("./"),
+            vec![PathBuf::from("index_tests/dependents/test_files")],
+            Catalog::bundled(),
+        )
+        .unwrap();
+        let fusion_loader = FusionLoader::new(&config, &fusion_index);
+        fusion_loader
+            .load_module("/top")
+            .unwrap_or_else(|err| panic!("\n{err}"));
+
+        // What depended on a removed module is still known, so that it can be rechecked
+        assert!(fusion_index.write().remove_module("/mid").is_some());
+        assert!(fusion_index.read().get_module(&"/mid".into()).is_none());
+        assert_eq!(
+            vec![Dependent::Module("/top".into())],
+            fusion_index.read().transitive_dependents("/mid")
+        );
+        assert_eq!(
+            vec![Dependent::Module("/top".into())],
+            fusion_index.read().transitive_dependents("/base")
+        );
+
+        // Modules that can't be found anymore are stubbed, and reported as missing since they'd be in the package
+        let orphan = fusion_loader
+            .load_module("/orphan")
+            .unwrap_or_else(|err| panic!("\n{err}"));
+        let removed = &orphan.requires[1].module;
+        assert_eq!("/removed", removed.name);
+        assert!(fusion_index.read().is_unknown_module(removed));
+        assert!(fusion_index.read().is_missing_module(removed));
+        assert!(!fusion_index.read().is_missing_module(&orphan.requires[2].module));
+    }
+
+    #[test]
+    fn index_cache() {
+        let temp_dir = TempDir::new().unwrap();
+        let directory = temp_dir.path();
+        let source_path = directory.join("src");
+        std::fs::create_dir_all(&source_path).unwrap();
+        for name in ["base", "mid", "top"] {
+            let file_name = format!("{name}.fusion");
+            std::fs::copy(
+                Path::new("index_tests/dependents/test_files").join(&file_name),
+                source_path.join(&file_name),
+            )
+            .unwrap();
+        }
+        let mut config = new_default_config();
+        config.package.module_roots = vec!["src".into()];
+        config.package.test_roots = Vec::new();
+        let loaded = load_index(&config, directory, true, false, false).unwrap();
+
+        let restore = |config: &FusionConfig| {
+            let fusion_index = FusionIndex::new(directory, vec![source_path.clone()], Catalog::bundled()).unwrap();
+            IndexCache::load(directory, config).restore(&fusion_index, config);
+            fusion_index
+        };
+        let file_modules = |fusion_index: &FusionIndexCell| -> Vec<String> {
+            let fusion_index = fusion_index.read();
+            fusion_index
+                .module_iter()
+                .filter(|module| !fusion_index.is_stub_module(module))
+                .map(|module| module.name.clone())
+                .collect()
+        };
+        let restored = restore(&config);
+        assert_eq!(vec!["/base", "/mid", "/top"], file_modules(&restored));
+        assert_eq!(format!("{:?}", loaded.read()), format!("{:?}", restored.read()));
+        assert_eq!(
+            loaded.read().transitive_dependents("/base"),
+            restored.read().transitive_dependents("/base")
+        );
+
+        // Changing a module invalidates it along with its dependents
+        std::fs::write(
+            source_path.join("mid.fusion"),
+            "(module mid \"/fusion\"\n  (require \"/fusion/experimental/defpub\" \"/base\")\n  (defpub (mid_value) 2))",
+        )
+        .unwrap();
+        assert_eq!(vec!["/base"], file_modules(&restore(&config)));
+
+        // So does changing the config
+        config.indent_width += 1;
+        assert!(file_modules(&restore(&config)).is_empty());
+    }
+
+    /// Writes a package of `modules` modules, each of which requires up to three others, and `tests` test scripts
+    fn generate_package(directory: &Path, modules: usize, tests: usize) {
+        let module_path = directory.join("src/pkg");
+        let test_path = directory.join("test");
+        std::fs::create_dir_all(&module_path).unwrap();
+        std::fs::create_dir_all(&test_path).unwrap();
+        for index in 0..modules {
+            let mut dependencies: Vec<usize> = [0, index.wrapping_sub(1), index.wrapping_sub(2)]
+                .into_iter()
+                .filter(|dependency| *dependency < index)
+                .collect();
+            dependencies.dedup();
+            let mut source = format!("(module m{index} \"/fusion\"\n");
+            for dependency in &dependencies {
+                source.push_str(&format!("  (require \"/pkg/m{dependency}\")\n"));
+            }
+            for value in 0..20 {
+                let calls: String = dependencies
+                    .iter()
+                    .map(|dependency| format!(" (v{dependency}_{})", value % 5))
+                    .collect();
+                source.push_str(&format!(
+                    "  (define (v{index}_{value} x)\n    \"Docs for v{index}_{value}.\"\n    \
+                     (let [(y (+ x 1))]\n      (if (> y 3) (+ y{calls}) (* y 2))))\n"
+                ));
+            }
+            let provided: Vec<String> = (0..20).map(|value| format!("v{index}_{value}")).collect();
+            source.push_str(&format!("  (provide {}))\n", provided.join(" ")));
+            std::fs::write(module_path.join(format!("m{index}.fusion")), source).unwrap();
+        }
+        for index in 0..tests {
+            let required = index * 7 % modules;
+            std::fs::write(
+                test_path.join(format!("t{index}.fusion")),
+                format!("(require \"/pkg/m{required}\")\n(v{required}_0 1)\n"),
+            )
+            .unwrap();
+        }
+    }
+
+    /// Times indexing a generated package without the index cache. Run it with
+    /// `cargo test --release -- --ignored --nocapture load_benchmark`, setting `RAYON_NUM_THREADS` to compare
+    /// thread counts.
+    #[test]
+    #[ignore]
+    fn load_benchmark() {
+        let temp_dir = TempDir::new().unwrap();
+        let directory = temp_dir.path();
+        generate_package(directory, 1500, 200);
+        let mut config = new_default_config();
+        config.package.module_roots = vec!["src".into()];
+        config.package.test_roots = vec!["test".into()];
+
+        let start = Instant::now();
+        let fusion_index = load_index(&config, directory, false, true, false).unwrap();
+        let elapsed = start.elapsed();
+        let fusion_index = fusion_index.read();
+        let modules = fusion_index
+            .module_iter()
+            .filter(|module| !fusion_index.is_stub_module(module))
+            .count();
+        assert_eq!((1500, 200), (modules, fusion_index.script_iter().count()));
+        println!(
+            "Indexed {modules} modules and 200 tests in {elapsed:.2?} with {} thread(s)",
+            rayon::current_num_threads()
+        );
+    }
 }
@@ -1,57 +1,248 @@
 // Copyright Ion Fusion contributors. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
+use crate::index::Resolutions;
 use derive_new::new;
 use fuusak::file::FusionFile;
 use fuusak::span::ShortSpan;
-use std::cell::RefCell;
-use std::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{self, Debug};
-use std::rc::Rc;
+use std::sync::Arc;
 
-pub type ModuleCell = Rc<RefCell<Module>>;
+/// Modules are never changed once loaded (reloading one replaces it in the index), so they can be shared
+/// between threads.
+pub type ModuleCell = Arc<Module>;
 
-#[derive(new, Debug)]
+#[derive(new, Clone, Debug, Serialize, Deserialize)]
 pub struct Origin {
     pub name: String,
     pub originates_from: ShortSpan,
 }
 
-#[derive(new, Debug)]
+/// The bindings that a require spec imports from its module. Specs that restrict or rename bindings wrap
+/// the spec they apply to, so `(prefix_in p (only_in "/m" a))` is `Prefixed(Names(All, [a]), p)`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum RequireType {
     /// For `(require "module")`
     All,
-    /// For `(require (only_in ...))`
-    Names(Vec<Origin>),
-    /// For `(require (rename_in ...))` and `(require (prefix_in ...))`
-    Mapped(BTreeMap<String, Origin>),
+    /// For `(require (only_in SPEC name ...))`
+    Names(Box<RequireType>, Vec<Origin>),
+    /// For `(require (rename_in SPEC (name new_name) ...))`, which maps each name to its new name
+    Mapped(Box<RequireType>, BTreeMap<String, Origin>),
+    /// For `(require (except_in SPEC name ...))`
+    Except(Box<RequireType>, Vec<Origin>),
+    /// For `(require (prefix_in prefix SPEC))`
+    Prefixed(Box<RequireType>, Origin),
+    /// For `(require (for_syntax SPEC ...))`. Phases aren't tracked, so these resolve like any other import.
+    ForSyntax(Box<RequireType>),
+}
+
+impl RequireType {
+    /// Finds where the binding that `name` refers to originates, given the provides of the required module.
+    /// Names that are listed explicitly in the spec resolve to where they're listed.
+    fn find_origin(&self, provides: &BTreeMap<String, ShortSpan>, name: &str) -> Option<ShortSpan> {
+        match self {
+            RequireType::All => provides.get(name).copied(),
+            RequireType::Names(inner, names) => names
+                .iter()
+                .find(|origin| origin.name == name)
+                .filter(|_| inner.find_origin(provides, name).is_some())
+                .map(|origin| origin.originates_from),
+            RequireType::Mapped(inner, mapping) => {
+                if let Some((original, origin)) = mapping.iter().find(|(_, origin)| origin.name == name) {
+                    inner.find_origin(provides, original).map(|_| origin.originates_from)
+                } else if mapping.contains_key(name) {
+                    // Renamed bindings are no longer available under their original name
+                    None
+                } else {
+                    inner.find_origin(provides, name)
+                }
+            }
+            RequireType::Except(inner, names) => match names.iter().any(|origin| origin.name == name) {
+                true => None,
+                false => inner.find_origin(provides, name),
+            },
+            RequireType::Prefixed(inner, prefix) => name
+                .strip_prefix(prefix.name.as_str())
+                .and_then(|name| inner.find_origin(provides, name)),
+            RequireType::ForSyntax(inner) => inner.find_origin(provides, name),
+        }
+    }
+
+    /// Returns the name that the required module provides the binding imported as `name` under, undoing
+    /// any renames and prefixes. Mirrors `find_origin`.
+    fn original_name(&self, name: &str) -> Option<String> {
+        match self {
+            RequireType::All => Some(name.into()),
+            RequireType::Names(inner, names) => names
+                .iter()
+                .find(|origin| origin.name == name)
+                .and_then(|_| inner.original_name(name)),
+            RequireType::Mapped(inner, mapping) => {
+                if let Some((original, _)) = mapping.iter().find(|(_, origin)| origin.name == name) {
+                    inner.original_name(original)
+                } else if mapping.contains_key(name) {
+                    None
+                } else {
+                    inner.original_name(name)
+                }
+            }
+            RequireType::Except(inner, names) => match names.iter().any(|origin| origin.name == name) {
+                true => None,
+                false => inner.original_name(name),
+            },
+            RequireType::Prefixed(inner, prefix) => name
+                .strip_prefix(prefix.name.as_str())
+                .and_then(|name| inner.original_name(name)),
+            RequireType::ForSyntax(inner) => inner.original_name(name),
+        }
+    }
+
+    /// Finds where the spec lists `name` explicitly (in `only_in` or `rename_in`), if the spec imports it
+    /// from there, regardless of whether the required module provides it
+    fn listed_origin(&self, name: &str) -> Option<ShortSpan> {
+        match self {
+            RequireType::All => None,
+            RequireType::Names(inner, names) => names
+                .iter()
+                .find(|origin| origin.name == name)
+                .filter(|_| inner.original_name(name).is_some())
+                .map(|origin| origin.originates_from),
+            RequireType::Mapped(inner, mapping) => {
+                if let Some((original, origin)) = mapping.iter().find(|(_, origin)| origin.name == name) {
+                    inner.original_name(original).map(|_| origin.originates_from)
+                } else if mapping.contains_key(name) {
+                    None
+                } else {
+                    inner.listed_origin(name)
+                }
+            }
+            RequireType::Except(inner, names) => match names.iter().any(|origin| origin.name == name) {
+                true => None,
+                false => inner.listed_origin(name),
+            },
+            RequireType::Prefixed(inner, prefix) => name
+                .strip_prefix(prefix.name.as_str())
+                .and_then(|name| inner.listed_origin(name)),
+            RequireType::ForSyntax(inner) => inner.listed_origin(name),
+        }
+    }
+
+    /// Collects the names that the spec lists explicitly but that the spec it applies to doesn't import,
+    /// along with where each is listed
+    fn missing_names(&self, provides: &BTreeMap<String, ShortSpan>, missing: &mut Vec<(String, ShortSpan)>) {
+        match self {
+            RequireType::All => {}
+            RequireType::Names(inner, names) | RequireType::Except(inner, names) => {
+                inner.missing_names(provides, missing);
+                for origin in names {
+                    if inner.find_origin(provides, &origin.name).is_none() {
+                        missing.push((origin.name.clone(), origin.originates_from));
+                    }
+                }
+            }
+            RequireType::Mapped(inner, mapping) => {
+                inner.missing_names(provides, missing);
+                for (original, origin) in mapping {
+                    if inner.find_origin(provides, original).is_none() {
+                        missing.push((original.clone(), origin.originates_from));
+                    }
+                }
+            }
+            RequireType::Prefixed(inner, _) | RequireType::ForSyntax(inner) => inner.missing_names(provides, missing),
+        }
+    }
+
+    /// Returns every name that the spec imports, mapped to where its binding originates like `find_origin`
+    fn imports(&self, provides: &BTreeMap<String, ShortSpan>) -> BTreeMap<String, ShortSpan> {
+        match self {
+            RequireType::All => provides.clone(),
+            RequireType::Names(inner, names) => {
+                let imports = inner.imports(provides);
+                names
+                    .iter()
+                    .filter(|origin| imports.contains_key(&origin.name))
+                    .map(|origin| (origin.name.clone(), origin.originates_from))
+                    .collect()
+            }
+            RequireType::Mapped(inner, mapping) => {
+                let mut imports = inner.imports(provides);
+                for (original, origin) in mapping {
+                    if imports.remove(original).is_some() {
+                        imports.insert(origin.name.clone(), origin.originates_from);
+                    }
+                }
+                imports
+            }
+            RequireType::Except(inner, names) => {
+                let mut imports = inner.imports(provides);
+                for origin in names {
+                    imports.remove(&origin.name);
+                }
+                imports
+            }
+            RequireType::Prefixed(inner, prefix) => inner
+                .imports(provides)
+                .into_iter()
+                .map(|(name, origin)| (format!("{}{}", prefix.name, name), origin))
+                .collect(),
+            RequireType::ForSyntax(inner) => inner.imports(provides),
+        }
+    }
 }
 
 #[derive(new)]
 pub struct RequireForm {
     pub module: ModuleCell,
     pub required: RequireType,
+    /// The require spec
+    pub span: ShortSpan,
+    /// The `(require ...)` form that contains the spec
+    pub form_span: ShortSpan,
 }
 
 impl RequireForm {
-    pub fn find_origin(&self, name: &String) -> Option<ShortSpan> {
-        match &self.required {
-            RequireType::All => self.module.borrow().provides.get(name).copied(),
-            RequireType::Names(names) => names
-                .iter()
-                .find(|origin| &origin.name == name)
-                .map(|origin| origin.originates_from),
-            RequireType::Mapped(mapping) => mapping
-                .values()
-                .find(|origin| &origin.name == name)
-                .map(|origin| origin.originates_from),
+    pub fn find_origin(&self, name: &str) -> Option<ShortSpan> {
+        let origin = self.required.find_origin(&self.module.provides, name);
+        // The bindings of unknown modules aren't known, so names that are listed explicitly are trusted
+        match self.module.is_unknown() {
+            true => origin.or_else(|| self.required.listed_origin(name)),
+            false => origin,
+        }
+    }
+
+    /// Returns true if this require would import `name` if its module provided the binding it refers to
+    pub fn could_import(&self, name: &str) -> bool {
+        self.required.original_name(name).is_some()
+    }
+
+    /// Returns the name that this require's module provides the binding imported as `name` under
+    pub fn original_name(&self, name: &str) -> Option<String> {
+        self.required
+            .original_name(name)
+            .filter(|original| self.module.provides.contains_key(original))
+    }
+
+    /// Returns every name that this require imports, mapped to where its binding originates
+    pub fn imports(&self) -> BTreeMap<String, ShortSpan> {
+        self.required.imports(&self.module.provides)
+    }
+
+    /// Returns the names that this require lists (in `only_in`, `except_in` or `rename_in`) but that aren't
+    /// available from its module, along with where each is listed
+    pub fn missing_names(&self) -> Vec<(String, ShortSpan)> {
+        let mut missing = Vec::new();
+        if !self.module.is_unknown() {
+            self.required.missing_names(&self.module.provides, &mut missing);
         }
+        missing
     }
 }
 
 impl fmt::Debug for RequireForm {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("RequireForm")
-            .field("module", &self.module.borrow().name)
+            .field("module", &self.module.name)
             .field("required", &self.required)
             .finish()
     }
@@ -63,6 +254,9 @@ pub struct Module {
     pub file: FusionFile,
     pub requires: Vec<RequireForm>,
     pub provides: BTreeMap<String, ShortSpan>,
+    /// Each name that's explicitly provided, in order, including any that are provided more than once
+    pub provided_names: Vec<Origin>,
+    pub resolutions: Resolutions,
 }
 
 impl Module {
@@ -72,14 +266,42 @@ impl Module {
         file: FusionFile,
         requires: Vec<RequireForm>,
         provides: BTreeMap<String, ShortSpan>,
+        provided_names: Vec<Origin>,
+        resolutions: Resolutions,
     ) -> ModuleCell {
-        Rc::new(RefCell::new(Module {
+        Arc::new(Module {
             name,
             language,
             file,
             requires,
             provides,
-        }))
+            provided_names,
+            resolutions,
+        })
+    }
+}
+
+impl Module {
+    /// Returns true if the module wasn't loaded from a file, but stubbed from the binding catalog or because it
+    /// couldn't be found
+    pub fn is_stub(&self) -> bool {
+        self.file.contents.is_empty() && self.file.file_name == FusionFile::empty_file().file_name
+    }
+
+    /// Returns true if the module is a stub for a module that couldn't be found, so its bindings are unknown.
+    /// Stubs from the binding catalog list every binding, and always have a language.
+    pub fn is_unknown(&self) -> bool {
+        self.is_stub() && self.language.is_empty()
+    }
+
+    /// Names of the modules that this module requires, including its language
+    pub fn dependencies(&self) -> BTreeSet<String> {
+        let language = Some(self.language.clone()).filter(|language| !language.is_empty());
+        self.requires
+            .iter()
+            .map(|require| require.module.name.clone())
+            .chain(language)
+            .collect()
     }
 }
 
@@ -92,6 +314,7 @@ impl fmt::Debug for Module {
             // omit the file since the AST is huge and not particularly useful
             .field("requires", &self.requires)
             .field("provides", &self.provides)
+            // omit resolutions, which are keyed by span and only meaningful alongside the file
             .finish()
     }
 }
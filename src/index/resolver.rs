@@ -0,0 +1,525 @@
+// Copyright Ion Fusion contributors. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+use crate::index::{ModuleCell, RequireForm};
+use fuusak::ast::{Expr, ListData};
+use fuusak::span::ShortSpan;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Forms whose second item is the name being defined, or the name followed by parameters. Besides `define`,
+/// these also provide the name.
+pub const DEFINING_FORMS: &[&str] = &[
+    "define",
+    "define_syntax",
+    "defpub",
+    "defpub_j",
+    "defpub_j_syntax",
+    "defpub_syntax",
+];
+
+/// What a symbol occurrence refers to. Binding occurrences (parameters, `let` names, and defined names)
+/// resolve to themselves.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Resolution {
+    /// A parameter or a binding within a body, introduced at the given span
+    Local(ShortSpan),
+    /// A definition at the top level of the module or script, introduced at the given span
+    Module(ShortSpan),
+    /// A binding imported from `module`, where `origin` is where the binding originates. `require` is the
+    /// index of the require form it's imported through, or `None` if the file's language provides it.
+    Imported {
+        module: String,
+        origin: ShortSpan,
+        require: Option<usize>,
+    },
+    /// One of a script's global bindings
+    Global,
+    /// A name that isn't bound otherwise, but that `modules` might provide, since they're neither in the module
+    /// paths nor the binding catalog
+    Unknown {
+        modules: Vec<String>,
+    },
+    Unbound,
+}
+
+/// Resolutions of the symbol occurrences in a file, keyed by the span of each occurrence
+pub type Resolutions = BTreeMap<ShortSpan, Resolution>;
+
+/// Where the names that aren't bound within a file come from
+pub struct Imports<'a> {
+    pub requires: &'a [RequireForm],
+    /// The module's language, or a script's top-level modules, all of whose provides are available
+    pub languages: &'a [ModuleCell],
+    pub globals: &'a [String],
+}
+
+impl Imports<'_> {
+    fn resolve(&self, name: &str) -> Resolution {
+        for (index, require) in self.requires.iter().enumerate() {
+            if let Some(origin) = require.find_origin(name) {
+                let module = require.module.name.clone();
+                return Resolution::Imported {
+                    module,
+                    origin,
+                    require: Some(index),
+                };
+            }
+        }
+        for language in self.languages {
+            if let Some(origin) = language.provides.get(name) {
+                return Resolution::Imported {
+                    module: language.name.clone(),
+                    origin: *origin,
+                    require: None,
+                };
+            }
+        }
+        if self.globals.iter().any(|global| global == name) {
+            return Resolution::Global;
+        }
+        // Modules from the binding catalog provide exactly what it lists, but unknown modules might provide anything
+        let unknown_requires = self
+            .requires
+            .iter()
+            .filter(|require| require.module.is_unknown() && require.could_import(name))
+            .map(|require| &require.module);
+        let unknown_languages = self.languages.iter().filter(|language| language.is_unknown());
+        let modules: Vec<String> = unknown_requires
+            .chain(unknown_languages)
+            .map(|module| module.name.clone())
+            .collect();
+        match modules.is_empty() {
+            true => Resolution::Unbound,
+            false => Resolution::Unknown { modules },
+        }
+    }
+}
+
+/// Resolves every symbol occurrence in a file's AST, other than those in quoted data and in `require`
+/// specs, which name bindings of other modules. Provided names are references to the bindings they provide.
+pub fn resolve(ast: &[Expr], imports: &Imports<'_>) -> Resolutions {
+    let mut resolver = Resolver {
+        imports,
+        scopes: Vec::new(),
+        resolutions: Resolutions::new(),
+    };
+    match top_level(ast) {
+        (Some(module_form), _) => resolver.resolve_module_form(module_form),
+        (None, forms) => resolver.resolve_body(&forms, Resolution::Module),
+    }
+    resolver.resolutions
+}
+
+/// Returns the names that a file defines at its top level, in order, including any that are defined more than once
+pub fn top_level_definitions(ast: &[Expr]) -> Vec<&Expr> {
+    let (_, forms) = top_level(ast);
+    let mut definitions = Vec::new();
+    collect_definitions(&forms, &mut definitions);
+    definitions
+}
+
+/// Returns a file's module form, if it's a module file, along with the forms at its top level, which for a
+/// module file are the body of its module form
+fn top_level(ast: &[Expr]) -> (Option<&ListData>, Vec<&Expr>) {
+    let forms: Vec<&Expr> = ast.iter().filter(|expr| expr.is_value()).collect();
+    if let [form] = forms[..]
+        && let Some(sexpr) = form.sexpr_value()
+        && head_name(sexpr) == Some("module")
+    {
+        (Some(sexpr), sexpr.item_iter().skip(3).collect())
+    } else {
+        (None, forms)
+    }
+}
+
+fn head_name(sexpr: &ListData) -> Option<&str> {
+    sexpr.item_iter().next().and_then(Expr::stripped_symbol_value)
+}
+
+/// Returns the name being defined by a defining form, if `form` is one
+fn defined_name(form: &ListData) -> Option<&Expr> {
+    let mut items = form.item_iter();
+    let head = items.next()?.stripped_symbol_value()?;
+    if !DEFINING_FORMS.contains(&head) {
+        return None;
+    }
+    let target = items.next()?;
+    match target.sexpr_value() {
+        Some(signature) => signature.item_iter().next().filter(|name| name.is_symbol()),
+        None => Some(target).filter(|name| name.is_symbol()),
+    }
+}
+
+/// Collects the names that the forms of a body define, including within `begin` forms
+fn collect_definitions<'e>(forms: &[&'e Expr], definitions: &mut Vec<&'e Expr>) {
+    for form in forms {
+        let Some(sexpr) = form.sexpr_value() else { continue };
+        if let Some(name) = defined_name(sexpr) {
+            definitions.push(name);
+        } else if head_name(sexpr) == Some("begin") {
+            let nested: Vec<&Expr> = sexpr.item_iter().skip(1).collect();
+            collect_definitions(&nested, definitions);
+        }
+    }
+}
+
+struct Resolver<'a, 'i> {
+    imports: &'i Imports<'a>,
+    /// Names bound by each enclosing scope, innermost last
+    scopes: Vec<BTreeMap<String, Resolution>>,
+    resolutions: Resolutions,
+}
+
+impl Resolver<'_, '_> {
+    fn lookup(&self, name: &str) -> Resolution {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).cloned())
+            .unwrap_or_else(|| self.imports.resolve(name))
+    }
+
+    fn is_bound_in_file(&self, name: &str) -> bool {
+        self.scopes.iter().any(|scope| scope.contains_key(name))
+    }
+
+    fn reference(&mut self, symbol: &Expr) {
+        if let Some(name) = symbol.stripped_symbol_value() {
+            let resolution = self.lookup(name);
+            self.resolutions.insert(symbol.span(), resolution);
+        }
+    }
+
+    /// Binds `symbol` in the innermost scope, recording the binding occurrence as resolving to itself
+    fn bind(&mut self, symbol: &Expr, binding: fn(ShortSpan) -> Resolution) {
+        if let Some(name) = symbol.stripped_symbol_value() {
+            let resolution = binding(symbol.span());
+            self.resolutions.insert(symbol.span(), resolution.clone());
+            self.scopes
+                .last_mut()
+                .expect("a scope to bind in")
+                .insert(name.to_string(), resolution);
+        }
+    }
+
+    /// Resolves a sequence of forms in a new scope, in which the names they define are bound up front
+    /// so that definitions can refer to each other regardless of order.
+    fn resolve_body(&mut self, forms: &[&Expr], binding: fn(ShortSpan) -> Resolution) {
+        let mut definitions = Vec::new();
+        collect_definitions(forms, &mut definitions);
+        self.scopes.push(BTreeMap::new());
+        for name in definitions {
+            self.bind(name, binding);
+        }
+        for form in forms {
+            self.resolve_expr(form);
+        }
+        self.scopes.pop();
+    }
+
+    fn resolve_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Atomic(_) => self.reference(expr),
+            Expr::SExpr(data) => self.resolve_sexpr(data),
+            Expr::List(data) | Expr::Struct(data) => {
+                for item in data.item_iter() {
+                    self.resolve_expr(item);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn resolve_sexpr(&mut self, sexpr: &ListData) {
+        let items: Vec<&Expr> = sexpr.item_iter().collect();
+        let Some(head) = items.first() else { return };
+        // A locally bound name shadows the syntactic form it would otherwise be
+        let form = head.stripped_symbol_value().filter(|name| !self.is_bound_in_file(name));
+        match form {
+            Some("quote") | Some("require") => self.reference(head),
+            Some("provide") => {
+                self.reference(head);
+                for spec in &items[1..] {
+                    self.resolve_provide_spec(spec);
+                }
+            }
+            Some("quasiquote") => {
+                self.reference(head);
+                for item in &items[1..] {
+                    self.resolve_quasiquoted(item);
+                }
+            }
+            Some("module") => self.resolve_module_form(sexpr),
+            Some(name) if DEFINING_FORMS.contains(&name) => self.resolve_definition(&items),
+            Some("lambda") => {
+                self.reference(head);
+                self.scopes.push(BTreeMap::new());
+                if let Some(formals) = items.get(1) {
+                    match formals.sexpr_value().or_else(|| formals.list_value()) {
+                        Some(formals) => formals
+                            .item_iter()
+                            .for_each(|formal| self.bind(formal, Resolution::Local)),
+                        None => self.bind(formals, Resolution::Local),
+                    }
+                }
+                self.resolve_body(items.get(2..).unwrap_or_default(), Resolution::Local);
+                self.scopes.pop();
+            }
+            Some("|") => {
+                // `(|a b| body)`, where the parameters end at the next `|`
+                let end = items[1..]
+                    .iter()
+                    .position(|item| item.symbol_value().is_some_and(|value| value == "|"))
+                    .map_or(items.len(), |position| position + 1);
+                self.reference(head);
+                self.scopes.push(BTreeMap::new());
+                for formal in &items[1..end] {
+                    self.bind(formal, Resolution::Local);
+                }
+                self.resolve_body(items.get(end + 1..).unwrap_or_default(), Resolution::Local);
+                self.scopes.pop();
+            }
+            Some("||") => {
+                self.reference(head);
+                self.resolve_body(&items[1..], Resolution::Local);
+            }
+            Some("let") | Some("lets") | Some("letrec") | Some("let_values") => self.resolve_let(&items),
+            _ => {
+                for item in &items {
+                    self.resolve_expr(item);
+                }
+            }
+        }
+    }
+
+    fn resolve_module_form(&mut self, sexpr: &ListData) {
+        let mut items = sexpr.item_iter();
+        if let Some(head) = items.next() {
+            self.reference(head);
+        }
+        // The module's name and language aren't references
+        let body: Vec<&Expr> = items.skip(2).collect();
+        self.resolve_body(&body, Resolution::Module);
+    }
+
+    /// Resolves the local names in a provide spec, skipping the exported names of `rename_out` and the names
+    /// excluded by `except_out`
+    fn resolve_provide_spec(&mut self, spec: &Expr) {
+        let Some(sexpr) = spec.sexpr_value() else {
+            self.reference(spec);
+            return;
+        };
+        let mut items = sexpr.item_iter();
+        let Some(head) = items.next() else { return };
+        self.reference(head);
+        match head.stripped_symbol_value() {
+            Some("rename_out") => {
+                for pair in items.filter_map(Expr::sexpr_value) {
+                    if let Some(local) = pair.item_iter().next() {
+                        self.reference(local);
+                    }
+                }
+            }
+            Some("except_out") => {
+                if let Some(spec) = items.next() {
+                    self.resolve_provide_spec(spec);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn resolve_quasiquoted(&mut self, expr: &Expr) {
+        let Some(data) = expr
+            .sexpr_value()
+            .or_else(|| expr.list_value())
+            .or_else(|| expr.struct_value())
+        else {
+            return;
+        };
+        let mut items = data.item_iter();
+        if expr.sexpr_value().is_some()
+            && let Some(head) = items.next()
+        {
+            if head.stripped_symbol_value() == Some("unquote") {
+                self.reference(head);
+                items.for_each(|item| self.resolve_expr(item));
+                return;
+            }
+            self.resolve_quasiquoted(head);
+        }
+        items.for_each(|item| self.resolve_quasiquoted(item));
+    }
+
+    /// Resolves a defining form, whose name was already bound by the enclosing body
+    fn resolve_definition(&mut self, items: &[&Expr]) {
+        self.reference(items[0]);
+        let Some(target) = items.get(1) else { return };
+        let body = items.get(2..).unwrap_or_default();
+        match target.sexpr_value() {
+            Some(signature) => {
+                let mut signature = signature.item_iter();
+                if let Some(name) = signature.next() {
+                    self.reference(name);
+                }
+                self.scopes.push(BTreeMap::new());
+                signature.for_each(|formal| self.bind(formal, Resolution::Local));
+                self.resolve_body(body, Resolution::Local);
+                self.scopes.pop();
+            }
+            None => {
+                self.reference(target);
+                for item in body {
+                    self.resolve_expr(item);
+                }
+            }
+        }
+    }
+
+    /// Resolves `let`, `lets`, `letrec`, and `let_values`, as well as named `let` loops
+    fn resolve_let(&mut self, items: &[&Expr]) {
+        let head = items[0];
+        let form = head.stripped_symbol_value().unwrap_or_default();
+        self.reference(head);
+        let loop_name = items.get(1).filter(|item| item.is_symbol());
+        let bindings_index = if loop_name.is_some() { 2 } else { 1 };
+        let bindings: Vec<(&Expr, Option<&Expr>)> = items
+            .get(bindings_index)
+            .and_then(|bindings| bindings.list_value().or_else(|| bindings.sexpr_value()))
+            .map(|bindings| {
+                bindings
+                    .item_iter()
+                    .filter_map(|binding| binding.sexpr_value().or_else(|| binding.list_value()))
+                    .filter_map(|binding| {
+                        let mut parts = binding.item_iter();
+                        parts.next().map(|names| (names, parts.next()))
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let bind_names = |resolver: &mut Self, names: &Expr| match names.sexpr_value().or_else(|| names.list_value()) {
+            Some(names) if form == "let_values" => names
+                .item_iter()
+                .for_each(|name| resolver.bind(name, Resolution::Local)),
+            _ => resolver.bind(names, Resolution::Local),
+        };
+
+        self.scopes.push(BTreeMap::new());
+        match form {
+            "lets" => {
+                for (names, init) in &bindings {
+                    if let Some(init) = init {
+                        self.resolve_expr(init);
+                    }
+                    bind_names(self, names);
+                }
+            }
+            "letrec" => {
+                for (names, _) in &bindings {
+                    bind_names(self, names);
+                }
+                for init in bindings.iter().filter_map(|(_, init)| *init) {
+                    self.resolve_expr(init);
+                }
+            }
+            _ => {
+                // The initial values of `let` are evaluated outside of the scope of its names
+                let scope = self.scopes.pop();
+                for init in bindings.iter().filter_map(|(_, init)| *init) {
+                    self.resolve_expr(init);
+                }
+                self.scopes.extend(scope);
+                if let Some(loop_name) = loop_name {
+                    self.bind(loop_name, Resolution::Local);
+                }
+                for (names, _) in &bindings {
+                    bind_names(self, names);
+                }
+            }
+        }
+        self.resolve_body(items.get(bindings_index + 1..).unwrap_or_default(), Resolution::Local);
+        self.scopes.pop();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::index::{Catalog, FusionIndex};
+    use fuusak::config::new_default_config;
+    use fuusak::file::FusionFileContent;
+    use std::path::PathBuf;
+
+    fn resolve_script(source: &str) -> Resolutions {
+        let config = new_default_config();
+        let file = FusionFileContent::new(PathBuf::from("test.fusion"), source.to_string())
+            .parse(&config)
+            .unwrap();
+        let index = FusionIndex::new(&PathBuf::from("./"), vec![], Catalog::bundled()).unwrap();
+        let fusion = index.write().get_stub_module("/fusion");
+        let imports = Imports {
+            requires: &[],
+            languages: &[fusion],
+            globals: &[],
+        };
+        resolve(&file.ast, &imports)
+    }
+
+    /// Returns the resolution of the `nth` occurrence of `symbol`, as (line, column) of what it refers to
+    fn resolution_of(source: &str, resolutions: &Resolutions, symbol: &str, nth: usize) -> String {
+        let resolution = resolutions
+            .iter()
+            .filter(|(span, _)| &source[span.start..span.end] == symbol)
+            .map(|(_, resolution)| resolution)
+            .nth(nth)
+            .unwrap_or_else(|| panic!("no occurrence {nth} of {symbol}"));
+        let describe = |target: &ShortSpan| {
+            let (line, col) = target.line_col(source);
+            format!("{line}:{col}")
+        };
+        match resolution {
+            Resolution::Local(target) => format!("local {}", describe(target)),
+            Resolution::Module(target) => format!("module {}", describe(target)),
+            Resolution::Imported { module, .. } => format!("imported from {module}"),
+            Resolution::Global => "global".into(),
+            Resolution::Unknown { modules } => format!("unknown from {}", modules.join(", ")),
+            Resolution::Unbound => "unbound".into(),
+        }
+    }
+
+    #[test]
+    fn lexical_scopes() {
+        let source = "(define (f x)\n  (let [(y x), (x 1)] (+ x y z)))\n\
+             (define g (lambda args (lets [(a 1), (b a)] (f b args))))\n\
+             (define h (|p| (letrec [(loop (|n| (loop n)))] (loop p))))\n\
+             (quote (f x))\n\
+             (quasiquote (x (unquote f)))\n";
+        let resolutions = resolve_script(source);
+        let resolution = |symbol, nth| resolution_of(source, &resolutions, symbol, nth);
+        assert_eq!("imported from /fusion", resolution("define", 0));
+        assert_eq!("module 1:10", resolution("f", 0));
+        assert_eq!("local 1:12", resolution("x", 0));
+        // `let` initial values see the enclosing `x`, while its body sees the new one
+        assert_eq!("local 1:12", resolution("x", 1));
+        assert_eq!("local 2:17", resolution("x", 3));
+        assert_eq!("local 2:10", resolution("y", 1));
+        // The binding catalog lists everything that /fusion provides
+        assert_eq!("unbound", resolution("z", 0));
+        assert_eq!("local 3:32", resolution("a", 1));
+        assert_eq!("local 3:19", resolution("args", 1));
+        assert_eq!("module 1:10", resolution("f", 1));
+        assert_eq!("local 4:13", resolution("p", 1));
+        assert_eq!("local 4:26", resolution("loop", 1));
+        assert_eq!("local 4:26", resolution("loop", 2));
+        // Quoted data isn't resolved, other than `unquote`d expressions
+        assert_eq!(
+            3,
+            resolutions
+                .keys()
+                .filter(|span| &source[span.start..span.end] == "f")
+                .count()
+        );
+        assert_eq!("module 1:10", resolution("f", 2));
+        assert_eq!("imported from /fusion", resolution("unquote", 0));
+    }
+}
@@ -1,18 +1,23 @@
 // Copyright Ion Fusion contributors. All Rights Reserved.
 // SPDX-License-Identifier: Apache-2.0
+use crate::index::{RequireForm, Resolutions};
 use fuusak::file::FusionFile;
-use std::cell::RefCell;
+use std::collections::BTreeSet;
 use std::fmt;
 use std::path::Path;
-use std::rc::Rc;
+use std::sync::Arc;
 
-pub type ScriptCell = Rc<RefCell<Script>>;
+pub type ScriptCell = Arc<Script>;
 
 pub struct Script {
     pub name: String,
     pub top_level_modules: Vec<String>,
     pub global_bindings: Vec<String>,
     pub files: Vec<FusionFile>,
+    /// The require forms of all the files, in order
+    pub requires: Vec<RequireForm>,
+    /// Resolutions of the symbols in each of the `files`
+    pub resolutions: Vec<Resolutions>,
 }
 
 impl Script {
@@ -21,13 +26,28 @@ impl Script {
         top_level_modules: Vec<String>,
         global_bindings: Vec<String>,
         files: Vec<FusionFile>,
+        requires: Vec<RequireForm>,
+        resolutions: Vec<Resolutions>,
     ) -> ScriptCell {
-        Rc::new(RefCell::new(Script {
+        Arc::new(Script {
             name,
             top_level_modules,
             global_bindings,
             files,
-        }))
+            requires,
+            resolutions,
+        })
+    }
+}
+
+impl Script {
+    /// Names of the modules that this script requires, including its top-level modules
+    pub fn dependencies(&self) -> BTreeSet<String> {
+        self.requires
+            .iter()
+            .map(|require| require.module.name.clone())
+            .chain(self.top_level_modules.iter().cloned())
+            .collect()
     }
 }
 
@@ -37,7 +57,7 @@ impl fmt::Debug for Script {
             .field("name", &self.name)
             .field("top_level_modules", &self.top_level_modules)
             .field("global_bindings", &self.global_bindings)
-            // omit the AST for files
+            // omit the AST and resolutions for files
             .field(
                 "files",
                 &self
@@ -46,6 +66,7 @@ impl fmt::Debug for Script {
                     .map(|file| file.file_name.as_ref())
                     .collect::<Vec<&Path>>(),
             )
+            .field("requires", &self.requires)
             .finish()
     }
 }
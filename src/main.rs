@@ -3,18 +3,28 @@
 #[macro_use]
 mod bin_error_macro;
 
+mod cache;
 mod check;
+mod deps;
+mod git;
 mod index;
+mod query;
+mod report;
 
+use cache::StyleCache;
 use clap::{Parser, Subcommand, ValueHint};
-use fuusak::config::{FusionConfig, load_config, write_default_config};
+use deps::{DependencyGraph, GraphFormat};
+use fuusak::checkstyle::{self, Violation};
+use fuusak::config::{ConfigResolver, FusionConfig, write_default_config};
 use fuusak::error::Error;
-use fuusak::file::{FusionFile, FusionFileContent};
-use fuusak::{diff_util, format};
+use fuusak::file::{FileSelector, FusionFile, FusionFileContent};
+use fuusak::{diff_util, format, layout};
 use fuusak_err_macro::err_generic;
-use std::env;
+use rayon::prelude::*;
+use report::{FileReport, ReportFormat};
 use std::fs::{self, File};
 use std::io::Write;
+use std::path::{Path, PathBuf};
 
 fn main() {
     let args = Args::parse();
@@ -24,32 +34,61 @@ fn main() {
             subcommand_create_config();
         }
         Command::Configured(configured_command) => {
-            let fusion_config = load_config(args.config.as_deref(), false).unwrap_or_else(|error| bail!("{}", error));
+            let silent = configured_command.has_machine_readable_output();
+            let config_resolver =
+                ConfigResolver::new(args.config.as_deref()).unwrap_or_else(|error| bail!("{}", error));
+            let fusion_config = config_resolver
+                .load_current_directory(silent)
+                .unwrap_or_else(|error| bail!("{}", error));
             match configured_command {
-                ConfiguredCommand::CheckCorrectnessWatch => {
-                    subcommand_check_correctness_watch(&fusion_config);
+                ConfiguredCommand::CheckCorrectnessWatch(index_args) => {
+                    subcommand_check_correctness_watch(&fusion_config, &index_args);
                 }
-                ConfiguredCommand::Checkstyle { file } => {
-                    subcommand_checkstyle(&fusion_config, &file);
+                ConfiguredCommand::Checkstyle { file, report } => {
+                    subcommand_checkstyle(&config_resolver, &file, report);
                 }
-                ConfiguredCommand::CheckstyleAll => {
-                    subcommand_checkstyle_all(&fusion_config);
+                ConfiguredCommand::CheckstyleAll { bulk_args, report } => {
+                    subcommand_checkstyle_all(&config_resolver, &fusion_config, &bulk_args, report);
                 }
                 ConfiguredCommand::DebugParser { file } => {
-                    subcommand_debug_parser(&fusion_config, &file);
+                    subcommand_debug_parser(&config_resolver, &file);
                 }
-                ConfiguredCommand::DebugIndex => {
-                    subcommand_debug_index(&fusion_config).unwrap_or_else(|err| bail!("Failed: {}", err));
+                ConfiguredCommand::Deps {
+                    root,
+                    reverse,
+                    depth,
+                    format,
+                    index_args,
+                } => {
+                    subcommand_deps(&fusion_config, root.as_deref(), reverse, depth, format, &index_args)
+                        .unwrap_or_else(|err| bail!("Failed: {}", err));
+                }
+                ConfiguredCommand::DebugIndex(index_args) => {
+                    subcommand_debug_index(&fusion_config, &index_args).unwrap_or_else(|err| bail!("Failed: {}", err));
                 }
                 ConfiguredCommand::Format { file } => {
-                    subcommand_format(&fusion_config, &file);
+                    subcommand_format(&config_resolver, &file);
                 }
-                ConfiguredCommand::FormatAll => {
-                    subcommand_format_all(&fusion_config);
+                ConfiguredCommand::FormatAll(bulk_args) => {
+                    subcommand_format_all(&config_resolver, &fusion_config, &bulk_args);
                 }
                 ConfiguredCommand::FormatServer => {
                     subcommand_format_server(&fusion_config);
                 }
+                ConfiguredCommand::Lint { fix, index_args } => {
+                    subcommand_lint(&fusion_config, fix, &index_args);
+                }
+                ConfiguredCommand::Query {
+                    query,
+                    json,
+                    index_args,
+                } => {
+                    subcommand_query(&fusion_config, &query, json, &index_args)
+                        .unwrap_or_else(|err| bail!("Failed: {}", err));
+                }
+                ConfiguredCommand::ShowConfig { file } => {
+                    subcommand_show_config(&config_resolver, file.as_deref());
+                }
             }
         }
     }
@@ -84,7 +123,29 @@ enum ConfiguredCommand {
     },
 
     /// outputs binding index generated by examining all the Fusion files in a package
-    DebugIndex,
+    DebugIndex(IndexArgs),
+
+    /// outputs the graph of which modules and tests require which modules
+    Deps {
+        /// only shows this module or test and what it depends on
+        #[arg(value_name = "ROOT")]
+        root: Option<String>,
+
+        /// shows what depends on ROOT instead
+        #[arg(long, requires = "root")]
+        reverse: bool,
+
+        /// only shows what's at most this many requires away from ROOT
+        #[arg(long, requires = "root")]
+        depth: Option<usize>,
+
+        /// format to output the graph in
+        #[arg(long, value_enum, default_value_t)]
+        format: GraphFormat,
+
+        #[command(flatten)]
+        index_args: IndexArgs,
+    },
 
     /// formats a single file
     Format {
@@ -92,14 +153,21 @@ enum ConfiguredCommand {
         file: String,
     },
 
-    /// recursively formats all Fusion files in the current directory
-    FormatAll,
+    /// recursively formats all Fusion files in the current directory (or the given paths)
+    FormatAll(BulkArgs),
 
     /// watches for changes to current package and checks for syntax errors
-    CheckCorrectnessWatch,
+    CheckCorrectnessWatch(IndexArgs),
 
-    /// recursively checks adherence to format on all Fusion files in the current directory
-    CheckstyleAll,
+    /// recursively checks adherence to format on all Fusion files in the current directory (or the given paths)
+    CheckstyleAll {
+        #[command(flatten)]
+        bulk_args: BulkArgs,
+
+        /// format to report violations in
+        #[arg(long, value_enum, default_value_t)]
+        report: ReportFormat,
+    },
 
     /// starts a format server which listens on stdin and outputs formatted code to stdout
     FormatServer,
@@ -108,10 +176,115 @@ enum ConfiguredCommand {
     Checkstyle {
         #[arg(value_hint = ValueHint::FilePath)]
         file: String,
+
+        /// format to report violations in
+        #[arg(long, value_enum, default_value_t)]
+        report: ReportFormat,
+    },
+
+    /// checks the package's modules and tests for unbound identifiers, unused or conflicting bindings and more
+    Lint {
+        /// fixes the problems that have an obvious fix (like unused requires) instead of reporting them
+        #[arg(long)]
+        fix: bool,
+
+        #[command(flatten)]
+        index_args: IndexArgs,
+    },
+
+    /// answers questions about the package's modules and tests, like where a name is defined
+    Query {
+        #[command(subcommand)]
+        query: QueryCommand,
+
+        /// outputs the results as JSON
+        #[arg(long, global = true)]
+        json: bool,
+
+        #[command(flatten)]
+        index_args: IndexArgs,
+    },
+
+    /// prints the effective config for a file (or the current directory) and where each value came from
+    ShowConfig {
+        #[arg(value_name = "FILE", value_hint = ValueHint::FilePath)]
+        file: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum QueryCommand {
+    /// lists the modules in the index
+    Modules,
+
+    /// lists the names that a module provides and where each is defined
+    Provides {
+        #[arg(value_name = "MODULE")]
+        module: String,
     },
+
+    /// finds the modules and tests that define a name at their top level
+    Definitions {
+        #[arg(value_name = "NAME")]
+        name: String,
+    },
+
+    /// finds the requires that import a name
+    Importers {
+        #[arg(value_name = "NAME")]
+        name: String,
+    },
+
+    /// lists the modules that a test depends on
+    ScriptDeps {
+        #[arg(value_name = "TEST")]
+        script: String,
+
+        /// also lists what those modules depend on
+        #[arg(long)]
+        transitive: bool,
+    },
+}
+
+impl ConfiguredCommand {
+    /// Commands that print machine readable output can't print anything else to stdout
+    fn has_machine_readable_output(&self) -> bool {
+        match self {
+            ConfiguredCommand::Checkstyle { report, .. } | ConfiguredCommand::CheckstyleAll { report, .. } => {
+                *report != ReportFormat::Text
+            }
+            // Shows which config files apply itself
+            ConfiguredCommand::ShowConfig { .. } => true,
+            ConfiguredCommand::Deps { .. } | ConfiguredCommand::Query { .. } => true,
+            _ => false,
+        }
+    }
+}
+
+#[derive(clap::Args)]
+struct BulkArgs {
+    /// files, directories, or glob patterns to operate on instead of the current directory
+    #[arg(value_name = "PATH", value_hint = ValueHint::AnyPath)]
+    paths: Vec<String>,
+
+    /// only operates on files that were added or modified relative to the given git revision
+    #[arg(long, value_name = "GIT_REV")]
+    changed_since: Option<String>,
+
+    /// ignores and doesn't update the cache of files known to be formatted
+    #[arg(long)]
+    no_cache: bool,
+}
+
+#[derive(clap::Args)]
+struct IndexArgs {
+    /// ignores and doesn't update the package's index cache, which spares re-indexing unchanged files
+    #[arg(long, global = true)]
+    no_cache: bool,
 }
 
-fn subcommand_debug_parser(fusion_config: &FusionConfig, path: &str) {
+fn subcommand_debug_parser(config_resolver: &ConfigResolver, path: &str) {
+    let fusion_config = &config_for_file(config_resolver, path);
     let file_contents = FusionFileContent::load(path).unwrap_or_else(|err| bail!("{}", err));
     let file = file_contents
         .parse(fusion_config)
@@ -119,101 +292,366 @@ fn subcommand_debug_parser(fusion_config: &FusionConfig, path: &str) {
     println!("{}", file.debug_ast());
 }
 
-fn subcommand_debug_index(fusion_config: &FusionConfig) -> Result<(), Error> {
-    let current_package_path =
-        env::current_dir().map_err(|err| err_generic!("failed to determine current working directory: {}", err))?;
-    let fusion_index = index::load_index(fusion_config, &current_package_path)?;
-    println!("Index:\n{:#?}", fusion_index.borrow());
+fn subcommand_debug_index(fusion_config: &FusionConfig, index_args: &IndexArgs) -> Result<(), Error> {
+    let current_package_path = fusion_config.package.directory()?;
+    let fusion_index = index::load_index(fusion_config, &current_package_path, !index_args.no_cache, false, false)?;
+    println!("Index:\n{:#?}", fusion_index.read());
+
+    let fusion_index = fusion_index.read();
+    println!("Stub modules from the binding catalog:");
+    for module in fusion_index.module_iter() {
+        if fusion_index.is_stub_module(module) {
+            println!("  {}", module.name);
+            for (name, binding) in fusion_index.catalog().exports(&module.name) {
+                println!("    {name}: {binding}");
+            }
+        }
+    }
+
+    println!("Unbound symbols:");
+    let print_unbound = |file: &FusionFile, resolutions: &index::Resolutions| {
+        for span in resolutions
+            .iter()
+            .filter(|(_, resolution)| **resolution == index::Resolution::Unbound)
+            .map(|(span, _)| span)
+        {
+            let (line, col) = span.line_col(&file.contents);
+            let name = &file.contents[span.start..span.end];
+            println!("  {}:{line}:{col}: {name}", file.file_name.display());
+        }
+    };
+    for module in fusion_index.module_iter() {
+        print_unbound(&module.file, &module.resolutions);
+    }
+    for script in fusion_index.script_iter() {
+        for (file, resolutions) in script.files.iter().zip(&script.resolutions) {
+            print_unbound(file, resolutions);
+        }
+    }
+    Ok(())
+}
+
+fn subcommand_deps(
+    fusion_config: &FusionConfig,
+    root: Option<&str>,
+    reverse: bool,
+    depth: Option<usize>,
+    format: GraphFormat,
+    index_args: &IndexArgs,
+) -> Result<(), Error> {
+    let current_package_path = fusion_config.package.directory()?;
+    // Cycles are among what the graph shows, so they mustn't fail the load
+    let fusion_index = index::load_index(fusion_config, &current_package_path, !index_args.no_cache, true, true)?;
+    let mut graph = DependencyGraph::new(&fusion_index.read());
+    if let Some(root) = root {
+        graph = graph.around(root, reverse, depth)?;
+    }
+    print!("{}", graph.render(format));
+    Ok(())
+}
+
+fn subcommand_query(
+    fusion_config: &FusionConfig,
+    query: &QueryCommand,
+    json: bool,
+    index_args: &IndexArgs,
+) -> Result<(), Error> {
+    let current_package_path = fusion_config.package.directory()?;
+    let fusion_index = index::load_index(fusion_config, &current_package_path, !index_args.no_cache, true, false)?;
+    let fusion_index = fusion_index.read();
+    let output = match query {
+        QueryCommand::Modules => query::render(&query::modules(&fusion_index), json),
+        QueryCommand::Provides { module } => query::render(&query::provides(&fusion_index, module)?, json),
+        QueryCommand::Definitions { name } => query::render(&query::definitions(&fusion_index, name), json),
+        QueryCommand::Importers { name } => query::render(&query::importers(&fusion_index, name), json),
+        QueryCommand::ScriptDeps { script, transitive } => {
+            query::render(&query::script_dependencies(&fusion_index, script, *transitive)?, json)
+        }
+    };
+    print!("{output}");
     Ok(())
 }
 
+fn subcommand_lint(fusion_config: &FusionConfig, fix: bool, index_args: &IndexArgs) {
+    let passed = check::lint(fusion_config, fix, !index_args.no_cache).unwrap_or_else(|err| bail!("Failed: {}", err));
+    if !passed {
+        bail!("Lint failed.")
+    }
+}
+
+fn subcommand_show_config(config_resolver: &ConfigResolver, path: Option<&str>) {
+    let settings = config_resolver
+        .explain(path.map(Path::new))
+        .unwrap_or_else(|err| bail!("{}", err));
+    let mut section = "";
+    for setting in settings {
+        if setting.section != section {
+            section = setting.section;
+            println!("[{section}]");
+        }
+        println!("{} = {}  # {}", setting.key, setting.value, setting.source);
+    }
+}
+
 fn subcommand_create_config() {
     write_default_config().unwrap_or_else(|err| bail!("Failed to write default config: {}", err));
 }
 
 fn format_file_in_place(fusion_config: &FusionConfig, fusion_file: &FusionFile) {
-    let formatted = format::format(fusion_config, &fusion_file.ast);
+    let formatted = format::format_file(fusion_config, fusion_file);
+    write_formatted(&fusion_file.file_name, &formatted).unwrap_or_else(|err| bail!("{}", err));
+}
 
+fn write_formatted(file_name: &Path, formatted: &str) -> Result<(), Error> {
     // Write formatted to a temp file
-    let temp_file_path = fusion_file.file_name.with_extension("tmp-fuusak");
+    let temp_file_path = file_name.with_extension("tmp-fuusak");
     let mut temp_file =
-        File::create(&temp_file_path).unwrap_or_else(|err| bail!("Failed to create temp file: {}", err));
-    write!(temp_file, "{formatted}").unwrap_or_else(|err| bail!("Failed to write to temp file: {}", err));
+        File::create(&temp_file_path).map_err(|err| err_generic!("Failed to create temp file: {}", err))?;
+    write!(temp_file, "{formatted}").map_err(|err| err_generic!("Failed to write to temp file: {}", err))?;
 
     // Replace original file with temp file via rename
-    fs::rename(&temp_file_path, &fusion_file.file_name).unwrap_or_else(|err| {
-        bail!(
-            "Failed to overwrite {:?} with formatted output: {}",
-            fusion_file.file_name,
-            err
-        )
-    });
+    fs::rename(&temp_file_path, file_name)
+        .map_err(|err| err_generic!("Failed to overwrite {:?} with formatted output: {}", file_name, err))
 }
 
-fn checkstyle(fusion_config: &FusionConfig, file: &FusionFile) -> bool {
-    println!("Checking {}...", file.file_name.display());
+/// Checks the style of `file`, returning every violation found. For text reports,
+/// a human readable description of the violations is returned as well.
+fn checkstyle_file(fusion_config: &FusionConfig, file: &FusionFile, report: ReportFormat) -> (Vec<Violation>, String) {
     let formatted = format::format(fusion_config, &file.ast);
+    let violations = checkstyle::check_formatted(fusion_config, &file.file_name, &file.contents, &formatted);
+    let text = match report {
+        ReportFormat::Text if !violations.is_empty() => text_report(fusion_config, file, &formatted),
+        _ => String::new(),
+    };
+    (violations, text)
+}
+
+fn text_report(fusion_config: &FusionConfig, file: &FusionFile, formatted: &str) -> String {
     let expected = formatted.trim_end();
-    let actual = file.contents.trim_end();
-    if expected == actual {
-        true
-    } else {
-        println!(
-            "File {} doesn't adhere to correct style. See diff to correct below:",
-            file.file_name.display()
-        );
-        println!("{}", diff_util::human_diff_lines(actual, expected));
-        false
+    let normalized = layout::normalize(&file.contents);
+    let actual = normalized.trim_end();
+    let mut report = String::new();
+    if expected != actual {
+        report.push_str(&format!(
+            "File {} doesn't adhere to correct style. See diff to correct below:\n{}\n",
+            file.file_name.display(),
+            diff_util::human_diff_lines(actual, expected)
+        ));
+    }
+    for violation in layout::check(fusion_config, &file.contents) {
+        report.push_str(&format!("File {} {}.\n", file.file_name.display(), violation));
     }
+    report
 }
 
-fn subcommand_format(fusion_config: &FusionConfig, path: &str) {
-    let file_content = FusionFileContent::load(path).unwrap_or_else(|err| bail!("{}", err));
-    let file = file_content.parse(fusion_config).unwrap_or_else(|err| bail!("{}", err));
-    format_file_in_place(fusion_config, &file);
+/// Prints the violations found in `files` in the given report format, then exits with an error if there were any
+fn finish_checkstyle(report: ReportFormat, files: &[FileReport], success_message: &str) {
+    let passed = files.iter().all(|file| file.violations.is_empty());
+    match report {
+        ReportFormat::Text if passed => println!("{success_message}"),
+        ReportFormat::Text => {}
+        _ => print!("{}", report::render(report, files)),
+    }
+    if !passed {
+        bail!("Checkstyle failed.")
+    }
+}
+
+/// The result of running a batch operation on a single file
+struct FileOutcome<T> {
+    file_name: PathBuf,
+    /// True if the file was skipped because the cache knows it to be well formatted
+    cached: bool,
+    result: T,
 }
 
-fn subcommand_format_all(fusion_config: &FusionConfig) {
-    let fusion_files =
-        FusionFile::recursively_load_directory(fusion_config, "./").unwrap_or_else(|err| bail!("{}", err));
-    for file in &fusion_files {
-        println!("Formatting {}...", file.file_name.display());
-        format_file_in_place(fusion_config, file);
+fn config_for_file(config_resolver: &ConfigResolver, path: &str) -> FusionConfig {
+    config_resolver
+        .config_for_file(Path::new(path))
+        .unwrap_or_else(|err| bail!("{}", err))
+}
+
+/// Finds the files that a bulk command should operate on
+fn select_files(fusion_config: &FusionConfig, bulk_args: &BulkArgs) -> Result<Vec<PathBuf>, Error> {
+    let mut paths = FileSelector::new(fusion_config)?.find_files(&bulk_args.paths)?;
+    if let Some(revision) = &bulk_args.changed_since {
+        let changed = git::changed_files(Path::new("."), revision)?;
+        paths.retain(|path| path.canonicalize().is_ok_and(|path| changed.contains(&path)));
     }
+    Ok(paths)
+}
+
+/// Loads, parses, and processes every selected Fusion file with its own config on a worker pool, skipping
+/// files that the cache knows to already be well formatted. Only one file's contents and AST need to be held
+/// in memory per worker at a time. `process` returns the file's result along with the file's new contents if they're
+/// now known to be well formatted; skipped files get a default result. Outcomes are returned in path order so that
+/// output is deterministic. Files that fail to load, parse, or process get an error result, and don't stop the
+/// remaining files from being processed.
+fn process_all_files<T, F>(
+    config_resolver: &ConfigResolver,
+    fusion_config: &FusionConfig,
+    bulk_args: &BulkArgs,
+    process: F,
+) -> Vec<FileOutcome<Result<T, Error>>>
+where
+    T: Default + Send,
+    F: Fn(&FusionConfig, &FusionFile) -> Result<(T, Option<String>), Error> + Sync,
+{
+    let paths = select_files(fusion_config, bulk_args).unwrap_or_else(|err| bail!("{}", err));
+    let cache = match bulk_args.no_cache {
+        true => StyleCache::disabled(),
+        false => StyleCache::load(Path::new("./")),
+    };
+    let process_file = |path: &PathBuf| -> Result<(bool, T), Error> {
+        let fusion_config = config_resolver.config_for_file(path)?;
+        let content = FusionFileContent::load(path)?;
+        if cache.is_clean(path, &fusion_config, &content.contents) {
+            return Ok((true, T::default()));
+        }
+        let file = content.parse(&fusion_config)?;
+        let (result, clean_contents) = process(&fusion_config, &file)?;
+        match clean_contents {
+            Some(contents) => cache.mark_clean(path, &fusion_config, &contents),
+            None => cache.mark_dirty(path),
+        }
+        Ok((false, result))
+    };
+    let outcomes = paths
+        .par_iter()
+        .map(|path| {
+            let result = process_file(path);
+            FileOutcome {
+                file_name: path.clone(),
+                cached: result.as_ref().is_ok_and(|(cached, _)| *cached),
+                result: result.map(|(_, result)| result),
+            }
+        })
+        .collect();
+    cache.save().unwrap_or_else(|err| eprintln!("{err}"));
+    outcomes
 }
 
-fn subcommand_check_correctness_watch(fusion_config: &FusionConfig) {
-    while check::check_correctness_watch(fusion_config).unwrap_or_else(|err| bail!("Failed: {}", err)) {}
+/// Prints the errors of the files that failed to load, parse, or process, then exits if there were any
+fn exit_on_failures<T>(outcomes: Vec<FileOutcome<Result<T, Error>>>) -> Vec<FileOutcome<T>> {
+    let mut failures = 0;
+    let outcomes = outcomes
+        .into_iter()
+        .filter_map(|outcome| match outcome.result {
+            Ok(result) => Some(FileOutcome {
+                file_name: outcome.file_name,
+                cached: outcome.cached,
+                result,
+            }),
+            Err(err) => {
+                eprintln!("{err}");
+                failures += 1;
+                None
+            }
+        })
+        .collect();
+    if failures > 0 {
+        bail!("Failed to process {} file(s).", failures);
+    }
+    outcomes
 }
 
-fn subcommand_checkstyle_all(fusion_config: &FusionConfig) {
-    let fusion_files =
-        FusionFile::recursively_load_directory(fusion_config, "./").unwrap_or_else(|err| bail!("{}", err));
-    let mut passed = true;
-    for file in &fusion_files {
-        if !checkstyle(fusion_config, file) {
-            passed = false;
+/// Prints a line (and any text output) for each file that was processed, and how many files were skipped
+fn print_outcomes<T>(outcomes: &[FileOutcome<T>], verb: &str, text: impl Fn(&T) -> &str) {
+    let mut cached = 0;
+    for outcome in outcomes {
+        if outcome.cached {
+            cached += 1;
+        } else {
+            println!("{verb} {}...", outcome.file_name.display());
+            print!("{}", text(&outcome.result));
         }
     }
-    if passed {
-        println!("All files adhere to correct style.");
-    } else {
-        bail!("Checkstyle failed.")
+    if cached > 0 {
+        println!("Skipped {cached} file(s) that were unchanged since the last run.");
     }
 }
 
-fn subcommand_checkstyle(fusion_config: &FusionConfig, path: &str) {
-    let file = FusionFile::load(fusion_config, path).unwrap_or_else(|err| bail!("{}", err));
-    if checkstyle(fusion_config, &file) {
-        println!("{path} adheres to correct style.");
-    } else {
-        bail!("Checkstyle failed.")
+fn subcommand_format(config_resolver: &ConfigResolver, path: &str) {
+    let fusion_config = &config_for_file(config_resolver, path);
+    let file_content = FusionFileContent::load(path).unwrap_or_else(|err| bail!("{}", err));
+    let file = file_content.parse(fusion_config).unwrap_or_else(|err| bail!("{}", err));
+    format_file_in_place(fusion_config, &file);
+}
+
+fn subcommand_format_all(config_resolver: &ConfigResolver, fusion_config: &FusionConfig, bulk_args: &BulkArgs) {
+    let outcomes = process_all_files(config_resolver, fusion_config, bulk_args, |fusion_config, file| {
+        let formatted = format::format_file(fusion_config, file);
+        if formatted != file.contents {
+            write_formatted(&file.file_name, &formatted)?;
+        }
+        Ok(((), Some(formatted)))
+    });
+    let outcomes = exit_on_failures(outcomes);
+    print_outcomes(&outcomes, "Formatting", |_| "");
+}
+
+fn subcommand_check_correctness_watch(fusion_config: &FusionConfig, index_args: &IndexArgs) {
+    let use_cache = !index_args.no_cache;
+    while check::check_correctness_watch(fusion_config, use_cache).unwrap_or_else(|err| bail!("Failed: {}", err)) {}
+}
+
+fn subcommand_checkstyle_all(
+    config_resolver: &ConfigResolver,
+    fusion_config: &FusionConfig,
+    bulk_args: &BulkArgs,
+    report: ReportFormat,
+) {
+    let outcomes = process_all_files(config_resolver, fusion_config, bulk_args, |fusion_config, file| {
+        let (violations, text) = checkstyle_file(fusion_config, file, report);
+        let clean_contents = violations.is_empty().then(|| file.contents.clone());
+        Ok(((violations, text), clean_contents))
+    });
+    // Files that can't be checked are reported like violations, so that machine readable reports still get written
+    let outcomes: Vec<FileOutcome<(Vec<Violation>, String)>> = outcomes
+        .into_iter()
+        .map(|outcome| FileOutcome {
+            result: outcome.result.unwrap_or_else(|err| {
+                let text = format!("File {} couldn't be checked: {err}\n", outcome.file_name.display());
+                (vec![checkstyle::file_error(&outcome.file_name, &err)], text)
+            }),
+            file_name: outcome.file_name,
+            cached: outcome.cached,
+        })
+        .collect();
+    if report == ReportFormat::Text {
+        print_outcomes(&outcomes, "Checking", |(_, text)| text);
     }
+    let files: Vec<FileReport> = outcomes
+        .into_iter()
+        .map(|outcome| FileReport {
+            file_name: outcome.file_name,
+            violations: outcome.result.0,
+        })
+        .collect();
+    finish_checkstyle(report, &files, "All files adhere to correct style.");
+}
+
+fn subcommand_checkstyle(config_resolver: &ConfigResolver, path: &str, report: ReportFormat) {
+    let fusion_config = &config_for_file(config_resolver, path);
+    let file_name = PathBuf::from(path);
+    let (violations, text) = match FusionFile::load(fusion_config, path) {
+        Ok(file) => checkstyle_file(fusion_config, &file, report),
+        Err(err) => {
+            let text = format!("File {path} couldn't be checked: {err}\n");
+            (vec![checkstyle::file_error(&file_name, &err)], text)
+        }
+    };
+    if report == ReportFormat::Text {
+        println!("Checking {}...", file_name.display());
+        print!("{text}");
+    }
+    let files = [FileReport { file_name, violations }];
+    finish_checkstyle(report, &files, &format!("{path} adheres to correct style."));
 }
 
 fn subcommand_format_server(fusion_config: &FusionConfig) {
     let file_content = FusionFileContent::load_stdin().unwrap_or_else(|err| bail!("{}", err));
     let file = file_content.parse(fusion_config).unwrap_or_else(|err| bail!("{}", err));
-    let formatted = format::format(fusion_config, &file.ast);
+    let formatted = format::format_file(fusion_config, &file);
     print!("{formatted}");
 }
@@ -0,0 +1,361 @@
+// Copyright Ion Fusion contributors. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+use crate::index::{FusionIndex, Module, Resolution, top_level_definitions};
+use fuusak::error::Error;
+use fuusak::file::FusionFile;
+use fuusak::span::ShortSpan;
+use fuusak_err_macro::err_generic;
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::{self, Display};
+use std::path::PathBuf;
+
+/// Where a binding is defined or a name is required
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct Location {
+    /// The module or test that the location is in
+    pub source: String,
+    /// The file, relative to the package, or `None` for bindings from the binding catalog
+    pub file: Option<PathBuf>,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+impl Location {
+    fn in_file(fusion_index: &FusionIndex, source: &str, file: &FusionFile, span: ShortSpan) -> Location {
+        let (line, column) = span.line_col(&file.contents);
+        Location {
+            source: source.into(),
+            file: Some(relative_path(fusion_index, file)),
+            line: Some(line),
+            column: Some(column),
+        }
+    }
+
+    fn in_catalog(module_name: &str) -> Location {
+        Location {
+            source: module_name.into(),
+            file: None,
+            line: None,
+            column: None,
+        }
+    }
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.file, self.line, self.column) {
+            (Some(file), Some(line), Some(column)) => {
+                write!(f, "{} ({}:{line}:{column})", self.source, file.display())
+            }
+            _ => write!(f, "{} (binding catalog)", self.source),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct ModuleSummary {
+    pub name: String,
+    pub language: String,
+    /// The module's file, relative to the package, or `None` for modules from the binding catalog
+    pub file: Option<PathBuf>,
+}
+
+impl Display for ModuleSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{} ({})", self.name, file.display()),
+            None => write!(f, "{} (binding catalog)", self.name),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct Provided {
+    pub name: String,
+    /// Where the binding is defined, which is in another module for re-exported bindings
+    pub definition: Option<Location>,
+}
+
+impl Display for Provided {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.definition {
+            Some(definition) => write!(f, "{}: {definition}", self.name),
+            None => write!(f, "{}: definition not found", self.name),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct Import {
+    /// The require spec that imports the name, in the importing module or test
+    #[serde(flatten)]
+    pub location: Location,
+    /// The module that the name is imported from
+    pub module: String,
+}
+
+impl Display for Import {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} imports it from {}", self.location, self.module)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct Dependency {
+    pub module: String,
+    /// True if the script requires the module itself, or uses it as a top-level module
+    pub direct: bool,
+}
+
+impl Display for Dependency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.direct {
+            true => write!(f, "{}", self.module),
+            false => write!(f, "{} (indirect)", self.module),
+        }
+    }
+}
+
+/// Renders query results one per line, or as a JSON array if `json` is set
+pub fn render<T: Serialize + Display>(results: &[T], json: bool) -> String {
+    match json {
+        true => serde_json::to_string_pretty(results).expect("query results serialize") + "\n",
+        false => results.iter().map(|result| format!("{result}\n")).collect(),
+    }
+}
+
+/// Lists every module in the index, including those stubbed from the binding catalog
+pub fn modules(fusion_index: &FusionIndex) -> Vec<ModuleSummary> {
+    fusion_index
+        .module_iter()
+        .map(|module| ModuleSummary {
+            name: module.name.clone(),
+            language: module.language.clone(),
+            file: (!fusion_index.is_stub_module(module)).then(|| relative_path(fusion_index, &module.file)),
+        })
+        .collect()
+}
+
+/// Lists the names that a module provides along with where each binding is defined
+pub fn provides(fusion_index: &FusionIndex, module_name: &str) -> Result<Vec<Provided>, Error> {
+    let module = fusion_index
+        .get_module(&module_name.into())
+        .ok_or_else(|| err_generic!("{} is not a module of this package", module_name))?;
+    Ok(module
+        .provides
+        .keys()
+        .map(|name| Provided {
+            name: name.clone(),
+            definition: find_definition(fusion_index, &module, name),
+        })
+        .collect())
+}
+
+/// Finds every top-level definition of `name` in the package's modules and tests, and the modules of the
+/// binding catalog that define it
+pub fn definitions(fusion_index: &FusionIndex, name: &str) -> Vec<Location> {
+    let mut definitions = Vec::new();
+    let mut find_in_file = |source: &str, file: &FusionFile| {
+        for definition in top_level_definitions(&file.ast) {
+            if definition.symbol_value().is_some_and(|defined| defined == name) {
+                definitions.push(Location::in_file(fusion_index, source, file, definition.span()));
+            }
+        }
+    };
+    for module in fusion_index.module_iter() {
+        find_in_file(&module.name, &module.file);
+    }
+    for script in fusion_index.script_iter() {
+        for file in &script.files {
+            find_in_file(&script.name, file);
+        }
+    }
+    for catalog_module in fusion_index.catalog().module_iter() {
+        // Modules of the package shadow the catalog's
+        let shadowed = fusion_index
+            .get_module(&catalog_module.name)
+            .is_some_and(|module| !fusion_index.is_stub_module(&module));
+        if !shadowed && catalog_module.bindings.contains_key(name) {
+            definitions.push(Location::in_catalog(&catalog_module.name));
+        }
+    }
+    definitions
+}
+
+/// Finds every require spec in the package's modules and tests that imports `name`, under that name
+pub fn importers(fusion_index: &FusionIndex, name: &str) -> Vec<Import> {
+    let mut imports = Vec::new();
+    let modules = fusion_index
+        .module_iter()
+        .map(|module| (&module.name, &module.requires, &module.file));
+    // A script's requires don't record which of its files they're in, but tests only have one file
+    let scripts = fusion_index
+        .script_iter()
+        .filter_map(|script| Some((&script.name, &script.requires, script.files.first()?)));
+    for (source, requires, file) in modules.chain(scripts) {
+        for require in requires.iter().filter(|require| require.imports().contains_key(name)) {
+            imports.push(Import {
+                location: Location::in_file(fusion_index, source, file, require.span),
+                module: require.module.name.clone(),
+            });
+        }
+    }
+    imports
+}
+
+/// Lists the modules that a script requires or uses as top-level modules, and with `transitive`, what those
+/// modules depend on in turn
+pub fn script_dependencies(
+    fusion_index: &FusionIndex,
+    script_name: &str,
+    transitive: bool,
+) -> Result<Vec<Dependency>, Error> {
+    let script = fusion_index
+        .get_script(&script_name.into())
+        .ok_or_else(|| err_generic!("{} is not a test of this package", script_name))?;
+    let mut dependencies: BTreeMap<String, bool> =
+        script.dependencies().into_iter().map(|module| (module, true)).collect();
+    if transitive {
+        let mut queue: VecDeque<String> = dependencies.keys().cloned().collect();
+        while let Some(module_name) = queue.pop_front() {
+            let Some(module) = fusion_index.get_module(&module_name) else {
+                continue;
+            };
+            for dependency in module.dependencies() {
+                if !dependencies.contains_key(&dependency) {
+                    dependencies.insert(dependency.clone(), false);
+                    queue.push_back(dependency);
+                }
+            }
+        }
+    }
+    Ok(dependencies
+        .into_iter()
+        .map(|(module, direct)| Dependency { module, direct })
+        .collect())
+}
+
+/// Finds where the binding that `module` provides as `name` is defined, following re-exports back to the module
+/// that defines it
+fn find_definition(fusion_index: &FusionIndex, module: &Module, name: &str) -> Option<Location> {
+    let origin = *module.provides.get(name)?;
+    if fusion_index.is_stub_module(module) {
+        let defining_module = fusion_index.catalog().defining_module(&module.name, name)?;
+        return Some(Location::in_catalog(&defining_module.name));
+    }
+    // The origin of a name provided with `rename_out` is wherever its local binding originates, which for an
+    // imported binding is in another file, so the module's own resolutions only apply to the other provides
+    let in_file = module
+        .provided_names
+        .iter()
+        .any(|provided| provided.name == name && provided.originates_from == origin);
+    match module.resolutions.get(&origin) {
+        Some(Resolution::Module(definition)) if in_file || *definition == origin => {
+            Some(Location::in_file(fusion_index, &module.name, &module.file, *definition))
+        }
+        Some(Resolution::Imported {
+            module: imported,
+            require,
+            ..
+        }) if in_file => {
+            let original_name = match require {
+                Some(index) => module.requires[*index].original_name(name)?,
+                None => name.into(),
+            };
+            find_definition(fusion_index, &*fusion_index.get_module(imported)?, &original_name)
+        }
+        _ => module.requires.iter().find_map(|require| {
+            let (local_name, _) = require
+                .imports()
+                .into_iter()
+                .find(|(_, imported)| *imported == origin)?;
+            let original_name = require.original_name(&local_name)?;
+            find_definition(fusion_index, &require.module, &original_name)
+        }),
+    }
+}
+
+fn relative_path(fusion_index: &FusionIndex, file: &FusionFile) -> PathBuf {
+    file.file_name
+        .strip_prefix(fusion_index.current_package_path())
+        .unwrap_or(&file.file_name)
+        .to_path_buf()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::index::{Catalog, FusionIndexCell, FusionLoader};
+    use fuusak::config::new_default_config;
+
+    fn load(test_dir: &str, modules: &[&str]) -> FusionIndexCell {
+        let config = new_default_config();
+        let fusion_index = FusionIndex::new(
+            &PathBuf::from("./"),
+            vec![PathBuf::from(format!("index_tests/{test_dir}/test_files"))],
+            Catalog::bundled(),
+        )
+        .unwrap();
+        let loader = FusionLoader::new(&config, &fusion_index);
+        for module in modules {
+            loader.load_module(module).unwrap_or_else(|err| panic!("\n{err}"));
+        }
+        fusion_index
+    }
+
+    #[test]
+    fn provided_definitions() {
+        let fusion_index = load("require_forms", &["/user", "/provide_first"]);
+        let fusion_index = fusion_index.read();
+        let render_provides = |module| render(&provides(&fusion_index, module).unwrap(), false);
+        assert_eq!(
+            "pa: /lib (index_tests/require_forms/test_files/lib.fusion:2:12)\n\
+             visible: /user (index_tests/require_forms/test_files/user.fusion:8:12)\n",
+            render_provides("/user")
+        );
+        assert_eq!(
+            "imported_later: /lib (index_tests/require_forms/test_files/lib.fusion:2:12)\n\
+             renamed_later: /provide_first (index_tests/require_forms/test_files/provide_first.fusion:5:12)\n",
+            render_provides("/provide_first")
+        );
+        assert_eq!(
+            Some(Location::in_catalog("/fusion/private/kernel")),
+            provides(&fusion_index, "/fusion")
+                .unwrap()
+                .into_iter()
+                .find(|provided| provided.name == "define")
+                .unwrap()
+                .definition
+        );
+        assert!(provides(&fusion_index, "/nope").is_err());
+    }
+
+    #[test]
+    fn names() {
+        let fusion_index = load("dependents", &["/top"]);
+        let fusion_index = fusion_index.read();
+        assert_eq!(
+            "/mid (index_tests/dependents/test_files/mid.fusion:4:12)\n",
+            render(&definitions(&fusion_index, "mid_value"), false)
+        );
+        assert_eq!(
+            "/mid (index_tests/dependents/test_files/mid.fusion:3:12) imports it from /base\n\
+             /top (index_tests/dependents/test_files/top.fusion:3:12) imports it from /base\n",
+            render(&importers(&fusion_index, "base_value"), false)
+        );
+        assert_eq!(
+            r#"[
+  {
+    "source": "/top",
+    "file": "index_tests/dependents/test_files/top.fusion",
+    "line": 3,
+    "column": 20,
+    "module": "/mid"
+  }
+]
+"#,
+            render(&importers(&fusion_index, "mid_value"), true)
+        );
+    }
+}
@@ -0,0 +1,287 @@
+// Copyright Ion Fusion contributors. All Rights Reserved.
+// SPDX-License-Identifier: Apache-2.0
+use clap::ValueEnum;
+use fuusak::checkstyle::{Rule, Violation};
+use serde_json::{Value, json};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+/// Output formats for checkstyle results
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    /// human readable diffs
+    #[default]
+    Text,
+    /// Checkstyle XML, as produced by Java's Checkstyle
+    Checkstyle,
+    /// SARIF 2.1.0 JSON
+    Sarif,
+    /// JUnit XML, with one test case per file
+    Junit,
+    /// JSON list of violations
+    Json,
+}
+
+/// The violations found in a single checked file
+pub struct FileReport {
+    pub file_name: PathBuf,
+    pub violations: Vec<Violation>,
+}
+
+/// Renders the results of checking `files` in a machine readable format. [`ReportFormat::Text`] isn't supported,
+/// since text output is printed as each file is checked.
+pub fn render(format: ReportFormat, files: &[FileReport]) -> String {
+    match format {
+        ReportFormat::Text => unreachable!("text reports are printed per file"),
+        ReportFormat::Checkstyle => render_checkstyle(files),
+        ReportFormat::Sarif => pretty(&render_sarif(files)),
+        ReportFormat::Junit => render_junit(files),
+        ReportFormat::Json => pretty(&render_json(files)),
+    }
+}
+
+fn render_checkstyle(files: &[FileReport]) -> String {
+    let mut output = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<checkstyle version=\"4.3\">\n");
+    for file in files {
+        writeln!(
+            output,
+            "  <file name=\"{}\">",
+            escape_xml(&path_string(&file.file_name))
+        )
+        .unwrap();
+        for violation in &file.violations {
+            writeln!(
+                output,
+                "    <error line=\"{}\" column=\"{}\" severity=\"error\" message=\"{}\" source=\"fuusak.{}\"/>",
+                violation.start.line,
+                violation.start.column,
+                escape_xml(&violation.message),
+                violation.rule.id()
+            )
+            .unwrap();
+        }
+        output.push_str("  </file>\n");
+    }
+    output.push_str("</checkstyle>\n");
+    output
+}
+
+fn render_sarif(files: &[FileReport]) -> Value {
+    let rules: Vec<Value> = Rule::ALL
+        .iter()
+        .map(|rule| json!({ "id": rule.id(), "shortDescription": { "text": rule.description() } }))
+        .collect();
+    let results: Vec<Value> = violations(files)
+        .map(|violation| {
+            let uri = path_string(&violation.file_name);
+            let region = json!({
+                "startLine": violation.start.line,
+                "startColumn": violation.start.column,
+                "endLine": violation.end.line,
+                "endColumn": violation.end.column,
+            });
+            let mut result = json!({
+                "ruleId": violation.rule.id(),
+                "ruleIndex": Rule::ALL.iter().position(|rule| *rule == violation.rule),
+                "level": "error",
+                "message": { "text": violation.message },
+                "locations": [{
+                    "physicalLocation": { "artifactLocation": { "uri": uri }, "region": region }
+                }],
+            });
+            if let Some(replacement) = &violation.replacement {
+                result["fixes"] = json!([{
+                    "description": { "text": "Apply the expected formatting" },
+                    "artifactChanges": [{
+                        "artifactLocation": { "uri": uri },
+                        "replacements": [{ "deletedRegion": region, "insertedContent": { "text": replacement } }],
+                    }],
+                }]);
+            }
+            result
+        })
+        .collect();
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "fuusak",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules,
+                }
+            },
+            "columnKind": "unicodeCodePoints",
+            "results": results,
+        }],
+    })
+}
+
+fn render_junit(files: &[FileReport]) -> String {
+    let failures = files.iter().filter(|file| !file.violations.is_empty()).count();
+    let mut output = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    writeln!(
+        output,
+        "<testsuites name=\"fuusak\" tests=\"{}\" failures=\"{failures}\">",
+        files.len()
+    )
+    .unwrap();
+    writeln!(
+        output,
+        "  <testsuite name=\"checkstyle\" tests=\"{}\" failures=\"{failures}\">",
+        files.len()
+    )
+    .unwrap();
+    for file in files {
+        let name = escape_xml(&path_string(&file.file_name));
+        if file.violations.is_empty() {
+            writeln!(output, "    <testcase classname=\"checkstyle\" name=\"{name}\"/>").unwrap();
+            continue;
+        }
+        writeln!(output, "    <testcase classname=\"checkstyle\" name=\"{name}\">").unwrap();
+        writeln!(
+            output,
+            "      <failure message=\"{} violation(s)\" type=\"checkstyle\">",
+            file.violations.len()
+        )
+        .unwrap();
+        for violation in &file.violations {
+            writeln!(
+                output,
+                "{name}:{}:{}: {} [{}]",
+                violation.start.line,
+                violation.start.column,
+                escape_xml(&violation.message),
+                violation.rule.id()
+            )
+            .unwrap();
+        }
+        output.push_str("      </failure>\n    </testcase>\n");
+    }
+    output.push_str("  </testsuite>\n</testsuites>\n");
+    output
+}
+
+fn render_json(files: &[FileReport]) -> Value {
+    let violations: Vec<Value> = violations(files)
+        .map(|violation| {
+            json!({
+                "file": path_string(&violation.file_name),
+                "rule": violation.rule.id(),
+                "message": violation.message,
+                "start": { "line": violation.start.line, "column": violation.start.column },
+                "end": { "line": violation.end.line, "column": violation.end.column },
+                "replacement": violation.replacement,
+            })
+        })
+        .collect();
+    json!({ "files_checked": files.len(), "violations": violations })
+}
+
+fn violations(files: &[FileReport]) -> impl Iterator<Item = &Violation> {
+    files.iter().flat_map(|file| &file.violations)
+}
+
+fn pretty(value: &Value) -> String {
+    serde_json::to_string_pretty(value).expect("JSON values always serialize") + "\n"
+}
+
+/// Formats a path with forward slashes, which all of the report formats expect
+fn path_string(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+fn escape_xml(value: &str) -> String {
+    let mut output = String::with_capacity(value.len());
+    for chr in value.chars() {
+        match chr {
+            '&' => output.push_str("&amp;"),
+            '<' => output.push_str("&lt;"),
+            '>' => output.push_str("&gt;"),
+            '"' => output.push_str("&quot;"),
+            '\'' => output.push_str("&apos;"),
+            _ => output.push(chr),
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fuusak::checkstyle::{self, check_formatted};
+    use fuusak::config::new_default_config;
+    use fuusak::error::Error;
+    use fuusak_err_macro::err_generic;
+
+    fn sample_reports() -> Vec<FileReport> {
+        let config = new_default_config();
+        let file_name = PathBuf::from("src/a&b.fusion");
+        vec![
+            FileReport {
+                violations: check_formatted(&config, &file_name, "(foo\nbar)\n", "(foo\n  bar)"),
+                file_name,
+            },
+            FileReport {
+                file_name: PathBuf::from("src/ok.fusion"),
+                violations: Vec::new(),
+            },
+        ]
+    }
+
+    #[test]
+    fn checkstyle_xml() {
+        assert_eq!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<checkstyle version=\"4.3\">\n  \
+             <file name=\"src/a&amp;b.fusion\">\n    \
+             <error line=\"2\" column=\"1\" severity=\"error\" message=\"Incorrect indentation\" source=\"fuusak.format\"/>\n  \
+             </file>\n  <file name=\"src/ok.fusion\">\n  </file>\n</checkstyle>\n",
+            render(ReportFormat::Checkstyle, &sample_reports())
+        );
+    }
+
+    #[test]
+    fn sarif() {
+        let sarif = render_sarif(&sample_reports());
+        let result = &sarif["runs"][0]["results"][0];
+        assert_eq!("format", result["ruleId"]);
+        assert_eq!(2, result["locations"][0]["physicalLocation"]["region"]["startLine"]);
+        assert_eq!(
+            "  ",
+            result["fixes"][0]["artifactChanges"][0]["replacements"][0]["insertedContent"]["text"]
+        );
+        assert_eq!(1, sarif["runs"][0]["results"].as_array().unwrap().len());
+    }
+
+    #[test]
+    fn junit() {
+        let junit = render(ReportFormat::Junit, &sample_reports());
+        assert!(junit.contains("<testsuite name=\"checkstyle\" tests=\"2\" failures=\"1\">"));
+        assert!(junit.contains("src/a&amp;b.fusion:2:1: Incorrect indentation [format]"));
+        assert!(junit.contains("<testcase classname=\"checkstyle\" name=\"src/ok.fusion\"/>"));
+    }
+
+    #[test]
+    fn json() {
+        let json = render_json(&sample_reports());
+        assert_eq!(2, json["files_checked"]);
+        assert_eq!("src/a&b.fusion", json["violations"][0]["file"]);
+        assert_eq!(1, json["violations"][0]["end"]["column"]);
+    }
+
+    #[test]
+    fn unparseable_file() {
+        let file_name = PathBuf::from("src/bad.fusion");
+        let files = [FileReport {
+            violations: vec![checkstyle::file_error(&file_name, &err_generic!("Failed to parse"))],
+            file_name,
+        }];
+        let sarif = render_sarif(&files);
+        assert_eq!("parse", sarif["runs"][0]["results"][0]["ruleId"]);
+        assert_eq!(5, sarif["runs"][0]["results"][0]["ruleIndex"]);
+        assert!(
+            render(ReportFormat::Checkstyle, &files).contains("message=\"Failed to parse\" source=\"fuusak.parse\"")
+        );
+    }
+}